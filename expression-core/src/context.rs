@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+
+use chrono_tz::Tz;
+
+use crate::{currency::ExchangeRates, PropertyValue};
+
+/// Settings shared by all evaluations of an expression, as opposed to the
+/// event, which changes with every evaluation
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvaluationContext {
+    /// Calendar functions interpret timestamps in this timezone, e.g. the
+    /// timezone the customer is billed in
+    pub timezone: Tz,
+
+    /// Keeps string properties as strings, instead of converting the ones
+    /// that look like numbers, e.g. `"007"`. They can still be converted
+    /// explicitly with `to_number` or `to_integer`.
+    pub strict_types: bool,
+
+    /// Values of the `params.` namespace, e.g. the rates of a plan, so the
+    /// same expression can be reused with different constants
+    pub params: HashMap<String, PropertyValue>,
+
+    /// Rates used by `convert`, e.g. the ones of the day of the invoice
+    pub exchange_rates: ExchangeRates,
+}
+
+impl Default for EvaluationContext {
+    fn default() -> Self {
+        Self {
+            timezone: Tz::UTC,
+            strict_types: false,
+            params: HashMap::new(),
+            exchange_rates: ExchangeRates::default(),
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use std::{collections::HashMap, fmt::Display};
+
+use bigdecimal::{BigDecimal, One, RoundingMode, Signed};
+
+use crate::evaluate::{EvaluationResult, ExpressionError};
+
+/// An ISO 4217 currency, e.g. `EUR`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Currency {
+    code: &'static str,
+    exponent: u32,
+}
+
+/// Active ISO 4217 currencies and the number of digits of their minor unit,
+/// sorted by code. Currencies without a minor unit, like gold, are left out.
+const CURRENCIES: &[(&str, u32)] = &[
+    ("AED", 2),
+    ("AFN", 2),
+    ("ALL", 2),
+    ("AMD", 2),
+    ("ANG", 2),
+    ("AOA", 2),
+    ("ARS", 2),
+    ("AUD", 2),
+    ("AWG", 2),
+    ("AZN", 2),
+    ("BAM", 2),
+    ("BBD", 2),
+    ("BDT", 2),
+    ("BGN", 2),
+    ("BHD", 3),
+    ("BIF", 0),
+    ("BMD", 2),
+    ("BND", 2),
+    ("BOB", 2),
+    ("BOV", 2),
+    ("BRL", 2),
+    ("BSD", 2),
+    ("BTN", 2),
+    ("BWP", 2),
+    ("BYN", 2),
+    ("BZD", 2),
+    ("CAD", 2),
+    ("CDF", 2),
+    ("CHE", 2),
+    ("CHF", 2),
+    ("CHW", 2),
+    ("CLF", 4),
+    ("CLP", 0),
+    ("CNY", 2),
+    ("COP", 2),
+    ("COU", 2),
+    ("CRC", 2),
+    ("CUP", 2),
+    ("CVE", 2),
+    ("CZK", 2),
+    ("DJF", 0),
+    ("DKK", 2),
+    ("DOP", 2),
+    ("DZD", 2),
+    ("EGP", 2),
+    ("ERN", 2),
+    ("ETB", 2),
+    ("EUR", 2),
+    ("FJD", 2),
+    ("FKP", 2),
+    ("GBP", 2),
+    ("GEL", 2),
+    ("GHS", 2),
+    ("GIP", 2),
+    ("GMD", 2),
+    ("GNF", 0),
+    ("GTQ", 2),
+    ("GYD", 2),
+    ("HKD", 2),
+    ("HNL", 2),
+    ("HTG", 2),
+    ("HUF", 2),
+    ("IDR", 2),
+    ("ILS", 2),
+    ("INR", 2),
+    ("IQD", 3),
+    ("IRR", 2),
+    ("ISK", 0),
+    ("JMD", 2),
+    ("JOD", 3),
+    ("JPY", 0),
+    ("KES", 2),
+    ("KGS", 2),
+    ("KHR", 2),
+    ("KMF", 0),
+    ("KPW", 2),
+    ("KRW", 0),
+    ("KWD", 3),
+    ("KYD", 2),
+    ("KZT", 2),
+    ("LAK", 2),
+    ("LBP", 2),
+    ("LKR", 2),
+    ("LRD", 2),
+    ("LSL", 2),
+    ("LYD", 3),
+    ("MAD", 2),
+    ("MDL", 2),
+    ("MGA", 2),
+    ("MKD", 2),
+    ("MMK", 2),
+    ("MNT", 2),
+    ("MOP", 2),
+    ("MRU", 2),
+    ("MUR", 2),
+    ("MVR", 2),
+    ("MWK", 2),
+    ("MXN", 2),
+    ("MXV", 2),
+    ("MYR", 2),
+    ("MZN", 2),
+    ("NAD", 2),
+    ("NGN", 2),
+    ("NIO", 2),
+    ("NOK", 2),
+    ("NPR", 2),
+    ("NZD", 2),
+    ("OMR", 3),
+    ("PAB", 2),
+    ("PEN", 2),
+    ("PGK", 2),
+    ("PHP", 2),
+    ("PKR", 2),
+    ("PLN", 2),
+    ("PYG", 0),
+    ("QAR", 2),
+    ("RON", 2),
+    ("RSD", 2),
+    ("RUB", 2),
+    ("RWF", 0),
+    ("SAR", 2),
+    ("SBD", 2),
+    ("SCR", 2),
+    ("SDG", 2),
+    ("SEK", 2),
+    ("SGD", 2),
+    ("SHP", 2),
+    ("SLE", 2),
+    ("SOS", 2),
+    ("SRD", 2),
+    ("SSP", 2),
+    ("STN", 2),
+    ("SVC", 2),
+    ("SYP", 2),
+    ("SZL", 2),
+    ("THB", 2),
+    ("TJS", 2),
+    ("TMT", 2),
+    ("TND", 3),
+    ("TOP", 2),
+    ("TRY", 2),
+    ("TTD", 2),
+    ("TWD", 2),
+    ("TZS", 2),
+    ("UAH", 2),
+    ("UGX", 0),
+    ("USD", 2),
+    ("USN", 2),
+    ("UYI", 0),
+    ("UYU", 2),
+    ("UYW", 4),
+    ("UZS", 2),
+    ("VED", 2),
+    ("VES", 2),
+    ("VND", 0),
+    ("VUV", 0),
+    ("WST", 2),
+    ("XAF", 0),
+    ("XCD", 2),
+    ("XCG", 2),
+    ("XOF", 0),
+    ("XPF", 0),
+    ("YER", 2),
+    ("ZAR", 2),
+    ("ZMW", 2),
+    ("ZWG", 2),
+];
+
+impl Currency {
+    /// The currency with the code, which is case-insensitive
+    pub fn from_code(code: &str) -> Option<Currency> {
+        let code = code.to_ascii_uppercase();
+        CURRENCIES
+            .binary_search_by_key(&code.as_str(), |(code, _)| code)
+            .ok()
+            .map(|i| Currency {
+                code: CURRENCIES[i].0,
+                exponent: CURRENCIES[i].1,
+            })
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    /// The number of digits after the decimal point of amounts, e.g. 2 for
+    /// EUR, 0 for JPY and 3 for KWD
+    pub fn exponent(&self) -> u32 {
+        self.exponent
+    }
+
+    /// The amount in the minor unit, rounded half up, e.g. cents for EUR
+    pub fn to_minor_units(&self, amount: &BigDecimal) -> BigDecimal {
+        (amount * self.minor_units_per_unit()).with_scale_round(0, RoundingMode::HalfUp)
+    }
+
+    pub fn from_minor_units(&self, minor_units: &BigDecimal) -> BigDecimal {
+        minor_units / self.minor_units_per_unit()
+    }
+
+    fn minor_units_per_unit(&self) -> BigDecimal {
+        BigDecimal::from(10u64.pow(self.exponent))
+    }
+}
+
+/// Exchange rates used by `convert`, a rate is the amount in the destination
+/// currency of one unit of the source currency
+///
+/// Rates can be used in both directions. Pairs without a rate are converted
+/// through the base currency, if there is one, e.g. USD to GBP as USD to EUR
+/// and EUR to GBP.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExchangeRates {
+    rates: HashMap<(Currency, Currency), BigDecimal>,
+    base: Option<Currency>,
+}
+
+impl ExchangeRates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_base(base: Currency) -> Self {
+        Self {
+            rates: HashMap::new(),
+            base: Some(base),
+        }
+    }
+
+    /// Adds or replaces the rate from `from` to `to`, which must be positive
+    pub fn insert(
+        &mut self,
+        from: Currency,
+        to: Currency,
+        rate: BigDecimal,
+    ) -> EvaluationResult<()> {
+        if !rate.is_positive() {
+            return Err(ExpressionError::InvalidExchangeRate(rate));
+        }
+        self.rates.insert((from, to), rate);
+        Ok(())
+    }
+
+    /// The rate from `from` to `to`, directly, inverted, or through the base currency
+    pub fn rate(&self, from: Currency, to: Currency) -> EvaluationResult<BigDecimal> {
+        if let Some(rate) = self.direct_rate(from, to) {
+            return Ok(rate);
+        }
+        self.base
+            .and_then(|base| Some(self.direct_rate(from, base)? * self.direct_rate(base, to)?))
+            .ok_or(ExpressionError::MissingExchangeRate { from, to })
+    }
+
+    fn direct_rate(&self, from: Currency, to: Currency) -> Option<BigDecimal> {
+        if from == to {
+            return Some(BigDecimal::one());
+        }
+        match (self.rates.get(&(from, to)), self.rates.get(&(to, from))) {
+            (Some(rate), _) => Some(rate.clone()),
+            (None, Some(inverse)) => Some(inverse.inverse()),
+            (None, None) => None,
+        }
+    }
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_currencies_are_sorted() {
+        assert!(CURRENCIES.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn test_from_code() {
+        for (code, exponent) in [("EUR", 2), ("usd", 2), ("JPY", 0), ("KWD", 3), ("CLF", 4)] {
+            let currency = Currency::from_code(code).unwrap();
+            assert_eq!(currency.code(), code.to_uppercase());
+            assert_eq!(currency.exponent(), exponent);
+        }
+        for code in ["", "EU", "EURO", "XAU", "ABC"] {
+            assert_eq!(Currency::from_code(code), None, "{code}");
+        }
+    }
+
+    fn currency(code: &str) -> Currency {
+        Currency::from_code(code).unwrap()
+    }
+
+    #[test]
+    fn test_exchange_rates() {
+        let decimal = |s: &str| s.parse::<BigDecimal>().unwrap();
+        let mut rates = ExchangeRates::with_base(currency("EUR"));
+        rates
+            .insert(currency("EUR"), currency("USD"), decimal("1.25"))
+            .unwrap();
+        rates
+            .insert(currency("GBP"), currency("EUR"), decimal("1.2"))
+            .unwrap();
+        rates
+            .insert(currency("USD"), currency("JPY"), decimal("150"))
+            .unwrap();
+
+        for (from, to, expected) in [
+            ("EUR", "EUR", "1"),
+            ("EUR", "USD", "1.25"),
+            ("USD", "EUR", "0.8"),
+            ("GBP", "USD", "1.5"),
+            ("USD", "JPY", "150"),
+        ] {
+            assert_eq!(
+                rates.rate(currency(from), currency(to)).unwrap(),
+                decimal(expected),
+                "{from} {to}"
+            );
+        }
+
+        // Cross rates only go through the base currency
+        assert!(matches!(
+            rates.rate(currency("GBP"), currency("JPY")),
+            Err(ExpressionError::MissingExchangeRate { from, to })
+                if from.code() == "GBP" && to.code() == "JPY"
+        ));
+        assert!(matches!(
+            ExchangeRates::new().rate(currency("GBP"), currency("USD")),
+            Err(ExpressionError::MissingExchangeRate { .. })
+        ));
+        assert!(matches!(
+            rates.insert(currency("EUR"), currency("CHF"), decimal("0")),
+            Err(ExpressionError::InvalidExchangeRate(_))
+        ));
+    }
+
+    #[test]
+    fn test_minor_units() {
+        let decimal = |s: &str| s.parse::<BigDecimal>().unwrap();
+        let eur = Currency::from_code("EUR").unwrap();
+        let jpy = Currency::from_code("JPY").unwrap();
+        let kwd = Currency::from_code("KWD").unwrap();
+
+        assert_eq!(eur.to_minor_units(&decimal("12.345")), decimal("1235"));
+        assert_eq!(jpy.to_minor_units(&decimal("12.5")), decimal("13"));
+        assert_eq!(kwd.to_minor_units(&decimal("1.2345")), decimal("1235"));
+        assert_eq!(eur.from_minor_units(&decimal("1234")), decimal("12.34"));
+        assert_eq!(jpy.from_minor_units(&decimal("1234")), decimal("1234"));
+        assert_eq!(kwd.from_minor_units(&decimal("1234")), decimal("1.234"));
+    }
+}
@@ -0,0 +1,438 @@
+use std::{fmt::Write, str::FromStr};
+
+use bigdecimal::{BigDecimal, RoundingMode, ToPrimitive};
+use chrono::{
+    format::StrftimeItems, DateTime, Datelike, Days, LocalResult, NaiveDate, NaiveDateTime,
+    NaiveTime, Offset, TimeDelta, TimeZone, Timelike, Utc,
+};
+
+use crate::{
+    evaluate::{EvaluationResult, ExpressionError},
+    parser::{ParseError, ParseResult},
+};
+
+/// Timestamps with an absolute value of at least this are interpreted as
+/// milliseconds since the epoch, smaller ones as seconds. As seconds, this is
+/// in the year 5138, as milliseconds it is in 1973.
+const MILLISECONDS_THRESHOLD: i64 = 100_000_000_000;
+
+/// Whether a timestamp is in milliseconds rather than seconds
+pub fn is_milliseconds(timestamp: &BigDecimal) -> bool {
+    timestamp.abs() >= MILLISECONDS_THRESHOLD
+}
+
+/// Converts a timestamp in seconds or milliseconds since the epoch
+pub fn from_timestamp(timestamp: &BigDecimal) -> EvaluationResult<DateTime<Utc>> {
+    let seconds = if is_milliseconds(timestamp) {
+        timestamp / BigDecimal::from(1000)
+    } else {
+        timestamp.clone()
+    };
+
+    from_seconds(&seconds).map_err(|_| ExpressionError::InvalidTimestamp(timestamp.clone()))
+}
+
+/// Moves a date and time by a number of seconds, which can be negative
+pub fn add_seconds(
+    datetime: &DateTime<Utc>,
+    seconds: &BigDecimal,
+) -> EvaluationResult<DateTime<Utc>> {
+    from_seconds(&(to_timestamp(datetime) + seconds))
+}
+
+fn from_seconds(seconds: &BigDecimal) -> EvaluationResult<DateTime<Utc>> {
+    let whole_seconds = seconds.with_scale_round(0, RoundingMode::Floor);
+    let nanoseconds = ((seconds - &whole_seconds) * BigDecimal::from(1_000_000_000))
+        .with_scale_round(0, RoundingMode::Floor);
+
+    whole_seconds
+        .to_i64()
+        .zip(nanoseconds.to_u32())
+        .and_then(|(s, ns)| DateTime::from_timestamp(s, ns))
+        .ok_or_else(|| ExpressionError::InvalidTimestamp(seconds.clone()))
+}
+
+/// Converts to a timestamp in seconds since the epoch
+pub fn to_timestamp<Tz: TimeZone>(datetime: &DateTime<Tz>) -> BigDecimal {
+    let nanoseconds = BigDecimal::new(datetime.timestamp_subsec_nanos().into(), 9);
+    (BigDecimal::from(datetime.timestamp()) + nanoseconds).normalized()
+}
+
+/// Formats `parse_datetime` tries when no format is given, after RFC 3339
+const LOCAL_DATETIME_FORMATS: [&str; 2] = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"];
+const LOCAL_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Parses a date and time, by default as RFC 3339 or ISO 8601, otherwise
+/// according to a strftime `format`. When the input has no offset it is the
+/// local time in `timezone`, when it has no time it is midnight.
+pub fn parse_datetime<Tz: TimeZone>(
+    s: &str,
+    format: Option<&str>,
+    timezone: &Tz,
+) -> EvaluationResult<DateTime<Utc>> {
+    let invalid = || ExpressionError::InvalidDateTime(s.to_owned());
+
+    let Some(format) = format else {
+        if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+            return Ok(dt.to_utc());
+        }
+        let local = LOCAL_DATETIME_FORMATS
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+            .or_else(|| {
+                NaiveDate::parse_from_str(s, LOCAL_DATE_FORMAT)
+                    .ok()
+                    .and_then(|date| date.and_hms_opt(0, 0, 0))
+            })
+            .ok_or_else(invalid)?;
+        return Ok(from_local_datetime(timezone, &local).to_utc());
+    };
+
+    validate_format(format)?;
+    if let Ok(dt) = DateTime::parse_from_str(s, format) {
+        return Ok(dt.to_utc());
+    }
+    let local = NaiveDateTime::parse_from_str(s, format)
+        .or_else(|_| NaiveDate::parse_from_str(s, format).map(|date| date.and_time(NaiveTime::MIN)))
+        .map_err(|_| invalid())?;
+    Ok(from_local_datetime(timezone, &local).to_utc())
+}
+
+/// Formats a date and time according to a strftime `format`
+pub fn format_datetime<Tz>(datetime: &DateTime<Tz>, format: &str) -> EvaluationResult<String>
+where
+    Tz: TimeZone,
+    Tz::Offset: std::fmt::Display,
+{
+    validate_format(format)?;
+    let mut formatted = String::new();
+    // Formatting still fails for valid specifiers the value lacks, e.g. a timezone name
+    write!(formatted, "{}", datetime.format(format))
+        .map_err(|_| ExpressionError::InvalidDateTimeFormat(format.to_owned()))?;
+    Ok(formatted)
+}
+
+fn validate_format(format: &str) -> EvaluationResult<()> {
+    StrftimeItems::new(format)
+        .parse()
+        .map(|_| ())
+        .map_err(|_| ExpressionError::InvalidDateTimeFormat(format.to_owned()))
+}
+
+/// The precision `date_trunc` truncates to
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DateUnit {
+    Minute,
+    Hour,
+    Day,
+    /// Weeks start on Monday
+    Week,
+    Month,
+    Year,
+}
+
+impl FromStr for DateUnit {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> ParseResult<Self> {
+        match s.to_lowercase().as_str() {
+            "minute" => Ok(DateUnit::Minute),
+            "hour" => Ok(DateUnit::Hour),
+            "day" => Ok(DateUnit::Day),
+            "week" => Ok(DateUnit::Week),
+            "month" => Ok(DateUnit::Month),
+            "year" => Ok(DateUnit::Year),
+            _ => Err(ParseError::InvalidDateUnit(s.to_owned())),
+        }
+    }
+}
+
+impl DateUnit {
+    /// Truncates the local date and time, the result is the moment that
+    /// local time occurred at in the timezone of `datetime`
+    pub fn truncate<Tz: TimeZone>(&self, datetime: &DateTime<Tz>) -> DateTime<Tz> {
+        let date = datetime.date_naive();
+        let truncated = match self {
+            DateUnit::Minute => date.and_hms_opt(datetime.hour(), datetime.minute(), 0),
+            DateUnit::Hour => date.and_hms_opt(datetime.hour(), 0, 0),
+            DateUnit::Day => date.and_hms_opt(0, 0, 0),
+            DateUnit::Week => date
+                .checked_sub_days(Days::new(date.weekday().num_days_from_monday().into()))
+                .and_then(|monday| monday.and_hms_opt(0, 0, 0)),
+            DateUnit::Month => NaiveDate::from_ymd_opt(date.year(), date.month(), 1)
+                .and_then(|first| first.and_hms_opt(0, 0, 0)),
+            DateUnit::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1)
+                .and_then(|first| first.and_hms_opt(0, 0, 0)),
+        };
+
+        let truncated = truncated.expect("truncated date is within range");
+        from_local_datetime(&datetime.timezone(), &truncated)
+    }
+}
+
+/// The moment a local date and time occurs in a timezone. Around DST
+/// transitions a local time can occur twice, then the first occurrence is
+/// used, or not at all, then the moment clocks skipped over it is used.
+fn from_local_datetime<Tz: TimeZone>(timezone: &Tz, local: &NaiveDateTime) -> DateTime<Tz> {
+    match timezone.from_local_datetime(local) {
+        LocalResult::Single(datetime) => datetime,
+        LocalResult::Ambiguous(earliest, _) => earliest,
+        LocalResult::None => {
+            // Offsets are less than a day, so a day earlier is still before the transition
+            let offset_before = timezone
+                .offset_from_utc_datetime(&(*local - TimeDelta::days(1)))
+                .fix();
+            let utc = *local - TimeDelta::seconds(offset_before.local_minus_utc().into());
+            timezone.from_utc_datetime(&utc)
+        }
+    }
+}
+
+/// Parses a time of day formatted as `HH:MM` or `HH:MM:SS`
+pub fn parse_time_of_day(s: &str) -> ParseResult<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M:%S"))
+        .map_err(|_| ParseError::InvalidTimeOfDay(s.to_owned()))
+}
+
+/// Whether `time` is in `[start, end)`. When `end` is before `start` the range
+/// wraps around midnight, e.g. 22:00 to 06:00.
+pub fn time_between(time: NaiveTime, start: NaiveTime, end: NaiveTime) -> bool {
+    if start <= end {
+        start <= time && time < end
+    } else {
+        time >= start || time < end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Tz;
+
+    use super::*;
+
+    fn datetime(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_from_timestamp_seconds() {
+        assert_eq!(
+            from_timestamp(&1717243200.into()).unwrap(),
+            datetime("2024-06-01T12:00:00Z")
+        );
+        assert_eq!(
+            from_timestamp(&"1717243200.25".parse().unwrap()).unwrap(),
+            datetime("2024-06-01T12:00:00.250Z")
+        );
+    }
+
+    #[test]
+    fn test_from_timestamp_milliseconds() {
+        assert_eq!(
+            from_timestamp(&1717243200123u64.into()).unwrap(),
+            datetime("2024-06-01T12:00:00.123Z")
+        );
+    }
+
+    #[test]
+    fn test_from_timestamp_negative() {
+        assert_eq!(
+            from_timestamp(&"-0.5".parse().unwrap()).unwrap(),
+            datetime("1969-12-31T23:59:59.500Z")
+        );
+    }
+
+    #[test]
+    fn test_to_timestamp() {
+        assert_eq!(
+            to_timestamp(&datetime("2024-06-01T12:00:00.250Z")),
+            "1717243200.25".parse::<BigDecimal>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        let value = datetime("2024-06-05T13:45:30Z");
+        let cases = [
+            (DateUnit::Minute, "2024-06-05T13:45:00Z"),
+            (DateUnit::Hour, "2024-06-05T13:00:00Z"),
+            (DateUnit::Day, "2024-06-05T00:00:00Z"),
+            (DateUnit::Week, "2024-06-03T00:00:00Z"),
+            (DateUnit::Month, "2024-06-01T00:00:00Z"),
+            (DateUnit::Year, "2024-01-01T00:00:00Z"),
+        ];
+        for (unit, expected) in cases {
+            assert_eq!(unit.truncate(&value), datetime(expected));
+        }
+    }
+
+    #[test]
+    fn test_date_unit_from_str() {
+        assert_eq!("HOUR".parse::<DateUnit>().unwrap(), DateUnit::Hour);
+        assert!(matches!(
+            "fortnight".parse::<DateUnit>(),
+            Err(ParseError::InvalidDateUnit(_))
+        ));
+    }
+
+    #[test]
+    fn test_time_between() {
+        let time = |s| parse_time_of_day(s).unwrap();
+        assert!(time_between(time("09:00"), time("09:00"), time("17:00")));
+        assert!(!time_between(time("17:00"), time("09:00"), time("17:00")));
+        assert!(time_between(time("23:30"), time("22:00"), time("06:00")));
+        assert!(time_between(time("05:59:59"), time("22:00"), time("06:00")));
+        assert!(!time_between(time("12:00"), time("22:00"), time("06:00")));
+    }
+
+    #[test]
+    fn test_parse_time_of_day_invalid() {
+        assert!(matches!(
+            parse_time_of_day("25:00"),
+            Err(ParseError::InvalidTimeOfDay(_))
+        ));
+    }
+
+    #[test]
+    fn test_truncate_in_timezone() {
+        let value = datetime("2024-06-01T02:30:00Z").with_timezone(&Tz::America__New_York);
+        assert_eq!(
+            DateUnit::Day.truncate(&value),
+            datetime("2024-05-31T04:00:00Z")
+        );
+        assert_eq!(
+            DateUnit::Month.truncate(&value),
+            datetime("2024-05-01T04:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_truncate_across_dst_transition() {
+        // Clocks went forward on 2024-03-10, so that day had 23 hours
+        let value = datetime("2024-03-10T20:00:00Z").with_timezone(&Tz::America__New_York);
+        assert_eq!(
+            DateUnit::Day.truncate(&value),
+            datetime("2024-03-10T05:00:00Z")
+        );
+        assert_eq!(
+            DateUnit::Hour.truncate(&value),
+            datetime("2024-03-10T20:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_truncate_to_skipped_midnight() {
+        // Clocks went from 00:00 to 01:00 on 2018-11-04 in Sao Paulo
+        let value = datetime("2018-11-04T12:00:00Z").with_timezone(&Tz::America__Sao_Paulo);
+        assert_eq!(
+            DateUnit::Day.truncate(&value),
+            datetime("2018-11-04T03:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_truncate_to_repeated_midnight() {
+        // Clocks went from 00:00 back to 23:00 on 2019-02-17 in Sao Paulo
+        let value = datetime("2019-02-16T12:00:00Z").with_timezone(&Tz::America__Sao_Paulo);
+        assert_eq!(
+            DateUnit::Day.truncate(&value),
+            datetime("2019-02-16T02:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_parse_datetime_default_formats() {
+        let cases = [
+            ("2024-06-01T12:00:00Z", "2024-06-01T12:00:00Z"),
+            ("2024-06-01T14:00:00.5+02:00", "2024-06-01T12:00:00.500Z"),
+            ("2024-06-01T12:00:00", "2024-06-01T12:00:00Z"),
+            ("2024-06-01 12:00:00.250", "2024-06-01T12:00:00.250Z"),
+            ("2024-06-01", "2024-06-01T00:00:00Z"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse_datetime(input, None, &Utc).unwrap(),
+                datetime(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_datetime_local_time_in_timezone() {
+        assert_eq!(
+            parse_datetime("2024-06-01 12:00:00", None, &Tz::Europe__Paris).unwrap(),
+            datetime("2024-06-01T10:00:00Z")
+        );
+        assert_eq!(
+            parse_datetime("2024-06-01T12:00:00Z", None, &Tz::Europe__Paris).unwrap(),
+            datetime("2024-06-01T12:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_parse_datetime_with_format() {
+        assert_eq!(
+            parse_datetime("01/06/2024 12:30", Some("%d/%m/%Y %H:%M"), &Utc).unwrap(),
+            datetime("2024-06-01T12:30:00Z")
+        );
+        assert_eq!(
+            parse_datetime("01/06/2024", Some("%d/%m/%Y"), &Utc).unwrap(),
+            datetime("2024-06-01T00:00:00Z")
+        );
+        assert_eq!(
+            parse_datetime("2024-06-01 12:30 +0200", Some("%Y-%m-%d %H:%M %z"), &Utc).unwrap(),
+            datetime("2024-06-01T10:30:00Z")
+        );
+    }
+
+    #[test]
+    fn test_parse_datetime_invalid() {
+        assert!(matches!(
+            parse_datetime("yesterday", None, &Utc),
+            Err(ExpressionError::InvalidDateTime(_))
+        ));
+        assert!(matches!(
+            parse_datetime("2024-06-01", Some("%d/%m/%Y"), &Utc),
+            Err(ExpressionError::InvalidDateTime(_))
+        ));
+        assert!(matches!(
+            parse_datetime("2024-06-01", Some("%Q"), &Utc),
+            Err(ExpressionError::InvalidDateTimeFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_format_datetime() {
+        let value = datetime("2024-06-01T12:30:00Z");
+        assert_eq!(
+            format_datetime(&value, "%Y-%m-%d %H:%M").unwrap(),
+            "2024-06-01 12:30"
+        );
+        assert_eq!(
+            format_datetime(&value.with_timezone(&Tz::Europe__Paris), "%H:%M %Z").unwrap(),
+            "14:30 CEST"
+        );
+        assert!(matches!(
+            format_datetime(&value, "%Y-%"),
+            Err(ExpressionError::InvalidDateTimeFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_add_seconds() {
+        let value = datetime("2024-06-01T12:00:00Z");
+        assert_eq!(
+            add_seconds(&value, &"5400.5".parse().unwrap()).unwrap(),
+            datetime("2024-06-01T13:30:00.500Z")
+        );
+        assert_eq!(
+            add_seconds(&value, &(-86400).into()).unwrap(),
+            datetime("2024-05-31T12:00:00Z")
+        );
+        assert!(matches!(
+            add_seconds(&value, &"1e20".parse().unwrap()),
+            Err(ExpressionError::InvalidTimestamp(_))
+        ));
+    }
+}
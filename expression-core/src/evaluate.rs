@@ -1,30 +1,188 @@
-use std::fmt::Display;
+use std::{cmp::Ordering, collections::HashMap, fmt::Display};
 
-use bigdecimal::{BigDecimal, RoundingMode, ToPrimitive};
+use bigdecimal::{num_bigint::Sign, BigDecimal, RoundingMode, ToPrimitive, Zero};
+use chrono::{DateTime, Datelike, SecondsFormat, Timelike, Utc};
+use chrono_tz::Tz;
 use thiserror::Error;
 
 use crate::{
-    parser::{EventAttribute, Expression, Function, Operation},
-    Event, PropertyValue,
+    currency::Currency,
+    datetime, math,
+    parser::{EventAttribute, Expression, Function, Lambda, Operation, PathSegment},
+    pricing::{self, Tier},
+    EvaluationContext, Event, PropertyValue,
 };
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ExpressionValue {
     Number(BigDecimal),
     String(String),
+    Boolean(bool),
+    DateTime(DateTime<Utc>),
+    /// A length of time in seconds
+    Duration(BigDecimal),
+    /// An amount in a currency, with the precision it was computed with.
+    /// Amounts are only rounded to the minor unit of their currency by
+    /// `round`, `ceil`, `floor`, `trunc`, `to_cents` and `convert`, so prices
+    /// below the minor unit keep their value, e.g. `money(0.0015, 'EUR') * 1000`.
+    Money(BigDecimal, Currency),
+    Array(Vec<ExpressionValue>),
+    Object(HashMap<String, ExpressionValue>),
+    Null,
+}
+
+/// What an expression is evaluated against: the event, the context and the
+/// variables bound by the enclosing lambdas and `let`s
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    event: &'a Event,
+    context: &'a EvaluationContext,
+    variable: Option<&'a Variable<'a>>,
+}
+
+/// A bound variable, linked to the variables of the enclosing scopes
+struct Variable<'a> {
+    name: &'a str,
+    value: &'a ExpressionValue,
+    parent: Option<&'a Variable<'a>>,
+}
+
+impl<'a> Scope<'a> {
+    /// Evaluates `expr` with `name` bound to `value`
+    fn evaluate_with_variable(
+        &self,
+        expr: &Expression,
+        name: &str,
+        value: &ExpressionValue,
+    ) -> EvaluationResult<ExpressionValue> {
+        let variable = Variable {
+            name,
+            value,
+            parent: self.variable,
+        };
+        expr.evaluate_in(&Scope {
+            variable: Some(&variable),
+            ..*self
+        })
+    }
+
+    fn variable(&self, name: &str) -> Option<&'a ExpressionValue> {
+        let mut variable = self.variable;
+        while let Some(v) = variable {
+            if v.name == name {
+                return Some(v.value);
+            }
+            variable = v.parent;
+        }
+        None
+    }
 }
 
 impl Expression {
+    /// Evaluates the expression in the default context, with timestamps in UTC
     pub fn evaluate(&self, event: &Event) -> EvaluationResult<ExpressionValue> {
+        self.evaluate_with_context(event, &EvaluationContext::default())
+    }
+
+    /// Evaluates the expression in the default context, with the values of the
+    /// `params.` namespace
+    pub fn evaluate_with_params(
+        &self,
+        event: &Event,
+        params: HashMap<String, PropertyValue>,
+    ) -> EvaluationResult<ExpressionValue> {
+        let context = EvaluationContext {
+            params,
+            ..Default::default()
+        };
+        self.evaluate_with_context(event, &context)
+    }
+
+    pub fn evaluate_with_context(
+        &self,
+        event: &Event,
+        context: &EvaluationContext,
+    ) -> EvaluationResult<ExpressionValue> {
+        self.evaluate_in(&Scope {
+            event,
+            context,
+            variable: None,
+        })
+    }
+
+    fn evaluate_in(&self, scope: &Scope) -> EvaluationResult<ExpressionValue> {
         let evaluated_expr = match self {
-            Expression::EventAttribute(attr) => attr.evaluate(event)?,
-            Expression::Function(f) => f.evaluate(event)?,
+            Expression::EventAttribute(attr) => attr.evaluate(scope)?,
+            Expression::Function(f) => f.evaluate(scope)?,
             Expression::String(s) => s.clone().into(),
             Expression::Decimal(d) => d.clone().into(),
-            Expression::UnaryMinus(inner) => {
-                ExpressionValue::Number(-(inner.evaluate(event)?.to_decimal()?))
+            Expression::Duration(seconds) => ExpressionValue::Duration(seconds.clone()),
+            Expression::Boolean(b) => (*b).into(),
+            Expression::Null => ExpressionValue::Null,
+            Expression::Array(exprs) => ExpressionValue::Array(
+                exprs
+                    .iter()
+                    .map(|expr| expr.evaluate_in(scope))
+                    .collect::<EvaluationResult<_>>()?,
+            ),
+            Expression::Map(entries) => ExpressionValue::Object(
+                entries
+                    .iter()
+                    .map(|(key, expr)| Ok((key.clone(), expr.evaluate_in(scope)?)))
+                    .collect::<EvaluationResult<_>>()?,
+            ),
+            Expression::Index(expr, key) => {
+                index(expr.evaluate_in(scope)?, &key.evaluate_in(scope)?)?
+            }
+            Expression::Let { name, value, body } => {
+                // The value is evaluated once, however many times the variable is used
+                let value = value.evaluate_in(scope)?;
+                scope.evaluate_with_variable(body, name, &value)?
+            }
+            Expression::Parameter(name, path) => {
+                // Unlike properties, parameters are expected to always be given
+                let value = scope
+                    .context
+                    .params
+                    .get(name)
+                    .ok_or_else(|| ExpressionError::MissingParameter(name.clone()))?;
+                nested_property(Some(value), path).map_or(ExpressionValue::Null, |value| {
+                    ExpressionValue::from_property(value, scope.context.strict_types)
+                })
+            }
+            Expression::Variable(name, path) => scope
+                .variable(name)
+                // Variables are resolved while parsing, unless the expression was built by hand
+                .ok_or_else(|| ExpressionError::UnknownVariable(name.clone()))?
+                .get(path)
+                .cloned()
+                .unwrap_or(ExpressionValue::Null),
+            Expression::UnaryMinus(inner) => match inner.evaluate_in(scope)? {
+                ExpressionValue::Null => ExpressionValue::Null,
+                ExpressionValue::Duration(seconds) => ExpressionValue::Duration(-seconds),
+                ExpressionValue::Money(amount, currency) => {
+                    ExpressionValue::Money(-amount, currency)
+                }
+                value => ExpressionValue::Number(-value.to_decimal()?),
+            },
+            Expression::Not(inner) => match inner.evaluate_in(scope)?.to_nullable_bool()? {
+                Some(b) => ExpressionValue::Boolean(!b),
+                None => ExpressionValue::Null,
+            },
+            Expression::BinOp { lhs, op, rhs } => op.evaluate(lhs.as_ref(), rhs.as_ref(), scope)?,
+            Expression::Conditional {
+                branches,
+                otherwise,
+            } => {
+                // Only the selected branch is evaluated, errors in the others are never raised.
+                // Like in SQL, a null condition is not satisfied.
+                for (condition, value) in branches {
+                    if condition.evaluate_in(scope)?.to_nullable_bool()? == Some(true) {
+                        return value.evaluate_in(scope);
+                    }
+                }
+                otherwise.evaluate_in(scope)?
             }
-            Expression::BinOp { lhs, op, rhs } => op.evaluate(lhs.as_ref(), rhs.as_ref(), event)?,
         };
 
         Ok(evaluated_expr)
@@ -35,7 +193,254 @@ impl ExpressionValue {
     pub fn to_decimal(&self) -> EvaluationResult<BigDecimal> {
         match self {
             ExpressionValue::Number(d) => Ok(d.clone()),
-            ExpressionValue::String(_) => Err(ExpressionError::ExpectedDecimal),
+            ExpressionValue::String(_)
+            | ExpressionValue::Boolean(_)
+            | ExpressionValue::DateTime(_)
+            | ExpressionValue::Duration(_)
+            | ExpressionValue::Money(..)
+            | ExpressionValue::Array(_)
+            | ExpressionValue::Object(_)
+            | ExpressionValue::Null => Err(ExpressionError::ExpectedDecimal),
+        }
+    }
+
+    pub fn to_bool(&self) -> EvaluationResult<bool> {
+        match self {
+            ExpressionValue::Boolean(b) => Ok(*b),
+            ExpressionValue::Number(_)
+            | ExpressionValue::String(_)
+            | ExpressionValue::DateTime(_)
+            | ExpressionValue::Duration(_)
+            | ExpressionValue::Money(..)
+            | ExpressionValue::Array(_)
+            | ExpressionValue::Object(_)
+            | ExpressionValue::Null => Err(ExpressionError::ExpectedBoolean),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, ExpressionValue::Null)
+    }
+
+    /// Like `to_bool`, but returns `None` for null values, to implement
+    /// three-valued logic
+    fn to_nullable_bool(&self) -> EvaluationResult<Option<bool>> {
+        match self {
+            ExpressionValue::Null => Ok(None),
+            value => value.to_bool().map(Some),
+        }
+    }
+
+    fn to_nullable_decimal(&self) -> EvaluationResult<Option<BigDecimal>> {
+        match self {
+            ExpressionValue::Null => Ok(None),
+            value => value.to_decimal().map(Some),
+        }
+    }
+
+    fn to_nullable_integer(&self) -> EvaluationResult<Option<i64>> {
+        self.to_nullable_decimal()?
+            .map(|d| d.to_i64().ok_or(ExpressionError::ExpectedDecimal))
+            .transpose()
+    }
+
+    /// Date and times, or numbers as timestamps in seconds or milliseconds
+    /// since the epoch
+    fn to_nullable_datetime(&self) -> EvaluationResult<Option<DateTime<Utc>>> {
+        match self {
+            ExpressionValue::DateTime(dt) => Ok(Some(*dt)),
+            ExpressionValue::Number(d) => datetime::from_timestamp(d).map(Some),
+            ExpressionValue::Null => Ok(None),
+            ExpressionValue::String(_)
+            | ExpressionValue::Boolean(_)
+            | ExpressionValue::Duration(_)
+            | ExpressionValue::Money(..)
+            | ExpressionValue::Array(_)
+            | ExpressionValue::Object(_) => Err(ExpressionError::ExpectedTimestamp),
+        }
+    }
+
+    /// Like `to_nullable_datetime`, for operands which can't be null. Numbers
+    /// are timestamps when combined with date and times, as `event.timestamp`
+    /// is a number.
+    fn to_datetime(&self) -> EvaluationResult<DateTime<Utc>> {
+        Ok(self.to_nullable_datetime()?.expect("non-null value"))
+    }
+
+    /// Explicit conversion to a number, unlike `to_decimal` strings are parsed
+    /// and booleans are 1 or 0
+    fn to_number(&self) -> EvaluationResult<BigDecimal> {
+        match self {
+            ExpressionValue::Number(d) => Ok(d.clone()),
+            ExpressionValue::String(s) => s
+                .trim()
+                .parse()
+                .map_err(|_| ExpressionError::InvalidConversion(s.clone(), "number")),
+            ExpressionValue::Boolean(b) => Ok(u8::from(*b).into()),
+            value => Err(ExpressionError::InvalidConversion(
+                value.to_string(),
+                "number",
+            )),
+        }
+    }
+
+    /// The length of a duration in seconds
+    fn to_nullable_duration(&self) -> EvaluationResult<Option<BigDecimal>> {
+        match self {
+            ExpressionValue::Duration(seconds) => Ok(Some(seconds.clone())),
+            ExpressionValue::Null => Ok(None),
+            _ => Err(ExpressionError::ExpectedDuration),
+        }
+    }
+
+    fn into_nullable_array(self) -> EvaluationResult<Option<Vec<ExpressionValue>>> {
+        match self {
+            ExpressionValue::Array(values) => Ok(Some(values)),
+            ExpressionValue::Null => Ok(None),
+            _ => Err(ExpressionError::ExpectedArray),
+        }
+    }
+
+    /// The value nested in this one at `path`, if there is one
+    fn get(&self, path: &[PathSegment]) -> Option<&ExpressionValue> {
+        let mut value = self;
+        for segment in path {
+            value = match (value, segment) {
+                (ExpressionValue::Object(object), PathSegment::Key(key)) => object.get(key)?,
+                (ExpressionValue::Array(array), PathSegment::Index(index)) => array.get(*index)?,
+                _ => return None,
+            };
+        }
+        Some(value)
+    }
+
+    /// Converts a property value, in strict mode strings are never parsed as numbers
+    pub(crate) fn from_property(value: &PropertyValue, strict_types: bool) -> Self {
+        match value {
+            PropertyValue::String(s) if !strict_types => match s.parse::<BigDecimal>() {
+                Ok(decimal_value) => decimal_value.into(),
+                Err(_) => s.clone().into(),
+            },
+            PropertyValue::String(s) => s.clone().into(),
+            PropertyValue::Number(n) => n.clone().into(),
+            PropertyValue::Boolean(b) => (*b).into(),
+            PropertyValue::Null => ExpressionValue::Null,
+            PropertyValue::Array(values) => ExpressionValue::Array(
+                values
+                    .iter()
+                    .map(|v| Self::from_property(v, strict_types))
+                    .collect(),
+            ),
+            PropertyValue::Object(object) => ExpressionValue::Object(
+                object
+                    .iter()
+                    .map(|(k, v)| (k.clone(), Self::from_property(v, strict_types)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Arrays and objects are displayed as JSON, with the other values nested in
+    /// them as JSON strings, numbers, booleans or null
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        match self {
+            ExpressionValue::Number(d) => d
+                .to_string()
+                .parse()
+                .map_or_else(|_| d.to_string().into(), serde_json::Value::Number),
+            ExpressionValue::Boolean(b) => (*b).into(),
+            ExpressionValue::Null => serde_json::Value::Null,
+            ExpressionValue::Array(values) => values.iter().map(Self::to_json).collect(),
+            ExpressionValue::Object(object) => object
+                .iter()
+                .map(|(k, v)| (k.clone(), v.to_json()))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+            value => value.to_string().into(),
+        }
+    }
+
+    /// The string representation of the value, as used by `concat`
+    fn to_nullable_string(&self) -> Option<String> {
+        match self {
+            ExpressionValue::Null => None,
+            value => Some(value.to_string()),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            ExpressionValue::Number(_) => "number",
+            ExpressionValue::String(_) => "string",
+            ExpressionValue::Boolean(_) => "boolean",
+            ExpressionValue::DateTime(_) => "datetime",
+            ExpressionValue::Duration(_) => "duration",
+            ExpressionValue::Money(..) => "money",
+            ExpressionValue::Array(_) => "array",
+            ExpressionValue::Object(_) => "object",
+            ExpressionValue::Null => "null",
+        }
+    }
+
+    /// Compares two values of the same type. Numbers and strings are ordered
+    /// naturally, `false` is ordered before `true`, date and times
+    /// chronologically, also against numbers as timestamps, and durations by
+    /// length.
+    fn compare(&self, other: &ExpressionValue) -> EvaluationResult<Ordering> {
+        match (self, other) {
+            (ExpressionValue::Number(l), ExpressionValue::Number(r)) => Ok(l.cmp(r)),
+            (l @ ExpressionValue::DateTime(_), r @ ExpressionValue::Number(_))
+            | (l @ ExpressionValue::Number(_), r @ ExpressionValue::DateTime(_)) => {
+                Ok(l.to_datetime()?.cmp(&r.to_datetime()?))
+            }
+            (ExpressionValue::String(l), ExpressionValue::String(r)) => Ok(l.cmp(r)),
+            (ExpressionValue::Boolean(l), ExpressionValue::Boolean(r)) => Ok(l.cmp(r)),
+            (ExpressionValue::DateTime(l), ExpressionValue::DateTime(r)) => Ok(l.cmp(r)),
+            (ExpressionValue::Duration(l), ExpressionValue::Duration(r)) => Ok(l.cmp(r)),
+            (ExpressionValue::Money(l, lc), ExpressionValue::Money(r, rc)) => {
+                same_currency(*lc, *rc)?;
+                Ok(l.cmp(r))
+            }
+            (l, r) => Err(ExpressionError::IncomparableTypes(
+                l.type_name(),
+                r.type_name(),
+            )),
+        }
+    }
+
+    /// Whether two values are equal. Unlike `compare`, values of different
+    /// types are never equal rather than incomparable, and arrays and objects
+    /// are equal when their elements are.
+    fn equals(&self, other: &ExpressionValue) -> EvaluationResult<bool> {
+        match (self, other) {
+            (ExpressionValue::Array(l), ExpressionValue::Array(r)) => {
+                if l.len() != r.len() {
+                    return Ok(false);
+                }
+                for (l, r) in l.iter().zip(r) {
+                    if !l.equals(r)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            (ExpressionValue::Object(l), ExpressionValue::Object(r)) => {
+                if l.len() != r.len() {
+                    return Ok(false);
+                }
+                for (key, l) in l {
+                    match r.get(key) {
+                        Some(r) if l.equals(r)? => {}
+                        _ => return Ok(false),
+                    }
+                }
+                Ok(true)
+            }
+            (l, r) => match l.compare(r) {
+                Ok(ordering) => Ok(ordering.is_eq()),
+                Err(ExpressionError::IncomparableTypes(..)) => Ok(false),
+                Err(e) => Err(e),
+            },
         }
     }
 }
@@ -48,8 +453,78 @@ pub enum ExpressionError {
     #[error("Expected a decimal")]
     ExpectedDecimal,
 
-    #[error("Variable: {0} not found")]
-    MissingVariable(String),
+    #[error("Expected a boolean")]
+    ExpectedBoolean,
+
+    #[error("Cannot compare a {0} with a {1}")]
+    IncomparableTypes(&'static str, &'static str),
+
+    #[error("Expected a string")]
+    ExpectedString,
+
+    #[error("Expected a timestamp")]
+    ExpectedTimestamp,
+
+    #[error("Expected a duration")]
+    ExpectedDuration,
+
+    #[error("Expected an array")]
+    ExpectedArray,
+
+    #[error("Expected an object")]
+    ExpectedObject,
+
+    #[error("Missing parameter: {0}")]
+    MissingParameter(String),
+
+    #[error("Unknown variable: {0}")]
+    UnknownVariable(String),
+
+    #[error("Cannot index a {0}")]
+    NotIndexable(&'static str),
+
+    #[error("Cannot convert {0} to a {1}")]
+    InvalidConversion(String, &'static str),
+
+    #[error("Division by zero")]
+    DivisionByZero,
+
+    #[error("{0} is undefined for {1}")]
+    Undefined(String, BigDecimal),
+
+    #[error("Numeric overflow")]
+    Overflow,
+
+    #[error("Invalid timestamp: {0}")]
+    InvalidTimestamp(BigDecimal),
+
+    #[error("Invalid date and time: {0}")]
+    InvalidDateTime(String),
+
+    #[error("Invalid date and time format: {0}")]
+    InvalidDateTimeFormat(String),
+
+    #[error("Invalid pricing tier: {0}")]
+    InvalidTier(String),
+
+    #[error("Expected a money amount")]
+    ExpectedMoney,
+
+    #[error("Unknown currency: {0}")]
+    UnknownCurrency(String),
+
+    #[error("Cannot combine amounts in {0} and {1}")]
+    CurrencyMismatch(Currency, Currency),
+
+    #[error("Missing exchange rate from {from} to {to}")]
+    MissingExchangeRate { from: Currency, to: Currency },
+
+    #[error("Invalid exchange rate: {0}")]
+    InvalidExchangeRate(BigDecimal),
+
+    #[cfg(feature = "wasm")]
+    #[error(transparent)]
+    Wasm(#[from] crate::wasm::WasmError),
 }
 
 pub type EvaluationResult<T> = Result<T, ExpressionError>;
@@ -64,19 +539,20 @@ impl From<BigDecimal> for ExpressionValue {
         ExpressionValue::Number(value)
     }
 }
+impl From<bool> for ExpressionValue {
+    fn from(value: bool) -> Self {
+        ExpressionValue::Boolean(value)
+    }
+}
+impl From<DateTime<Utc>> for ExpressionValue {
+    fn from(value: DateTime<Utc>) -> Self {
+        ExpressionValue::DateTime(value)
+    }
+}
 
 impl From<PropertyValue> for ExpressionValue {
     fn from(value: PropertyValue) -> Self {
-        match value {
-            PropertyValue::String(s) => {
-                if let Ok(decimal_value) = s.parse::<BigDecimal>() {
-                    decimal_value.into()
-                } else {
-                    s.into()
-                }
-            }
-            PropertyValue::Number(n) => n.into(),
-        }
+        ExpressionValue::from_property(&value, false)
     }
 }
 
@@ -85,17 +561,28 @@ impl Display for ExpressionValue {
         match self {
             ExpressionValue::Number(d) => d.fmt(f),
             ExpressionValue::String(s) => s.fmt(f),
+            ExpressionValue::Boolean(b) => b.fmt(f),
+            ExpressionValue::DateTime(dt) => {
+                f.write_str(&dt.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            }
+            ExpressionValue::Duration(seconds) => write!(f, "{}s", seconds.normalized()),
+            ExpressionValue::Money(amount, currency) => write!(f, "{amount} {currency}"),
+            ExpressionValue::Array(_) | ExpressionValue::Object(_) => self.to_json().fmt(f),
+            ExpressionValue::Null => f.write_str("null"),
         }
     }
 }
 
 impl Function {
-    pub fn evaluate(&self, event: &Event) -> EvaluationResult<ExpressionValue> {
+    fn evaluate(&self, scope: &Scope) -> EvaluationResult<ExpressionValue> {
         match self {
             Function::Concat(args) => {
+                // Null arguments are skipped, like in SQL
                 let evaluated_args = args
                     .iter()
-                    .map(|e| e.evaluate(event).map(|v| v.to_string()))
+                    .map(|e| e.evaluate_in(scope))
+                    .filter(|v| !v.as_ref().is_ok_and(ExpressionValue::is_null))
+                    .map(|v| v.map(|v| v.to_string()))
                     .collect::<EvaluationResult<Vec<String>>>()?;
 
                 Ok(ExpressionValue::String(evaluated_args.concat()))
@@ -103,118 +590,1004 @@ impl Function {
             Function::Round(expr, digit_expr) => evaluate_with_rounding_mode(
                 expr.as_ref(),
                 digit_expr.as_ref().map(AsRef::as_ref),
-                event,
+                scope,
                 RoundingMode::HalfUp,
             ),
             Function::Ceil(expr, digit_expr) => evaluate_with_rounding_mode(
                 expr.as_ref(),
                 digit_expr.as_ref().map(AsRef::as_ref),
-                event,
+                scope,
                 RoundingMode::Ceiling,
             ),
             Function::Floor(expr, digit_expr) => evaluate_with_rounding_mode(
                 expr.as_ref(),
                 digit_expr.as_ref().map(AsRef::as_ref),
-                event,
+                scope,
                 RoundingMode::Floor,
             ),
             Function::Least(args) => {
-                let min_value = args
-                    .iter()
-                    .map(|e| e.evaluate(event).and_then(|v| v.to_decimal()))
-                    .collect::<EvaluationResult<Vec<BigDecimal>>>()?
-                    .into_iter()
-                    .min()
-                    .ok_or(ExpressionError::EmptyArgumentList)?;
-                Ok(ExpressionValue::Number(min_value))
+                if args.is_empty() {
+                    return Err(ExpressionError::EmptyArgumentList);
+                }
+                let min_value = evaluate_non_null_decimals(args, scope)?.into_iter().min();
+                Ok(min_value.map_or(ExpressionValue::Null, ExpressionValue::Number))
             }
             Function::Greatest(args) => {
-                let max_value = args
+                if args.is_empty() {
+                    return Err(ExpressionError::EmptyArgumentList);
+                }
+                let max_value = evaluate_non_null_decimals(args, scope)?.into_iter().max();
+                Ok(max_value.map_or(ExpressionValue::Null, ExpressionValue::Number))
+            }
+            Function::Coalesce(args) => {
+                if args.is_empty() {
+                    return Err(ExpressionError::EmptyArgumentList);
+                }
+                for arg in args {
+                    let value = arg.evaluate_in(scope)?;
+                    if !value.is_null() {
+                        return Ok(value);
+                    }
+                }
+                Ok(ExpressionValue::Null)
+            }
+            Function::IsNull(expr) => Ok(expr.evaluate_in(scope)?.is_null().into()),
+            Function::HasProperty(expr) => match expr.evaluate_in(scope)? {
+                ExpressionValue::String(name) => {
+                    Ok(scope.event.properties.contains_key(&name).into())
+                }
+                _ => Err(ExpressionError::ExpectedString),
+            },
+            Function::Abs(expr) => evaluate_decimal_function(expr, scope, |d| Ok(d.abs())),
+            Function::Sign(expr) => evaluate_decimal_function(expr, scope, |d| {
+                Ok(match d.sign() {
+                    Sign::Minus => (-1).into(),
+                    Sign::NoSign => 0.into(),
+                    Sign::Plus => 1.into(),
+                })
+            }),
+            Function::Sqrt(expr) => evaluate_decimal_function(expr, scope, |d| math::sqrt(&d)),
+            Function::Ln(expr) => evaluate_decimal_function(expr, scope, |d| math::ln(&d)),
+            Function::Log10(expr) => evaluate_decimal_function(expr, scope, |d| math::log10(&d)),
+            Function::Exp(expr) => evaluate_decimal_function(expr, scope, |d| math::exp(&d)),
+            Function::Clamp(expr, min, max) => {
+                // A null bound leaves that side unbounded
+                let value = expr.evaluate_in(scope)?;
+                if value.is_null() {
+                    return Ok(ExpressionValue::Null);
+                }
+                let mut clamped = value.to_decimal()?;
+                if let Some(min) = min.evaluate_in(scope)?.to_nullable_decimal()? {
+                    clamped = clamped.max(min);
+                }
+                if let Some(max) = max.evaluate_in(scope)?.to_nullable_decimal()? {
+                    clamped = clamped.min(max);
+                }
+                Ok(clamped.into())
+            }
+            Function::Trunc(expr, digit_expr) => evaluate_with_rounding_mode(
+                expr.as_ref(),
+                digit_expr.as_ref().map(AsRef::as_ref),
+                scope,
+                RoundingMode::Down,
+            ),
+            Function::Upper(expr) => {
+                evaluate_string_function(expr, scope, |s| Ok(s.to_uppercase().into()))
+            }
+            Function::Lower(expr) => {
+                evaluate_string_function(expr, scope, |s| Ok(s.to_lowercase().into()))
+            }
+            Function::Trim(expr) => {
+                evaluate_string_function(expr, scope, |s| Ok(s.trim().to_owned().into()))
+            }
+            Function::Length(expr) => evaluate_string_function(expr, scope, |s| {
+                Ok(BigDecimal::from(s.chars().count() as u64).into())
+            }),
+            Function::Substring(expr, start, length) => {
+                let Some(s) = expr.evaluate_in(scope)?.to_nullable_string() else {
+                    return Ok(ExpressionValue::Null);
+                };
+                let Some(start) = start.evaluate_in(scope)?.to_nullable_integer()? else {
+                    return Ok(ExpressionValue::Null);
+                };
+                let length = match length {
+                    Some(length) => match length.evaluate_in(scope)?.to_nullable_integer()? {
+                        Some(length) => Some(length),
+                        None => return Ok(ExpressionValue::Null),
+                    },
+                    None => None,
+                };
+                Ok(substring(&s, start, length)?.into())
+            }
+            Function::Replace(expr, from, to) => {
+                let (Some(s), Some(from), Some(to)) = (
+                    expr.evaluate_in(scope)?.to_nullable_string(),
+                    from.evaluate_in(scope)?.to_nullable_string(),
+                    to.evaluate_in(scope)?.to_nullable_string(),
+                ) else {
+                    return Ok(ExpressionValue::Null);
+                };
+                if from.is_empty() {
+                    return Ok(s.into());
+                }
+                Ok(s.replace(&from, &to).into())
+            }
+            Function::SplitPart(expr, delimiter, index) => {
+                let (Some(s), Some(delimiter), Some(index)) = (
+                    expr.evaluate_in(scope)?.to_nullable_string(),
+                    delimiter.evaluate_in(scope)?.to_nullable_string(),
+                    index.evaluate_in(scope)?.to_nullable_integer()?,
+                ) else {
+                    return Ok(ExpressionValue::Null);
+                };
+                Ok(split_part(&s, &delimiter, index)?.into())
+            }
+            Function::ConcatWs(separator, args) => {
+                let Some(separator) = separator.evaluate_in(scope)?.to_nullable_string() else {
+                    return Ok(ExpressionValue::Null);
+                };
+                // Null arguments are skipped, like in `concat`
+                let mut parts = Vec::with_capacity(args.len());
+                for arg in args {
+                    if let Some(part) = arg.evaluate_in(scope)?.to_nullable_string() {
+                        parts.push(part);
+                    }
+                }
+                Ok(parts.join(&separator).into())
+            }
+            Function::Matches(expr, pattern) => {
+                evaluate_string_function(expr, scope, |s| Ok(pattern.regex().is_match(&s).into()))
+            }
+            Function::RegexExtract(expr, pattern, group) => {
+                let (Some(s), Some(group)) = (
+                    expr.evaluate_in(scope)?.to_nullable_string(),
+                    group.evaluate_in(scope)?.to_nullable_integer()?,
+                ) else {
+                    return Ok(ExpressionValue::Null);
+                };
+                let index = usize::try_from(group)
+                    .ok()
+                    .filter(|i| *i < pattern.regex().captures_len())
+                    .ok_or_else(|| {
+                        ExpressionError::Undefined("regex_extract group".to_owned(), group.into())
+                    })?;
+
+                // No match, or a group that didn't participate in the match, results in null
+                let extracted = pattern
+                    .regex()
+                    .captures(&s)
+                    .and_then(|captures| captures.get(index))
+                    .map(|m| m.as_str().to_owned());
+                Ok(extracted.map_or(ExpressionValue::Null, ExpressionValue::String))
+            }
+            Function::RegexReplace(expr, pattern, replacement) => {
+                let (Some(s), Some(replacement)) = (
+                    expr.evaluate_in(scope)?.to_nullable_string(),
+                    replacement.evaluate_in(scope)?.to_nullable_string(),
+                ) else {
+                    return Ok(ExpressionValue::Null);
+                };
+                Ok(pattern
+                    .regex()
+                    .replace_all(&s, replacement.as_str())
+                    .into_owned()
+                    .into())
+            }
+            Function::Year(expr) => {
+                evaluate_date_function(expr, scope, |dt| ExpressionValue::Number(dt.year().into()))
+            }
+            Function::Month(expr) => {
+                evaluate_date_function(expr, scope, |dt| ExpressionValue::Number(dt.month().into()))
+            }
+            Function::Day(expr) => {
+                evaluate_date_function(expr, scope, |dt| ExpressionValue::Number(dt.day().into()))
+            }
+            Function::Hour(expr) => {
+                evaluate_date_function(expr, scope, |dt| ExpressionValue::Number(dt.hour().into()))
+            }
+            Function::DayOfWeek(expr) => evaluate_date_function(expr, scope, |dt| {
+                ExpressionValue::Number(dt.weekday().number_from_monday().into())
+            }),
+            Function::DateTrunc(unit, expr) => {
+                let value = expr.evaluate_in(scope)?;
+                let Some(dt) = value.to_nullable_datetime()? else {
+                    return Ok(ExpressionValue::Null);
+                };
+                let truncated = unit
+                    .truncate(&dt.with_timezone(&scope.context.timezone))
+                    .with_timezone(&Utc);
+                match value {
+                    ExpressionValue::Number(timestamp) if datetime::is_milliseconds(&timestamp) => {
+                        let milliseconds =
+                            datetime::to_timestamp(&truncated) * BigDecimal::from(1000);
+                        Ok(milliseconds.normalized().into())
+                    }
+                    ExpressionValue::Number(_) => Ok(datetime::to_timestamp(&truncated).into()),
+                    _ => Ok(truncated.into()),
+                }
+            }
+            Function::TimeBetween(expr, start, end) => evaluate_date_function(expr, scope, |dt| {
+                datetime::time_between(dt.time(), *start, *end).into()
+            }),
+            Function::ParseDateTime(expr, format) => {
+                let Some(s) = expr.evaluate_in(scope)?.to_nullable_string() else {
+                    return Ok(ExpressionValue::Null);
+                };
+                let format = match format {
+                    Some(format) => match format.evaluate_in(scope)?.to_nullable_string() {
+                        Some(format) => Some(format),
+                        None => return Ok(ExpressionValue::Null),
+                    },
+                    None => None,
+                };
+                Ok(
+                    datetime::parse_datetime(&s, format.as_deref(), &scope.context.timezone)?
+                        .into(),
+                )
+            }
+            Function::FormatDateTime(expr, format) => {
+                let Some(dt) = expr.evaluate_in(scope)?.to_nullable_datetime()? else {
+                    return Ok(ExpressionValue::Null);
+                };
+                let Some(format) = format.evaluate_in(scope)?.to_nullable_string() else {
+                    return Ok(ExpressionValue::Null);
+                };
+                let local = dt.with_timezone(&scope.context.timezone);
+                Ok(datetime::format_datetime(&local, &format)?.into())
+            }
+            Function::ToHours(expr) => evaluate_duration_function(expr, scope, 3600),
+            Function::ToMinutes(expr) => evaluate_duration_function(expr, scope, 60),
+            Function::ToSeconds(expr) => evaluate_duration_function(expr, scope, 1),
+            Function::ToNumber(expr) => match expr.evaluate_in(scope)? {
+                ExpressionValue::Null => Ok(ExpressionValue::Null),
+                value => Ok(value.to_number()?.into()),
+            },
+            Function::ToInteger(expr) => match expr.evaluate_in(scope)? {
+                ExpressionValue::Null => Ok(ExpressionValue::Null),
+                value => Ok(value
+                    .to_number()?
+                    .with_scale_round(0, RoundingMode::Down)
+                    .into()),
+            },
+            Function::ToString(expr) => Ok(expr
+                .evaluate_in(scope)?
+                .to_nullable_string()
+                .map_or(ExpressionValue::Null, ExpressionValue::String)),
+            Function::ToEpoch(expr) => {
+                evaluate_date_function(expr, scope, |dt| datetime::to_timestamp(&dt).into())
+            }
+            // Like in SQL, aggregates skip null elements
+            Function::Sum(expr) => evaluate_array_function(expr, scope, |values| {
+                Ok(sum(values)?.unwrap_or_else(|| BigDecimal::zero().into()))
+            }),
+            Function::Count(expr) => evaluate_array_function(expr, scope, |values| {
+                Ok(BigDecimal::from(count(&values)).into())
+            }),
+            Function::Avg(expr) => evaluate_array_function(expr, scope, |values| {
+                let count = count(&values);
+                match sum(values)? {
+                    Some(total) => divide(total, BigDecimal::from(count).into()),
+                    None => Ok(ExpressionValue::Null),
+                }
+            }),
+            Function::Min(expr) => {
+                evaluate_array_function(expr, scope, |values| extremum(values, Ordering::Less))
+            }
+            Function::Max(expr) => {
+                evaluate_array_function(expr, scope, |values| extremum(values, Ordering::Greater))
+            }
+            Function::Contains(expr, value) => {
+                let haystack = expr.evaluate_in(scope)?;
+                let value = value.evaluate_in(scope)?;
+                if haystack.is_null() || value.is_null() {
+                    return Ok(ExpressionValue::Null);
+                }
+                match haystack {
+                    ExpressionValue::String(s) => Ok(s.contains(&value.to_string()).into()),
+                    haystack => Ok(contains(
+                        &haystack.into_nullable_array()?.expect("non-null value"),
+                        &value,
+                    )?
+                    .into()),
+                }
+            }
+            Function::Join(expr, separator) => {
+                let (Some(values), Some(separator)) = (
+                    expr.evaluate_in(scope)?.into_nullable_array()?,
+                    separator.evaluate_in(scope)?.to_nullable_string(),
+                ) else {
+                    return Ok(ExpressionValue::Null);
+                };
+                // Null elements are skipped, like the arguments of `concat_ws`
+                let parts = values
+                    .iter()
+                    .filter_map(ExpressionValue::to_nullable_string)
+                    .collect::<Vec<_>>();
+                Ok(parts.join(&separator).into())
+            }
+            Function::Map(expr, lambda) => evaluate_array_function(expr, scope, |values| {
+                values
+                    .iter()
+                    .map(|value| lambda.apply(value, scope))
+                    .collect::<EvaluationResult<Vec<_>>>()
+                    .map(ExpressionValue::Array)
+            }),
+            Function::Filter(expr, lambda) => evaluate_array_function(expr, scope, |values| {
+                // Like conditions, a null result doesn't keep the element
+                let mut kept = Vec::new();
+                for value in values {
+                    if lambda.apply(&value, scope)?.to_nullable_bool()? == Some(true) {
+                        kept.push(value);
+                    }
+                }
+                Ok(ExpressionValue::Array(kept))
+            }),
+            Function::Graduated(units, tiers) => {
+                evaluate_tiered_function(units, tiers, scope, pricing::graduated)
+            }
+            Function::Volume(units, tiers) => {
+                evaluate_tiered_function(units, tiers, scope, pricing::volume)
+            }
+            Function::Package(units, size, price, free_units) => {
+                let (Some(units), Some(size), Some(price)) = (
+                    units.evaluate_in(scope)?.to_nullable_decimal()?,
+                    size.evaluate_in(scope)?.to_nullable_decimal()?,
+                    price.evaluate_in(scope)?.to_nullable_decimal()?,
+                ) else {
+                    return Ok(ExpressionValue::Null);
+                };
+                // Like the other optional arguments, null free units are left out
+                let free_units = evaluate_optional_decimal(free_units, scope)?.unwrap_or_default();
+                Ok(pricing::package(&units, &size, &price, &free_units)?.into())
+            }
+            Function::Percentage(amount, rate, fixed_fee, min, max) => {
+                let (Some(amount), Some(rate)) = (
+                    amount.evaluate_in(scope)?.to_nullable_decimal()?,
+                    rate.evaluate_in(scope)?.to_nullable_decimal()?,
+                ) else {
+                    return Ok(ExpressionValue::Null);
+                };
+                let fixed_fee = evaluate_optional_decimal(fixed_fee, scope)?.unwrap_or_default();
+                let min = evaluate_optional_decimal(min, scope)?;
+                let max = evaluate_optional_decimal(max, scope)?;
+                Ok(
+                    pricing::percentage(&amount, &rate, &fixed_fee, min.as_ref(), max.as_ref())
+                        .into(),
+                )
+            }
+            Function::Money(amount, currency) => {
+                evaluate_money_function(amount, currency, scope, |amount, _| amount)
+            }
+            Function::ToCents(expr) => match expr.evaluate_in(scope)? {
+                ExpressionValue::Money(amount, currency) => {
+                    Ok(currency.to_minor_units(&amount).into())
+                }
+                ExpressionValue::Null => Ok(ExpressionValue::Null),
+                _ => Err(ExpressionError::ExpectedMoney),
+            },
+            Function::FromCents(cents, currency) => {
+                evaluate_money_function(cents, currency, scope, |cents, currency| {
+                    currency.from_minor_units(&cents)
+                })
+            }
+            Function::Convert(amount, from, to) => {
+                let (amount, from, to) = match (
+                    amount.evaluate_in(scope)?,
+                    evaluate_currency(from, scope)?,
+                    evaluate_currency(to, scope)?,
+                ) {
+                    (ExpressionValue::Null, _, _) | (_, None, _) | (_, _, None) => {
+                        return Ok(ExpressionValue::Null)
+                    }
+                    (ExpressionValue::Money(amount, currency), Some(from), Some(to)) => {
+                        (amount, same_currency(currency, from)?, to)
+                    }
+                    (value, Some(from), Some(to)) => (value.to_decimal()?, from, to),
+                };
+                let rate = scope.context.exchange_rates.rate(from, to)?;
+                let converted =
+                    (amount * rate).with_scale_round(to.exponent() as i64, RoundingMode::HalfUp);
+                Ok(ExpressionValue::Money(converted, to))
+            }
+            Function::User(function, args) => {
+                let args = args
                     .iter()
-                    .map(|e| e.evaluate(event).and_then(|v| v.to_decimal()))
-                    .collect::<EvaluationResult<Vec<BigDecimal>>>()?
-                    .into_iter()
-                    .max()
-                    .ok_or(ExpressionError::EmptyArgumentList)?;
-                Ok(ExpressionValue::Number(max_value))
+                    .map(|e| e.evaluate_in(scope))
+                    .collect::<EvaluationResult<Vec<_>>>()?;
+                function.call(&args)
             }
         }
     }
 }
 
-fn evaluate_with_rounding_mode(
+impl Lambda {
+    /// Evaluates the body with the parameter bound to `value`
+    fn apply(&self, value: &ExpressionValue, scope: &Scope) -> EvaluationResult<ExpressionValue> {
+        scope.evaluate_with_variable(&self.body, &self.parameter, value)
+    }
+}
+
+/// Evaluates a function of a number and a currency code returning an amount
+/// in that currency, null arguments result in null
+fn evaluate_money_function<F>(
     expr: &Expression,
-    digits: Option<&Expression>,
-    event: &Event,
-    rounding_mode: RoundingMode,
-) -> EvaluationResult<ExpressionValue> {
-    let evaluated_decimal = expr.evaluate(event)?.to_decimal()?;
-    let round_digits = match digits {
-        Some(digit_expr) => digit_expr
-            .evaluate(event)?
-            .to_decimal()?
-            .to_i64()
-            .ok_or(ExpressionError::ExpectedDecimal)?,
-        None => 0,
+    currency: &Expression,
+    scope: &Scope,
+    f: F,
+) -> EvaluationResult<ExpressionValue>
+where
+    F: FnOnce(BigDecimal, Currency) -> BigDecimal,
+{
+    let Some(value) = expr.evaluate_in(scope)?.to_nullable_decimal()? else {
+        return Ok(ExpressionValue::Null);
     };
+    let Some(currency) = evaluate_currency(currency, scope)? else {
+        return Ok(ExpressionValue::Null);
+    };
+    Ok(ExpressionValue::Money(f(value, currency), currency))
+}
 
-    Ok(ExpressionValue::Number(
-        evaluated_decimal.with_scale_round(round_digits, rounding_mode),
-    ))
+/// Evaluates a currency code, `None` if it's null
+fn evaluate_currency(expr: &Expression, scope: &Scope) -> EvaluationResult<Option<Currency>> {
+    let code = match expr.evaluate_in(scope)? {
+        ExpressionValue::String(code) => code,
+        ExpressionValue::Null => return Ok(None),
+        _ => return Err(ExpressionError::ExpectedString),
+    };
+    Currency::from_code(&code)
+        .map(Some)
+        .ok_or(ExpressionError::UnknownCurrency(code))
 }
 
-impl EventAttribute {
-    pub fn evaluate(&self, event: &Event) -> EvaluationResult<ExpressionValue> {
-        let evaluated_attribute = match self {
-            EventAttribute::Code => event.code.to_owned().into(),
-            EventAttribute::Timestamp => event.timestamp.clone().into(),
+/// Evaluates a pricing function of units and tiers, null units or tiers result in null
+fn evaluate_tiered_function<F>(
+    units: &Expression,
+    tiers: &Expression,
+    scope: &Scope,
+    f: F,
+) -> EvaluationResult<ExpressionValue>
+where
+    F: FnOnce(&BigDecimal, &[Tier]) -> EvaluationResult<BigDecimal>,
+{
+    let Some(units) = units.evaluate_in(scope)?.to_nullable_decimal()? else {
+        return Ok(ExpressionValue::Null);
+    };
+    let Some(tiers) = tiers.evaluate_in(scope)?.into_nullable_array()? else {
+        return Ok(ExpressionValue::Null);
+    };
+    let tiers = tiers
+        .iter()
+        .map(tier)
+        .collect::<EvaluationResult<Vec<_>>>()?;
+    Ok(f(&units, &tiers)?.into())
+}
 
-            EventAttribute::Properties(name) => {
-                let value = event
-                    .properties
-                    .get(name)
-                    .ok_or(ExpressionError::MissingVariable(name.clone()))?;
+/// A tier from `[from, to, per_unit]` or `[from, to, per_unit, flat]`, `to` is
+/// null for the last tier
+fn tier(value: &ExpressionValue) -> EvaluationResult<Tier> {
+    let invalid = || ExpressionError::InvalidTier(value.to_string());
+    let ExpressionValue::Array(values) = value else {
+        return Err(invalid());
+    };
+    let decimal = |i: usize| values[i].to_decimal().map_err(|_| invalid());
+    match values.len() {
+        3 | 4 => Ok(Tier {
+            from: decimal(0)?,
+            to: values[1].to_nullable_decimal().map_err(|_| invalid())?,
+            per_unit: decimal(2)?,
+            flat: if values.len() == 4 {
+                decimal(3)?
+            } else {
+                BigDecimal::zero()
+            },
+        }),
+        _ => Err(invalid()),
+    }
+}
 
-                match value {
-                    PropertyValue::String(s) => {
-                        if let Ok(decimal_value) = s.parse() {
-                            ExpressionValue::Number(decimal_value)
-                        } else {
-                            ExpressionValue::String(s.clone())
-                        }
-                    }
-                    PropertyValue::Number(d) => ExpressionValue::Number(d.to_owned()),
-                }
-            }
-        };
-        Ok(evaluated_attribute)
+/// The value of an optional argument, a missing or null argument is `None`
+fn evaluate_optional_decimal(
+    expr: &Option<Box<Expression>>,
+    scope: &Scope,
+) -> EvaluationResult<Option<BigDecimal>> {
+    match expr {
+        Some(expr) => expr.evaluate_in(scope)?.to_nullable_decimal(),
+        None => Ok(None),
     }
 }
 
-impl Operation {
-    pub fn evaluate(
-        &self,
-        lhs: &Expression,
-        rhs: &Expression,
-        event: &Event,
-    ) -> EvaluationResult<ExpressionValue> {
-        let lhs_decimal = lhs.evaluate(event)?.to_decimal()?;
-        let rhs_decimal = rhs.evaluate(event)?.to_decimal()?;
+/// Evaluates a function of a single array argument, a null argument results in null
+fn evaluate_array_function<F>(
+    expr: &Expression,
+    scope: &Scope,
+    f: F,
+) -> EvaluationResult<ExpressionValue>
+where
+    F: FnOnce(Vec<ExpressionValue>) -> EvaluationResult<ExpressionValue>,
+{
+    match expr.evaluate_in(scope)?.into_nullable_array()? {
+        Some(values) => f(values),
+        None => Ok(ExpressionValue::Null),
+    }
+}
 
-        let evaluated = match self {
-            Operation::Add => lhs_decimal + rhs_decimal,
-            Operation::Subtract => lhs_decimal - rhs_decimal,
-            Operation::Multiply => lhs_decimal * rhs_decimal,
-            Operation::Divide => lhs_decimal / rhs_decimal,
-        };
+/// Value of an object at a key or element of an array at an index starting at 0.
+/// Missing keys and indexes out of bounds result in null, so lookups can be
+/// given a default with `??`.
+fn index(value: ExpressionValue, key: &ExpressionValue) -> EvaluationResult<ExpressionValue> {
+    let indexed = match (value, key) {
+        (ExpressionValue::Null, _) | (_, ExpressionValue::Null) => None,
+        (ExpressionValue::Object(mut object), key) => object.remove(&key.to_string()),
+        (ExpressionValue::Array(mut values), key) => key
+            .to_nullable_integer()?
+            .and_then(|i| usize::try_from(i).ok())
+            .filter(|i| *i < values.len())
+            .map(|i| values.swap_remove(i)),
+        (value, _) => return Err(ExpressionError::NotIndexable(value.type_name())),
+    };
+    Ok(indexed.unwrap_or(ExpressionValue::Null))
+}
 
-        Ok(evaluated.into())
+/// Whether one of the values is equal to `value`, as with `==`
+fn contains(values: &[ExpressionValue], value: &ExpressionValue) -> EvaluationResult<bool> {
+    for v in values {
+        if v.equals(value)? {
+            return Ok(true);
+        }
     }
+    Ok(false)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Like SQL's `in`, when the value isn't found but the array has null elements
+/// the result is null, as any of them could have been equal
+fn is_in(value: &ExpressionValue, array: ExpressionValue) -> EvaluationResult<ExpressionValue> {
+    let values = array.into_nullable_array()?.expect("non-null value");
+    if contains(&values, value)? {
+        Ok(true.into())
+    } else if values.iter().any(ExpressionValue::is_null) {
+        Ok(ExpressionValue::Null)
+    } else {
+        Ok(false.into())
+    }
+}
+
+/// Number of non-null values
+fn count(values: &[ExpressionValue]) -> u64 {
+    values.iter().filter(|v| !v.is_null()).count() as u64
+}
+
+/// Sum of the non-null values, which are either all numbers or all durations.
+/// `None` when there are no values to sum.
+fn sum(values: Vec<ExpressionValue>) -> EvaluationResult<Option<ExpressionValue>> {
+    let mut values = values.into_iter().filter(|v| !v.is_null());
+    let mut total = match values.next() {
+        Some(
+            value @ (ExpressionValue::Number(_)
+            | ExpressionValue::Duration(_)
+            | ExpressionValue::Money(..)),
+        ) => value,
+        Some(_) => return Err(ExpressionError::ExpectedDecimal),
+        None => return Ok(None),
+    };
+    for value in values {
+        total = match (total, value) {
+            (ExpressionValue::Number(l), ExpressionValue::Number(r)) => (l + r).into(),
+            (ExpressionValue::Duration(l), ExpressionValue::Duration(r)) => {
+                ExpressionValue::Duration(l + r)
+            }
+            (ExpressionValue::Money(l, lc), ExpressionValue::Money(r, rc)) => {
+                ExpressionValue::Money(l + r, same_currency(lc, rc)?)
+            }
+            (ExpressionValue::Duration(_), _) => return Err(ExpressionError::ExpectedDuration),
+            (ExpressionValue::Money(..), _) => return Err(ExpressionError::ExpectedMoney),
+            _ => return Err(ExpressionError::ExpectedDecimal),
+        };
+    }
+    Ok(Some(total))
+}
+
+/// The smallest or largest of the non-null values, depending on `ordering`,
+/// null when there are none
+fn extremum(values: Vec<ExpressionValue>, ordering: Ordering) -> EvaluationResult<ExpressionValue> {
+    let mut result = ExpressionValue::Null;
+    for value in values.into_iter().filter(|v| !v.is_null()) {
+        if result.is_null() || value.compare(&result)? == ordering {
+            result = value;
+        }
+    }
+    Ok(result)
+}
+
+/// Converts a duration argument to a number of `unit_seconds`, a null argument
+/// results in null
+fn evaluate_duration_function(
+    expr: &Expression,
+    scope: &Scope,
+    unit_seconds: u32,
+) -> EvaluationResult<ExpressionValue> {
+    match expr.evaluate_in(scope)?.to_nullable_duration()? {
+        Some(seconds) => Ok((seconds / BigDecimal::from(unit_seconds))
+            .normalized()
+            .into()),
+        None => Ok(ExpressionValue::Null),
+    }
+}
+
+/// Evaluates a function of a single date and time or timestamp argument in the
+/// timezone of the context, a null argument results in null
+fn evaluate_date_function<F>(
+    expr: &Expression,
+    scope: &Scope,
+    f: F,
+) -> EvaluationResult<ExpressionValue>
+where
+    F: FnOnce(DateTime<Tz>) -> ExpressionValue,
+{
+    match expr.evaluate_in(scope)?.to_nullable_datetime()? {
+        Some(dt) => Ok(f(dt.with_timezone(&scope.context.timezone))),
+        None => Ok(ExpressionValue::Null),
+    }
+}
+
+/// Evaluates a function of a single string argument, a null argument results in null
+fn evaluate_string_function<F>(
+    expr: &Expression,
+    scope: &Scope,
+    f: F,
+) -> EvaluationResult<ExpressionValue>
+where
+    F: FnOnce(String) -> EvaluationResult<ExpressionValue>,
+{
+    match expr.evaluate_in(scope)?.to_nullable_string() {
+        Some(s) => f(s),
+        None => Ok(ExpressionValue::Null),
+    }
+}
+
+/// Characters from the 1-based `start` position, like SQL's `substring` a start
+/// before the first character shortens the result instead of shifting it.
+fn substring(s: &str, start: i64, length: Option<i64>) -> EvaluationResult<String> {
+    let first = start.max(1);
+    let count = match length {
+        Some(length) if length < 0 => {
+            return Err(ExpressionError::Undefined(
+                "substring length".to_owned(),
+                length.into(),
+            ))
+        }
+        Some(length) => start.saturating_add(length).saturating_sub(first).max(0),
+        None => i64::MAX,
+    };
+
+    let skip = usize::try_from(first - 1).unwrap_or(usize::MAX);
+    let take = usize::try_from(count).unwrap_or(usize::MAX);
+    Ok(s.chars().skip(skip).take(take).collect())
+}
+
+/// The part at the 1-based `index` after splitting on `delimiter`. Negative
+/// indexes count from the end, out of range indexes result in an empty string.
+fn split_part(s: &str, delimiter: &str, index: i64) -> EvaluationResult<String> {
+    if index == 0 {
+        return Err(ExpressionError::Undefined(
+            "split_part index".to_owned(),
+            index.into(),
+        ));
+    }
+
+    let parts = if delimiter.is_empty() {
+        vec![s]
+    } else {
+        s.split(delimiter).collect()
+    };
+    let position = if index > 0 {
+        usize::try_from(index - 1).ok()
+    } else {
+        usize::try_from(index.unsigned_abs())
+            .ok()
+            .and_then(|i| parts.len().checked_sub(i))
+    };
+
+    Ok(position
+        .and_then(|i| parts.get(i))
+        .copied()
+        .unwrap_or_default()
+        .to_owned())
+}
+
+/// Evaluates a function of a single decimal argument, a null argument results in null
+fn evaluate_decimal_function<F>(
+    expr: &Expression,
+    scope: &Scope,
+    f: F,
+) -> EvaluationResult<ExpressionValue>
+where
+    F: FnOnce(BigDecimal) -> EvaluationResult<BigDecimal>,
+{
+    match expr.evaluate_in(scope)?.to_nullable_decimal()? {
+        Some(d) => Ok(f(d)?.into()),
+        None => Ok(ExpressionValue::Null),
+    }
+}
+
+/// Evaluates all arguments as decimals, leaving out the null values
+fn evaluate_non_null_decimals(
+    args: &[Expression],
+    scope: &Scope,
+) -> EvaluationResult<Vec<BigDecimal>> {
+    let mut decimals = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg.evaluate_in(scope)? {
+            ExpressionValue::Null => {}
+            value => decimals.push(value.to_decimal()?),
+        }
+    }
+    Ok(decimals)
+}
+
+fn evaluate_with_rounding_mode(
+    expr: &Expression,
+    digits: Option<&Expression>,
+    scope: &Scope,
+    rounding_mode: RoundingMode,
+) -> EvaluationResult<ExpressionValue> {
+    let evaluated = expr.evaluate_in(scope)?;
+    if evaluated.is_null() {
+        return Ok(ExpressionValue::Null);
+    }
+    // Amounts are rounded to the minor unit of their currency by default
+    let (evaluated_decimal, currency) = match evaluated {
+        ExpressionValue::Money(amount, currency) => (amount, Some(currency)),
+        value => (value.to_decimal()?, None),
+    };
+    let round_digits = match digits {
+        Some(digit_expr) => match digit_expr.evaluate_in(scope)? {
+            ExpressionValue::Null => return Ok(ExpressionValue::Null),
+            value => value
+                .to_decimal()?
+                .to_i64()
+                .ok_or(ExpressionError::ExpectedDecimal)?,
+        },
+        None => currency.map_or(0, |currency| currency.exponent().into()),
+    };
+
+    let rounded = evaluated_decimal.with_scale_round(round_digits, rounding_mode);
+    match currency {
+        Some(currency) => Ok(ExpressionValue::Money(rounded, currency)),
+        None => Ok(ExpressionValue::Number(rounded)),
+    }
+}
+
+impl EventAttribute {
+    fn evaluate(&self, scope: &Scope) -> EvaluationResult<ExpressionValue> {
+        let evaluated_attribute = match self {
+            EventAttribute::Code => scope.event.code.to_owned().into(),
+            EventAttribute::Timestamp => scope.event.timestamp.clone().into(),
+
+            EventAttribute::Properties(name, path) => {
+                // Missing properties evaluate to null, so they can be defaulted with `coalesce`,
+                // and so do paths to nested values that don't exist
+                let value = scope.event.properties.get(name);
+                nested_property(value, path).map_or(ExpressionValue::Null, |value| {
+                    ExpressionValue::from_property(value, scope.context.strict_types)
+                })
+            }
+        };
+        Ok(evaluated_attribute)
+    }
+}
+
+/// The value nested in a property at `path`, if there is one
+fn nested_property<'a>(
+    mut value: Option<&'a PropertyValue>,
+    path: &[PathSegment],
+) -> Option<&'a PropertyValue> {
+    for segment in path {
+        value = match (value, segment) {
+            (Some(PropertyValue::Object(object)), PathSegment::Key(key)) => object.get(key),
+            (Some(PropertyValue::Array(array)), PathSegment::Index(index)) => array.get(*index),
+            _ => None,
+        };
+    }
+    value
+}
+
+impl Operation {
+    fn evaluate(
+        &self,
+        lhs: &Expression,
+        rhs: &Expression,
+        scope: &Scope,
+    ) -> EvaluationResult<ExpressionValue> {
+        // Logical operators short-circuit, so the right hand side is only
+        // evaluated when it determines the result. Nulls follow SQL's
+        // three-valued logic, e.g. `false and null` is false, `true and null` is null.
+        match self {
+            Operation::And => {
+                let lhs_bool = lhs.evaluate_in(scope)?.to_nullable_bool()?;
+                if lhs_bool == Some(false) {
+                    return Ok(false.into());
+                }
+                let evaluated = match (lhs_bool, rhs.evaluate_in(scope)?.to_nullable_bool()?) {
+                    (_, Some(false)) => false.into(),
+                    (Some(true), Some(true)) => true.into(),
+                    _ => ExpressionValue::Null,
+                };
+                return Ok(evaluated);
+            }
+            Operation::Fallback => {
+                // Like `coalesce`, the fallback is only evaluated when it is used
+                let lhs_value = lhs.evaluate_in(scope)?;
+                if lhs_value.is_null() {
+                    return rhs.evaluate_in(scope);
+                }
+                return Ok(lhs_value);
+            }
+            Operation::Or => {
+                let lhs_bool = lhs.evaluate_in(scope)?.to_nullable_bool()?;
+                if lhs_bool == Some(true) {
+                    return Ok(true.into());
+                }
+                let evaluated = match (lhs_bool, rhs.evaluate_in(scope)?.to_nullable_bool()?) {
+                    (_, Some(true)) => true.into(),
+                    (Some(false), Some(false)) => false.into(),
+                    _ => ExpressionValue::Null,
+                };
+                return Ok(evaluated);
+            }
+            _ => {}
+        }
+
+        let lhs_value = lhs.evaluate_in(scope)?;
+        let rhs_value = rhs.evaluate_in(scope)?;
+
+        // Any other operation involving a null results in null
+        if lhs_value.is_null() || rhs_value.is_null() {
+            return Ok(ExpressionValue::Null);
+        }
+
+        let evaluated = match self {
+            Operation::Add => add(lhs_value, rhs_value)?,
+            Operation::Subtract => subtract(lhs_value, rhs_value)?,
+            Operation::Multiply => multiply(lhs_value, rhs_value)?,
+            Operation::Divide => divide(lhs_value, rhs_value)?,
+            Operation::IntegerDivide => {
+                math::floored_div_rem(&lhs_value.to_decimal()?, &rhs_value.to_decimal()?)?
+                    .0
+                    .into()
+            }
+            Operation::Modulo => {
+                math::floored_div_rem(&lhs_value.to_decimal()?, &rhs_value.to_decimal()?)?
+                    .1
+                    .into()
+            }
+            Operation::Power => {
+                math::pow(&lhs_value.to_decimal()?, &rhs_value.to_decimal()?)?.into()
+            }
+            Operation::Equal => lhs_value.equals(&rhs_value)?.into(),
+            Operation::NotEqual => (!lhs_value.equals(&rhs_value)?).into(),
+            Operation::LessThan => lhs_value.compare(&rhs_value)?.is_lt().into(),
+            Operation::LessThanOrEqual => lhs_value.compare(&rhs_value)?.is_le().into(),
+            Operation::GreaterThan => lhs_value.compare(&rhs_value)?.is_gt().into(),
+            Operation::GreaterThanOrEqual => lhs_value.compare(&rhs_value)?.is_ge().into(),
+            Operation::In => is_in(&lhs_value, rhs_value)?,
+            Operation::And | Operation::Or | Operation::Fallback => {
+                unreachable!("short-circuiting operators are handled above")
+            }
+        };
+
+        Ok(evaluated)
+    }
+}
+
+// Besides numbers, `+` and `-` support moving date and times by durations,
+// and differences between date and times. Numbers are timestamps when
+// combined with date and times or durations, converted by `to_datetime` as
+// in comparisons.
+
+fn add(lhs: ExpressionValue, rhs: ExpressionValue) -> EvaluationResult<ExpressionValue> {
+    match (lhs, rhs) {
+        (ExpressionValue::Money(l, lc), ExpressionValue::Money(r, rc)) => {
+            Ok(ExpressionValue::Money(l + r, same_currency(lc, rc)?))
+        }
+        (ExpressionValue::Money(..), _) | (_, ExpressionValue::Money(..)) => {
+            Err(ExpressionError::ExpectedMoney)
+        }
+        (ExpressionValue::Duration(l), ExpressionValue::Duration(r)) => {
+            Ok(ExpressionValue::Duration(l + r))
+        }
+        (ExpressionValue::Duration(seconds), value)
+        | (value, ExpressionValue::Duration(seconds)) => {
+            let datetime = value.to_datetime()?;
+            Ok(datetime::add_seconds(&datetime, &seconds)?.into())
+        }
+        (lhs, rhs) => Ok((lhs.to_decimal()? + rhs.to_decimal()?).into()),
+    }
+}
+
+fn subtract(lhs: ExpressionValue, rhs: ExpressionValue) -> EvaluationResult<ExpressionValue> {
+    match (lhs, rhs) {
+        (ExpressionValue::Money(l, lc), ExpressionValue::Money(r, rc)) => {
+            Ok(ExpressionValue::Money(l - r, same_currency(lc, rc)?))
+        }
+        (ExpressionValue::Money(..), _) | (_, ExpressionValue::Money(..)) => {
+            Err(ExpressionError::ExpectedMoney)
+        }
+        (ExpressionValue::Duration(l), ExpressionValue::Duration(r)) => {
+            Ok(ExpressionValue::Duration(l - r))
+        }
+        (value, ExpressionValue::Duration(seconds)) => {
+            let datetime = value.to_datetime()?;
+            Ok(datetime::add_seconds(&datetime, &-seconds)?.into())
+        }
+        (lhs @ ExpressionValue::DateTime(_), rhs) | (lhs, rhs @ ExpressionValue::DateTime(_)) => {
+            Ok(ExpressionValue::Duration(
+                datetime::to_timestamp(&lhs.to_datetime()?)
+                    - datetime::to_timestamp(&rhs.to_datetime()?),
+            ))
+        }
+        (lhs, rhs) => Ok((lhs.to_decimal()? - rhs.to_decimal()?).into()),
+    }
+}
+
+fn multiply(lhs: ExpressionValue, rhs: ExpressionValue) -> EvaluationResult<ExpressionValue> {
+    match (lhs, rhs) {
+        (ExpressionValue::Money(amount, currency), factor)
+        | (factor, ExpressionValue::Money(amount, currency)) => Ok(ExpressionValue::Money(
+            amount * factor.to_decimal()?,
+            currency,
+        )),
+        (ExpressionValue::Duration(seconds), factor)
+        | (factor, ExpressionValue::Duration(seconds)) => {
+            Ok(ExpressionValue::Duration(seconds * factor.to_decimal()?))
+        }
+        (lhs, rhs) => Ok((lhs.to_decimal()? * rhs.to_decimal()?).into()),
+    }
+}
+
+fn divide(lhs: ExpressionValue, rhs: ExpressionValue) -> EvaluationResult<ExpressionValue> {
+    match (lhs, rhs) {
+        (ExpressionValue::Duration(l), ExpressionValue::Duration(r)) => {
+            Ok(checked_divide(l, r)?.into())
+        }
+        (ExpressionValue::Duration(l), rhs) => Ok(ExpressionValue::Duration(checked_divide(
+            l,
+            rhs.to_decimal()?,
+        )?)),
+        (ExpressionValue::Money(l, lc), ExpressionValue::Money(r, rc)) => {
+            same_currency(lc, rc)?;
+            Ok(checked_divide(l, r)?.into())
+        }
+        (ExpressionValue::Money(amount, currency), rhs) => Ok(ExpressionValue::Money(
+            checked_divide(amount, rhs.to_decimal()?)?,
+            currency,
+        )),
+        (lhs, rhs) => Ok(checked_divide(lhs.to_decimal()?, rhs.to_decimal()?)?.into()),
+    }
+}
+
+fn checked_divide(lhs: BigDecimal, rhs: BigDecimal) -> EvaluationResult<BigDecimal> {
+    if rhs.is_zero() {
+        return Err(ExpressionError::DivisionByZero);
+    }
+    Ok(lhs / rhs)
+}
+
+/// The currency of two amounts, which can only be combined in the same currency
+fn same_currency(lhs: Currency, rhs: Currency) -> EvaluationResult<Currency> {
+    if lhs != rhs {
+        return Err(ExpressionError::CurrencyMismatch(lhs, rhs));
+    }
+    Ok(lhs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        currency::ExchangeRates,
+        function::{ArgumentType, FunctionRegistry},
+        parser::Pattern,
+    };
 
     fn evaluate_and_compare(expr: Expression, event: &Event, expected_result: ExpressionValue) {
         match expr.evaluate(event) {
@@ -254,7 +1627,7 @@ mod tests {
 
     #[test]
     fn test_evaluate_event_attribute_property_decimal() {
-        let expr = Expression::EventAttribute(EventAttribute::Properties("bar".into()));
+        let expr = Expression::EventAttribute(EventAttribute::Properties("bar".into(), vec![]));
         let properties = vec![("bar".into(), "123".into())].into_iter().collect();
         let event = Event {
             properties,
@@ -265,7 +1638,7 @@ mod tests {
 
     #[test]
     fn test_evaluate_event_attribute_property_no_decimal() {
-        let expr = Expression::EventAttribute(EventAttribute::Properties("bar".into()));
+        let expr = Expression::EventAttribute(EventAttribute::Properties("bar".into(), vec![]));
         let properties = vec![("bar".into(), "foo".into())].into_iter().collect();
         let event = Event {
             properties,
@@ -428,4 +1801,2625 @@ mod tests {
         let event = Default::default();
         evaluate_and_compare(expr, &event, ExpressionValue::String("test-123".into()));
     }
+
+    #[test]
+    fn test_evaluate_boolean() {
+        let expr = Expression::Boolean(true);
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+    }
+
+    #[test]
+    fn test_evaluate_not() {
+        let expr = Expression::Not(Box::new(Expression::Boolean(true)));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(false));
+    }
+
+    #[test]
+    fn test_evaluate_compare_numbers() {
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::Decimal("2.50".parse::<BigDecimal>().unwrap())),
+            op: Operation::Equal,
+            rhs: Box::new(Expression::Decimal("2.5".parse::<BigDecimal>().unwrap())),
+        };
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::Decimal(2.into())),
+            op: Operation::GreaterThanOrEqual,
+            rhs: Box::new(Expression::Decimal(3.into())),
+        };
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(false));
+    }
+
+    #[test]
+    fn test_evaluate_compare_strings() {
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::String("abc".into())),
+            op: Operation::LessThan,
+            rhs: Box::new(Expression::String("abd".into())),
+        };
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::String("abc".into())),
+            op: Operation::NotEqual,
+            rhs: Box::new(Expression::String("abc".into())),
+        };
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(false));
+    }
+
+    #[test]
+    fn test_evaluate_compare_mismatched_types() {
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::String("abc".into())),
+            op: Operation::LessThan,
+            rhs: Box::new(Expression::Decimal(1.into())),
+        };
+        let event = Default::default();
+        assert!(matches!(
+            expr.evaluate(&event),
+            Err(ExpressionError::IncomparableTypes("string", "number"))
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_equality_of_mismatched_types() {
+        let event = Default::default();
+        let string = || Box::new(Expression::String("1".into()));
+        let number = || Box::new(Expression::Decimal(1.into()));
+
+        let expr = binop(string(), Operation::Equal, number());
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(false));
+        let expr = binop(number(), Operation::NotEqual, string());
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+
+        let array = || Box::new(Expression::Array(vec![Expression::Decimal(1.into())]));
+        let expr = binop(array(), Operation::Equal, array());
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+        let expr = binop(array(), Operation::Equal, number());
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(false));
+    }
+
+    #[test]
+    fn test_evaluate_equality_with_null() {
+        let event = Default::default();
+        let null = || Box::new(path("missing", vec![]));
+        let number = || Box::new(Expression::Decimal(1.into()));
+
+        for op in [Operation::Equal, Operation::NotEqual] {
+            let expr = binop(number(), op.clone(), null());
+            evaluate_and_compare(expr, &event, ExpressionValue::Null);
+            let expr = binop(null(), op, null());
+            evaluate_and_compare(expr, &event, ExpressionValue::Null);
+        }
+    }
+
+    #[test]
+    fn test_evaluate_and_short_circuits() {
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::Boolean(false)),
+            op: Operation::And,
+            rhs: Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                "missing".into(),
+                vec![],
+            ))),
+        };
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(false));
+    }
+
+    #[test]
+    fn test_evaluate_or_short_circuits() {
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::Boolean(true)),
+            op: Operation::Or,
+            rhs: Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                "missing".into(),
+                vec![],
+            ))),
+        };
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+    }
+
+    #[test]
+    fn test_evaluate_logical_operator_requires_boolean() {
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::Decimal(1.into())),
+            op: Operation::Or,
+            rhs: Box::new(Expression::Boolean(true)),
+        };
+        let event = Default::default();
+        assert!(matches!(
+            expr.evaluate(&event),
+            Err(ExpressionError::ExpectedBoolean)
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_conditional() {
+        let expr = Expression::Conditional {
+            branches: vec![
+                (Expression::Boolean(false), Expression::Decimal(1.into())),
+                (Expression::Boolean(true), Expression::Decimal(2.into())),
+            ],
+            otherwise: Box::new(Expression::Decimal(3.into())),
+        };
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(2.into()));
+    }
+
+    #[test]
+    fn test_evaluate_conditional_otherwise() {
+        let expr = Expression::Conditional {
+            branches: vec![(Expression::Boolean(false), Expression::Decimal(1.into()))],
+            otherwise: Box::new(Expression::Decimal(3.into())),
+        };
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(3.into()));
+    }
+
+    #[test]
+    fn test_evaluate_conditional_skips_other_branches() {
+        let expr = Expression::Conditional {
+            branches: vec![(Expression::Boolean(true), Expression::Decimal(1.into()))],
+            otherwise: Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                "missing".into(),
+                vec![],
+            ))),
+        };
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(1.into()));
+    }
+
+    #[test]
+    fn test_evaluate_conditional_requires_boolean() {
+        let expr = Expression::Conditional {
+            branches: vec![(Expression::Decimal(1.into()), Expression::Decimal(1.into()))],
+            otherwise: Box::new(Expression::Decimal(2.into())),
+        };
+        let event = Default::default();
+        assert!(matches!(
+            expr.evaluate(&event),
+            Err(ExpressionError::ExpectedBoolean)
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_missing_property_is_null() {
+        let expr = Expression::EventAttribute(EventAttribute::Properties("missing".into(), vec![]));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Null);
+    }
+
+    #[test]
+    fn test_evaluate_null_propagates_through_arithmetic() {
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::Decimal(1.into())),
+            op: Operation::Add,
+            rhs: Box::new(Expression::Null),
+        };
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Null);
+
+        let expr = Expression::UnaryMinus(Box::new(Expression::Null));
+        evaluate_and_compare(expr, &event, ExpressionValue::Null);
+    }
+
+    #[test]
+    fn test_evaluate_null_propagates_through_comparison() {
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::Null),
+            op: Operation::Equal,
+            rhs: Box::new(Expression::Null),
+        };
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Null);
+    }
+
+    #[test]
+    fn test_evaluate_null_three_valued_logic() {
+        let event = Default::default();
+        let cases = [
+            (
+                Operation::And,
+                Expression::Boolean(false),
+                ExpressionValue::Boolean(false),
+            ),
+            (
+                Operation::And,
+                Expression::Boolean(true),
+                ExpressionValue::Null,
+            ),
+            (
+                Operation::Or,
+                Expression::Boolean(true),
+                ExpressionValue::Boolean(true),
+            ),
+            (
+                Operation::Or,
+                Expression::Boolean(false),
+                ExpressionValue::Null,
+            ),
+        ];
+        for (op, rhs, expected) in cases {
+            let expr = Expression::BinOp {
+                lhs: Box::new(Expression::Null),
+                op,
+                rhs: Box::new(rhs),
+            };
+            evaluate_and_compare(expr, &event, expected);
+        }
+
+        let expr = Expression::Not(Box::new(Expression::Null));
+        evaluate_and_compare(expr, &event, ExpressionValue::Null);
+    }
+
+    #[test]
+    fn test_evaluate_conditional_null_condition() {
+        let expr = Expression::Conditional {
+            branches: vec![(Expression::Null, Expression::Decimal(1.into()))],
+            otherwise: Box::new(Expression::Decimal(2.into())),
+        };
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(2.into()));
+    }
+
+    #[test]
+    fn test_evaluate_round_null() {
+        let expr = Expression::Function(Function::Round(Box::new(Expression::Null), None));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Null);
+    }
+
+    #[test]
+    fn test_evaluate_concat_skips_null() {
+        let expr = Expression::Function(Function::Concat(vec![
+            Expression::String("a".into()),
+            Expression::Null,
+            Expression::String("b".into()),
+        ]));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::String("ab".into()));
+    }
+
+    #[test]
+    fn test_evaluate_least_skips_null() {
+        let expr = Expression::Function(Function::Least(vec![
+            Expression::Null,
+            Expression::Decimal(2.into()),
+        ]));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(2.into()));
+
+        let expr = Expression::Function(Function::Greatest(vec![Expression::Null]));
+        evaluate_and_compare(expr, &event, ExpressionValue::Null);
+    }
+
+    #[test]
+    fn test_evaluate_coalesce() {
+        let expr = Expression::Function(Function::Coalesce(vec![
+            Expression::EventAttribute(EventAttribute::Properties("missing".into(), vec![])),
+            Expression::Null,
+            Expression::Decimal(3.into()),
+            Expression::Decimal(4.into()),
+        ]));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(3.into()));
+    }
+
+    #[test]
+    fn test_evaluate_is_null() {
+        let expr = Expression::Function(Function::IsNull(Box::new(Expression::Null)));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+
+        let expr = Expression::Function(Function::IsNull(Box::new(Expression::String("".into()))));
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(false));
+    }
+
+    #[test]
+    fn test_evaluate_has_property() {
+        let properties = vec![("bar".into(), "123".into())].into_iter().collect();
+        let event = Event {
+            properties,
+            ..Default::default()
+        };
+
+        let expr = Expression::Function(Function::HasProperty(Box::new(Expression::String(
+            "bar".into(),
+        ))));
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+
+        let expr = Expression::Function(Function::HasProperty(Box::new(Expression::String(
+            "foo".into(),
+        ))));
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(false));
+    }
+
+    #[test]
+    fn test_evaluate_binop_divide_by_zero() {
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::Decimal(4.into())),
+            op: Operation::Divide,
+            rhs: Box::new(Expression::Decimal(0.into())),
+        };
+        let event = Default::default();
+        assert!(matches!(
+            expr.evaluate(&event),
+            Err(ExpressionError::DivisionByZero)
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_binop_integer_divide() {
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::UnaryMinus(Box::new(Expression::Decimal(
+                7.into(),
+            )))),
+            op: Operation::IntegerDivide,
+            rhs: Box::new(Expression::Decimal(2.into())),
+        };
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number((-4).into()));
+    }
+
+    #[test]
+    fn test_evaluate_binop_modulo() {
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::UnaryMinus(Box::new(Expression::Decimal(
+                7.into(),
+            )))),
+            op: Operation::Modulo,
+            rhs: Box::new(Expression::Decimal(2.into())),
+        };
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(1.into()));
+    }
+
+    #[test]
+    fn test_evaluate_binop_power() {
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::Decimal("1.5".parse::<BigDecimal>().unwrap())),
+            op: Operation::Power,
+            rhs: Box::new(Expression::Decimal(2.into())),
+        };
+        let event = Default::default();
+        evaluate_and_compare(
+            expr,
+            &event,
+            ExpressionValue::Number("2.25".parse::<BigDecimal>().unwrap()),
+        );
+    }
+
+    fn decimal(s: &str) -> Box<Expression> {
+        Box::new(Expression::Decimal(s.parse::<BigDecimal>().unwrap()))
+    }
+
+    #[test]
+    fn test_evaluate_abs() {
+        let expr = Expression::Function(Function::Abs(decimal("-1.5")));
+        let event = Default::default();
+        evaluate_and_compare(
+            expr,
+            &event,
+            ExpressionValue::Number("1.5".parse().unwrap()),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_sign() {
+        let event = Default::default();
+        for (input, expected) in [("-1.5", -1), ("0", 0), ("0.001", 1)] {
+            let expr = Expression::Function(Function::Sign(decimal(input)));
+            evaluate_and_compare(expr, &event, ExpressionValue::Number(expected.into()));
+        }
+    }
+
+    #[test]
+    fn test_evaluate_sqrt() {
+        let expr = Expression::Function(Function::Sqrt(decimal("16")));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(4.into()));
+    }
+
+    #[test]
+    fn test_evaluate_sqrt_negative() {
+        let expr = Expression::Function(Function::Sqrt(decimal("-1")));
+        let event = Default::default();
+        assert!(matches!(
+            expr.evaluate(&event),
+            Err(ExpressionError::Undefined(name, _)) if name == "sqrt"
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_ln_zero() {
+        let expr = Expression::Function(Function::Ln(decimal("0")));
+        let event = Default::default();
+        assert!(matches!(
+            expr.evaluate(&event),
+            Err(ExpressionError::Undefined(name, _)) if name == "ln"
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_log10() {
+        let expr = Expression::Function(Function::Log10(decimal("100")));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(2.into()));
+    }
+
+    #[test]
+    fn test_evaluate_exp() {
+        let expr = Expression::Function(Function::Exp(decimal("0")));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(1.into()));
+    }
+
+    #[test]
+    fn test_evaluate_math_function_null() {
+        let expr = Expression::Function(Function::Sqrt(Box::new(Expression::Null)));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Null);
+    }
+
+    #[test]
+    fn test_evaluate_clamp() {
+        let event = Default::default();
+        for (input, expected) in [("-5", "0"), ("50", "50"), ("150", "100")] {
+            let expr = Expression::Function(Function::Clamp(
+                decimal(input),
+                decimal("0"),
+                decimal("100"),
+            ));
+            evaluate_and_compare(
+                expr,
+                &event,
+                ExpressionValue::Number(expected.parse().unwrap()),
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_clamp_null_bound() {
+        let expr = Expression::Function(Function::Clamp(
+            decimal("150"),
+            Box::new(Expression::Null),
+            decimal("100"),
+        ));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(100.into()));
+    }
+
+    #[test]
+    fn test_evaluate_trunc() {
+        let event = Default::default();
+        let expr = Expression::Function(Function::Trunc(decimal("-12.78"), None));
+        evaluate_and_compare(expr, &event, ExpressionValue::Number((-12).into()));
+
+        let expr = Expression::Function(Function::Trunc(decimal("12.78"), Some(decimal("1"))));
+        evaluate_and_compare(
+            expr,
+            &event,
+            ExpressionValue::Number("12.7".parse().unwrap()),
+        );
+    }
+
+    fn string(s: &str) -> Box<Expression> {
+        Box::new(Expression::String(s.into()))
+    }
+
+    #[test]
+    fn test_evaluate_upper_lower() {
+        let event = Default::default();
+        let expr = Expression::Function(Function::Upper(string("straße")));
+        evaluate_and_compare(expr, &event, ExpressionValue::String("STRASSE".into()));
+
+        let expr = Expression::Function(Function::Lower(string("ÉTÉ")));
+        evaluate_and_compare(expr, &event, ExpressionValue::String("été".into()));
+    }
+
+    #[test]
+    fn test_evaluate_trim() {
+        let expr = Expression::Function(Function::Trim(string("  a b\t")));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::String("a b".into()));
+    }
+
+    #[test]
+    fn test_evaluate_length_counts_characters() {
+        let expr = Expression::Function(Function::Length(string("héllo👋")));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(6.into()));
+    }
+
+    #[test]
+    fn test_evaluate_length_of_number() {
+        let expr = Expression::Function(Function::Length(decimal("12.5")));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(4.into()));
+    }
+
+    #[test]
+    fn test_evaluate_substring() {
+        let event = Default::default();
+        let cases = [
+            ("2", None, "éllo"),
+            ("2", Some("3"), "éll"),
+            ("0", Some("3"), "hé"),
+            ("10", Some("3"), ""),
+        ];
+        for (start, length, expected) in cases {
+            let expr = Expression::Function(Function::Substring(
+                string("héllo"),
+                decimal(start),
+                length.map(decimal),
+            ));
+            evaluate_and_compare(expr, &event, ExpressionValue::String(expected.into()));
+        }
+    }
+
+    #[test]
+    fn test_evaluate_substring_negative_length() {
+        let expr = Expression::Function(Function::Substring(
+            string("hello"),
+            decimal("1"),
+            Some(decimal("-1")),
+        ));
+        let event = Default::default();
+        assert!(matches!(
+            expr.evaluate(&event),
+            Err(ExpressionError::Undefined(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_replace() {
+        let expr =
+            Expression::Function(Function::Replace(string("a-b-c"), string("-"), string("_")));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::String("a_b_c".into()));
+    }
+
+    #[test]
+    fn test_evaluate_split_part() {
+        let event = Default::default();
+        let cases = [("1", "eu"), ("3", "1a"), ("-1", "1a"), ("4", "")];
+        for (index, expected) in cases {
+            let expr = Expression::Function(Function::SplitPart(
+                string("eu::west::1a"),
+                string("::"),
+                decimal(index),
+            ));
+            evaluate_and_compare(expr, &event, ExpressionValue::String(expected.into()));
+        }
+    }
+
+    #[test]
+    fn test_evaluate_split_part_zero_index() {
+        let expr = Expression::Function(Function::SplitPart(
+            string("a,b"),
+            string(","),
+            decimal("0"),
+        ));
+        let event = Default::default();
+        assert!(matches!(
+            expr.evaluate(&event),
+            Err(ExpressionError::Undefined(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_concat_ws() {
+        let expr = Expression::Function(Function::ConcatWs(
+            string("|"),
+            vec![
+                Expression::String("a".into()),
+                Expression::Null,
+                Expression::Decimal(1.into()),
+            ],
+        ));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::String("a|1".into()));
+    }
+
+    #[test]
+    fn test_evaluate_string_function_null() {
+        let expr = Expression::Function(Function::Upper(Box::new(Expression::Null)));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Null);
+    }
+
+    fn pattern(s: &str) -> Pattern {
+        Pattern::new(s).unwrap()
+    }
+
+    #[test]
+    fn test_evaluate_matches() {
+        let event = Default::default();
+        let expr = Expression::Function(Function::Matches(string("api_call"), pattern("^api_")));
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+
+        let expr = Expression::Function(Function::Matches(string("web_call"), pattern("^api_")));
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(false));
+    }
+
+    #[test]
+    fn test_evaluate_matches_is_linear() {
+        // Would take exponential time with a backtracking engine
+        let input = format!("{}!", "a".repeat(10_000));
+        let expr = Expression::Function(Function::Matches(string(&input), pattern("^(a+)+$")));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(false));
+    }
+
+    #[test]
+    fn test_evaluate_regex_extract() {
+        let event = Default::default();
+        let expr = Expression::Function(Function::RegexExtract(
+            string("region=eu-west-1"),
+            pattern("region=([a-z]+)-([a-z]+)"),
+            decimal("2"),
+        ));
+        evaluate_and_compare(expr, &event, ExpressionValue::String("west".into()));
+
+        let expr = Expression::Function(Function::RegexExtract(
+            string("nothing"),
+            pattern("region=([a-z]+)"),
+            decimal("1"),
+        ));
+        evaluate_and_compare(expr, &event, ExpressionValue::Null);
+    }
+
+    #[test]
+    fn test_evaluate_regex_extract_unknown_group() {
+        let expr = Expression::Function(Function::RegexExtract(
+            string("a"),
+            pattern("(a)"),
+            decimal("2"),
+        ));
+        let event = Default::default();
+        assert!(matches!(
+            expr.evaluate(&event),
+            Err(ExpressionError::Undefined(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_regex_replace() {
+        let expr = Expression::Function(Function::RegexReplace(
+            string("user-123-abc-45"),
+            pattern("[0-9]+"),
+            string("#"),
+        ));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::String("user-#-abc-#".into()));
+    }
+
+    #[test]
+    fn test_evaluate_regex_replace_with_groups() {
+        let expr = Expression::Function(Function::RegexReplace(
+            string("2024-05"),
+            pattern("(?P<year>[0-9]{4})-(?P<month>[0-9]{2})"),
+            string("${month}/${year}"),
+        ));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::String("05/2024".into()));
+    }
+
+    fn timestamp_event(timestamp: &str) -> Event {
+        let datetime = timestamp.parse::<DateTime<Utc>>().unwrap();
+        Event {
+            timestamp: PropertyValue::Number(datetime::to_timestamp(&datetime)),
+            ..Default::default()
+        }
+    }
+
+    fn timestamp() -> Box<Expression> {
+        Box::new(Expression::EventAttribute(EventAttribute::Timestamp))
+    }
+
+    #[test]
+    fn test_evaluate_calendar_functions() {
+        let event = timestamp_event("2024-06-01T22:30:00Z");
+        let cases = [
+            (Function::Year(timestamp()), 2024),
+            (Function::Month(timestamp()), 6),
+            (Function::Day(timestamp()), 1),
+            (Function::Hour(timestamp()), 22),
+            (Function::DayOfWeek(timestamp()), 6),
+        ];
+        for (function, expected) in cases {
+            evaluate_and_compare(
+                Expression::Function(function),
+                &event,
+                ExpressionValue::Number(expected.into()),
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_calendar_function_milliseconds() {
+        let expr = Expression::Function(Function::Year(decimal("1717280999000")));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(2024.into()));
+    }
+
+    #[test]
+    fn test_evaluate_date_trunc() {
+        let event = timestamp_event("2024-06-01T22:30:00Z");
+        let expr = Expression::Function(Function::DateTrunc(datetime::DateUnit::Day, timestamp()));
+        evaluate_and_compare(expr, &event, ExpressionValue::Number(1717200000.into()));
+    }
+
+    #[test]
+    fn test_evaluate_date_trunc_datetime() {
+        let expr = Expression::Function(Function::DateTrunc(
+            datetime::DateUnit::Day,
+            parse_datetime("2024-06-01T10:00:00Z"),
+        ));
+        evaluate_and_compare(expr, &Event::default(), utc("2024-06-01T00:00:00Z"));
+
+        let expr = binop(
+            Box::new(Expression::Function(Function::DateTrunc(
+                datetime::DateUnit::Day,
+                parse_datetime("2024-06-01T10:00:00Z"),
+            ))),
+            Operation::Equal,
+            parse_datetime("2024-06-01"),
+        );
+        evaluate_and_compare(expr, &Event::default(), ExpressionValue::Boolean(true));
+    }
+
+    #[test]
+    fn test_evaluate_date_trunc_milliseconds() {
+        // 2024-06-01T22:29:59.500Z
+        let expr = Expression::Function(Function::DateTrunc(
+            datetime::DateUnit::Day,
+            decimal("1717280999500"),
+        ));
+        let event = Default::default();
+        evaluate_and_compare(
+            expr,
+            &event,
+            ExpressionValue::Number(1717200000000u64.into()),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_time_between() {
+        let event = timestamp_event("2024-06-01T22:30:00Z");
+        let expr = Expression::Function(Function::TimeBetween(
+            timestamp(),
+            datetime::parse_time_of_day("22:00").unwrap(),
+            datetime::parse_time_of_day("06:00").unwrap(),
+        ));
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+    }
+
+    #[test]
+    fn test_evaluate_calendar_function_null() {
+        let expr = Expression::Function(Function::Hour(Box::new(Expression::Null)));
+        let event = Default::default();
+        evaluate_and_compare(expr, &event, ExpressionValue::Null);
+    }
+
+    #[test]
+    fn test_evaluate_calendar_function_invalid_timestamp() {
+        let expr = Expression::Function(Function::Hour(decimal("1e30")));
+        let event = Default::default();
+        assert!(matches!(
+            expr.evaluate(&event),
+            Err(ExpressionError::InvalidTimestamp(_))
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_calendar_functions_in_timezone() {
+        let event = timestamp_event("2024-06-01T02:30:00Z");
+        let context = EvaluationContext {
+            timezone: Tz::America__New_York,
+            ..Default::default()
+        };
+        let cases = [
+            (Function::Day(timestamp()), 31),
+            (Function::Hour(timestamp()), 22),
+            (Function::DayOfWeek(timestamp()), 5),
+        ];
+        for (function, expected) in cases {
+            let expr = Expression::Function(function);
+            assert_eq!(
+                expr.evaluate_with_context(&event, &context).unwrap(),
+                ExpressionValue::Number(expected.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_hour_follows_dst() {
+        let context = EvaluationContext {
+            timezone: Tz::Europe__Paris,
+            ..Default::default()
+        };
+        let expr = Expression::Function(Function::Hour(timestamp()));
+        for (timestamp, expected) in [("2024-03-30T12:00:00Z", 13), ("2024-03-31T12:00:00Z", 14)] {
+            let event = timestamp_event(timestamp);
+            assert_eq!(
+                expr.evaluate_with_context(&event, &context).unwrap(),
+                ExpressionValue::Number(expected.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_date_trunc_in_timezone() {
+        let event = timestamp_event("2024-06-01T02:30:00Z");
+        let context = EvaluationContext {
+            timezone: Tz::America__New_York,
+            ..Default::default()
+        };
+        let expr = Expression::Function(Function::DateTrunc(datetime::DateUnit::Day, timestamp()));
+        // 2024-05-31T04:00:00Z
+        assert_eq!(
+            expr.evaluate_with_context(&event, &context).unwrap(),
+            ExpressionValue::Number(1717128000.into())
+        );
+    }
+
+    fn utc(s: &str) -> ExpressionValue {
+        ExpressionValue::DateTime(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_evaluate_parse_datetime() {
+        let expr = Expression::Function(Function::ParseDateTime(
+            Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                "started_at".into(),
+                vec![],
+            ))),
+            None,
+        ));
+        let mut event = Event::default();
+        event
+            .properties
+            .insert("started_at".into(), "2024-06-01T12:00:00+02:00".into());
+        evaluate_and_compare(expr, &event, utc("2024-06-01T10:00:00Z"));
+    }
+
+    #[test]
+    fn test_evaluate_parse_datetime_with_format_in_timezone() {
+        let expr = Expression::Function(Function::ParseDateTime(
+            string("01/06/2024 12:00"),
+            Some(string("%d/%m/%Y %H:%M")),
+        ));
+        let context = EvaluationContext {
+            timezone: Tz::Europe__Paris,
+            ..Default::default()
+        };
+        assert_eq!(
+            expr.evaluate_with_context(&Event::default(), &context)
+                .unwrap(),
+            utc("2024-06-01T10:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_evaluate_parse_datetime_null() {
+        let expr = Expression::Function(Function::ParseDateTime(Box::new(Expression::Null), None));
+        evaluate_and_compare(expr, &Event::default(), ExpressionValue::Null);
+    }
+
+    #[test]
+    fn test_evaluate_format_datetime() {
+        let expr = Expression::Function(Function::FormatDateTime(
+            timestamp(),
+            string("%Y-%m-%d %H:%M"),
+        ));
+        let event = timestamp_event("2024-06-01T22:30:00Z");
+        let context = EvaluationContext {
+            timezone: Tz::Europe__Paris,
+            ..Default::default()
+        };
+        assert_eq!(
+            expr.evaluate_with_context(&event, &context).unwrap(),
+            ExpressionValue::String("2024-06-02 00:30".into())
+        );
+    }
+
+    #[test]
+    fn test_evaluate_to_epoch() {
+        let expr = Expression::Function(Function::ToEpoch(Box::new(Expression::Function(
+            Function::ParseDateTime(string("2024-06-01T00:00:00.5Z"), None),
+        ))));
+        evaluate_and_compare(
+            expr,
+            &Event::default(),
+            ExpressionValue::Number("1717200000.5".parse().unwrap()),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_to_epoch_milliseconds() {
+        let expr = Expression::Function(Function::ToEpoch(decimal("1717200000500")));
+        evaluate_and_compare(
+            expr,
+            &Event::default(),
+            ExpressionValue::Number("1717200000.5".parse().unwrap()),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_calendar_function_of_datetime() {
+        let expr = Expression::Function(Function::Month(Box::new(Expression::Function(
+            Function::ParseDateTime(string("2024-06-01T00:00:00Z"), None),
+        ))));
+        evaluate_and_compare(expr, &Event::default(), ExpressionValue::Number(6.into()));
+    }
+
+    #[test]
+    fn test_evaluate_calendar_function_of_string() {
+        let expr = Expression::Function(Function::Month(string("2024-06-01")));
+        assert!(matches!(
+            expr.evaluate(&Event::default()),
+            Err(ExpressionError::ExpectedTimestamp)
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_compare_datetimes() {
+        let parse = |s| {
+            Box::new(Expression::Function(Function::ParseDateTime(
+                string(s),
+                None,
+            )))
+        };
+        let expr = Expression::BinOp {
+            lhs: parse("2024-06-01T12:00:00+02:00"),
+            op: Operation::LessThan,
+            rhs: parse("2024-06-01T11:00:00Z"),
+        };
+        evaluate_and_compare(expr, &Event::default(), ExpressionValue::Boolean(true));
+    }
+
+    #[test]
+    fn test_evaluate_compare_timestamp_with_datetime() {
+        let event = timestamp_event("2024-06-01T12:00:00Z");
+        let expr = binop(
+            timestamp(),
+            Operation::GreaterThan,
+            parse_datetime("2024-01-01"),
+        );
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+
+        let expr = binop(
+            parse_datetime("2024-06-01T12:00:00Z"),
+            Operation::Equal,
+            timestamp(),
+        );
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+
+        // Timestamps in milliseconds
+        let expr = binop(
+            decimal("1717243200000"),
+            Operation::LessThanOrEqual,
+            parse_datetime("2024-06-01T12:00:00Z"),
+        );
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+    }
+
+    #[test]
+    fn test_evaluate_timestamp_arithmetic_and_comparison_agree() {
+        let event = timestamp_event("2024-06-01T12:00:00Z");
+        let start = || parse_datetime("2024-06-01T10:00:00Z");
+
+        // event.timestamp - start > 1h
+        let expr = binop(
+            Box::new(binop(timestamp(), Operation::Subtract, start())),
+            Operation::GreaterThan,
+            Box::new(Expression::Duration(3600.into())),
+        );
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+
+        let expr = binop(timestamp(), Operation::GreaterThan, start());
+        evaluate_and_compare(expr, &event, ExpressionValue::Boolean(true));
+
+        // Strings are neither timestamps in arithmetic nor in comparisons
+        let expr = binop(string("2024-06-01"), Operation::Subtract, start());
+        assert!(matches!(
+            expr.evaluate(&event),
+            Err(ExpressionError::ExpectedTimestamp)
+        ));
+        let expr = binop(string("2024-06-01"), Operation::GreaterThan, start());
+        assert!(matches!(
+            expr.evaluate(&event),
+            Err(ExpressionError::IncomparableTypes("string", "datetime"))
+        ));
+    }
+
+    #[test]
+    fn test_display_datetime() {
+        assert_eq!(
+            utc("2024-06-01T12:00:00Z").to_string(),
+            "2024-06-01T12:00:00Z"
+        );
+        assert_eq!(
+            utc("2024-06-01T12:00:00.25Z").to_string(),
+            "2024-06-01T12:00:00.250Z"
+        );
+    }
+
+    fn duration(seconds: &str) -> ExpressionValue {
+        ExpressionValue::Duration(seconds.parse().unwrap())
+    }
+
+    fn parse_datetime(s: &str) -> Box<Expression> {
+        Box::new(Expression::Function(Function::ParseDateTime(
+            string(s),
+            None,
+        )))
+    }
+
+    #[test]
+    fn test_evaluate_subtract_datetimes() {
+        let expr = Expression::BinOp {
+            lhs: parse_datetime("2024-06-01T13:30:00Z"),
+            op: Operation::Subtract,
+            rhs: parse_datetime("2024-06-01T12:00:00Z"),
+        };
+        evaluate_and_compare(expr, &Event::default(), duration("5400"));
+    }
+
+    #[test]
+    fn test_evaluate_subtract_timestamp_from_datetime() {
+        let expr = Expression::BinOp {
+            lhs: parse_datetime("2024-06-01T13:00:00Z"),
+            op: Operation::Subtract,
+            rhs: timestamp(),
+        };
+        let event = timestamp_event("2024-06-01T12:00:00Z");
+        evaluate_and_compare(expr, &event, duration("3600"));
+    }
+
+    #[test]
+    fn test_evaluate_add_duration() {
+        let expr = Expression::BinOp {
+            lhs: timestamp(),
+            op: Operation::Add,
+            rhs: Box::new(Expression::Duration(90.into())),
+        };
+        let event = timestamp_event("2024-06-01T12:00:00Z");
+        evaluate_and_compare(expr, &event, utc("2024-06-01T12:01:30Z"));
+
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::Duration(90.into())),
+            op: Operation::Add,
+            rhs: parse_datetime("2024-06-01T12:00:00Z"),
+        };
+        evaluate_and_compare(expr, &event, utc("2024-06-01T12:01:30Z"));
+    }
+
+    #[test]
+    fn test_evaluate_subtract_duration() {
+        let expr = Expression::BinOp {
+            lhs: parse_datetime("2024-06-01T12:00:00Z"),
+            op: Operation::Subtract,
+            rhs: Box::new(Expression::Duration(86400.into())),
+        };
+        evaluate_and_compare(expr, &Event::default(), utc("2024-05-31T12:00:00Z"));
+    }
+
+    #[test]
+    fn test_evaluate_duration_arithmetic() {
+        let hour = || Box::new(Expression::Duration(3600.into()));
+        let cases = [
+            (hour(), Operation::Add, hour(), duration("7200")),
+            (hour(), Operation::Subtract, hour(), duration("0")),
+            (
+                hour(),
+                Operation::Multiply,
+                decimal("1.5"),
+                duration("5400"),
+            ),
+            (decimal("2"), Operation::Multiply, hour(), duration("7200")),
+            (hour(), Operation::Divide, decimal("4"), duration("900")),
+            (
+                hour(),
+                Operation::Divide,
+                Box::new(Expression::Duration(900.into())),
+                ExpressionValue::Number(4.into()),
+            ),
+            (
+                hour(),
+                Operation::GreaterThan,
+                Box::new(Expression::Duration(900.into())),
+                ExpressionValue::Boolean(true),
+            ),
+        ];
+        for (lhs, op, rhs, expected) in cases {
+            evaluate_and_compare(
+                Expression::BinOp { lhs, op, rhs },
+                &Event::default(),
+                expected,
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_add_datetimes_fails() {
+        let expr = Expression::BinOp {
+            lhs: parse_datetime("2024-06-01T12:00:00Z"),
+            op: Operation::Add,
+            rhs: parse_datetime("2024-06-01T12:00:00Z"),
+        };
+        assert!(matches!(
+            expr.evaluate(&Event::default()),
+            Err(ExpressionError::ExpectedDecimal)
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_duration_conversions() {
+        let minutes = || Box::new(Expression::Duration(5400.into()));
+        let cases = [
+            (Function::ToHours(minutes()), "1.5"),
+            (Function::ToMinutes(minutes()), "90"),
+            (Function::ToSeconds(minutes()), "5400"),
+        ];
+        for (function, expected) in cases {
+            evaluate_and_compare(
+                Expression::Function(function),
+                &Event::default(),
+                ExpressionValue::Number(expected.parse().unwrap()),
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_session_length_in_hours() {
+        let mut event = Event::default();
+        event
+            .properties
+            .insert("started_at".into(), "2024-06-01T12:00:00Z".into());
+        event
+            .properties
+            .insert("ended_at".into(), "2024-06-01T14:15:00Z".into());
+        let property = |name: &str| {
+            Box::new(Expression::Function(Function::ParseDateTime(
+                Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                    name.into(),
+                    vec![],
+                ))),
+                None,
+            )))
+        };
+        let expr = Expression::Function(Function::ToHours(Box::new(Expression::BinOp {
+            lhs: property("ended_at"),
+            op: Operation::Subtract,
+            rhs: property("started_at"),
+        })));
+        evaluate_and_compare(
+            expr,
+            &event,
+            ExpressionValue::Number("2.25".parse().unwrap()),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_duration_conversion_requires_duration() {
+        let expr = Expression::Function(Function::ToHours(decimal("3600")));
+        assert!(matches!(
+            expr.evaluate(&Event::default()),
+            Err(ExpressionError::ExpectedDuration)
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_negative_duration() {
+        let expr = Expression::UnaryMinus(Box::new(Expression::Duration(60.into())));
+        evaluate_and_compare(expr, &Event::default(), duration("-60"));
+    }
+
+    #[test]
+    fn test_display_duration() {
+        assert_eq!(duration("5400.50").to_string(), "5400.5s");
+    }
+
+    fn property_event(name: &str, value: &str) -> Event {
+        let mut event = Event::default();
+        event.properties.insert(name.into(), value.into());
+        event
+    }
+
+    fn property(name: &str) -> Box<Expression> {
+        Box::new(Expression::EventAttribute(EventAttribute::Properties(
+            name.into(),
+            vec![],
+        )))
+    }
+
+    #[test]
+    fn test_evaluate_strict_types_keeps_strings() {
+        let event = property_event("id", "007");
+        let expr = Expression::Function(Function::Concat(vec![
+            Expression::String("region-".into()),
+            Expression::EventAttribute(EventAttribute::Properties("id".into(), vec![])),
+        ]));
+        evaluate_and_compare(
+            expr.clone(),
+            &event,
+            ExpressionValue::String("region-7".into()),
+        );
+
+        let context = EvaluationContext {
+            strict_types: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            expr.evaluate_with_context(&event, &context).unwrap(),
+            ExpressionValue::String("region-007".into())
+        );
+    }
+
+    #[test]
+    fn test_evaluate_strict_types_requires_conversion() {
+        let event = property_event("quantity", "12");
+        let context = EvaluationContext {
+            strict_types: true,
+            ..Default::default()
+        };
+        let expr = Expression::BinOp {
+            lhs: property("quantity"),
+            op: Operation::Multiply,
+            rhs: decimal("2"),
+        };
+        assert!(matches!(
+            expr.evaluate_with_context(&event, &context),
+            Err(ExpressionError::ExpectedDecimal)
+        ));
+
+        let expr = Expression::BinOp {
+            lhs: Box::new(Expression::Function(Function::ToNumber(property(
+                "quantity",
+            )))),
+            op: Operation::Multiply,
+            rhs: decimal("2"),
+        };
+        assert_eq!(
+            expr.evaluate_with_context(&event, &context).unwrap(),
+            ExpressionValue::Number(24.into())
+        );
+    }
+
+    #[test]
+    fn test_evaluate_to_number() {
+        let cases = [
+            (string(" 1e5 "), "100000"),
+            (string("-0.25"), "-0.25"),
+            (decimal("3.5"), "3.5"),
+            (Box::new(Expression::Boolean(true)), "1"),
+        ];
+        for (arg, expected) in cases {
+            evaluate_and_compare(
+                Expression::Function(Function::ToNumber(arg)),
+                &Event::default(),
+                ExpressionValue::Number(expected.parse().unwrap()),
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_to_number_invalid() {
+        let expr = Expression::Function(Function::ToNumber(string("abc")));
+        assert!(matches!(
+            expr.evaluate(&Event::default()),
+            Err(ExpressionError::InvalidConversion(value, "number")) if value == "abc"
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_to_integer() {
+        let cases = [
+            (string("12.7"), "12"),
+            (string("-12.7"), "-12"),
+            (string("007"), "7"),
+            (decimal("3"), "3"),
+        ];
+        for (arg, expected) in cases {
+            evaluate_and_compare(
+                Expression::Function(Function::ToInteger(arg)),
+                &Event::default(),
+                ExpressionValue::Number(expected.parse().unwrap()),
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_to_string() {
+        let cases = [
+            (decimal("1.50"), "1.50"),
+            (Box::new(Expression::Boolean(false)), "false"),
+            (string("007"), "007"),
+        ];
+        for (arg, expected) in cases {
+            evaluate_and_compare(
+                Expression::Function(Function::ToString(arg)),
+                &Event::default(),
+                ExpressionValue::String(expected.into()),
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_conversions_of_null() {
+        for function in [
+            Function::ToNumber(Box::new(Expression::Null)),
+            Function::ToInteger(Box::new(Expression::Null)),
+            Function::ToString(Box::new(Expression::Null)),
+        ] {
+            evaluate_and_compare(
+                Expression::Function(function),
+                &Event::default(),
+                ExpressionValue::Null,
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_json_property_values() {
+        let mut event = Event::default();
+        event.properties.insert("enabled".into(), true.into());
+        event
+            .properties
+            .insert("region".into(), PropertyValue::Null);
+        event.properties.insert(
+            "items".into(),
+            PropertyValue::Array(vec![1u64.into(), "a".into()]),
+        );
+
+        evaluate_and_compare(*property("enabled"), &event, ExpressionValue::Boolean(true));
+        evaluate_and_compare(*property("region"), &event, ExpressionValue::Null);
+        evaluate_and_compare(
+            *property("items"),
+            &event,
+            ExpressionValue::Array(vec![
+                ExpressionValue::Number(1.into()),
+                ExpressionValue::String("a".into()),
+            ]),
+        );
+        evaluate_and_compare(
+            Expression::Function(Function::ToString(property("items"))),
+            &event,
+            ExpressionValue::String(r#"[1,"a"]"#.into()),
+        );
+    }
+
+    fn nested_event() -> Event {
+        let json = r#"{
+            "code": "compute",
+            "timestamp": 1717243200,
+            "properties": {
+                "my-key": "a",
+                "usage": {"cpu": {"seconds": 30}},
+                "items": [{"qty": 2, "price": 1.5}, {"qty": 1}]
+            }
+        }"#;
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn path(name: &str, path: Vec<PathSegment>) -> Expression {
+        Expression::EventAttribute(EventAttribute::Properties(name.into(), path))
+    }
+
+    #[test]
+    fn test_evaluate_nested_properties() {
+        let event = nested_event();
+        let key = |k: &str| PathSegment::Key(k.into());
+        let cases = [
+            (path("my-key", vec![]), ExpressionValue::String("a".into())),
+            (
+                path("usage", vec![key("cpu"), key("seconds")]),
+                ExpressionValue::Number(30.into()),
+            ),
+            (
+                path("items", vec![PathSegment::Index(0), key("price")]),
+                ExpressionValue::Number("1.5".parse().unwrap()),
+            ),
+            (
+                path("items", vec![PathSegment::Index(1), key("qty")]),
+                ExpressionValue::Number(1.into()),
+            ),
+        ];
+        for (expr, expected) in cases {
+            evaluate_and_compare(expr, &event, expected);
+        }
+    }
+
+    #[test]
+    fn test_evaluate_missing_nested_properties() {
+        let event = nested_event();
+        let key = |k: &str| PathSegment::Key(k.into());
+        let cases = [
+            path("usage", vec![key("memory"), key("bytes")]),
+            path("items", vec![PathSegment::Index(2), key("qty")]),
+            path("items", vec![PathSegment::Index(1), key("price")]),
+            path("items", vec![key("qty")]),
+            path("usage", vec![PathSegment::Index(0)]),
+            path("my-key", vec![key("a")]),
+        ];
+        for expr in cases {
+            evaluate_and_compare(expr, &event, ExpressionValue::Null);
+        }
+    }
+
+    fn items() -> Box<Expression> {
+        Box::new(path("items", vec![]))
+    }
+
+    fn function(f: Function) -> Box<Expression> {
+        Box::new(Expression::Function(f))
+    }
+
+    /// `x -> body`
+    fn lambda(body: Expression) -> Lambda {
+        Lambda {
+            parameter: "x".into(),
+            body: Box::new(body),
+        }
+    }
+
+    /// `x.key`
+    fn x(key: &str) -> Box<Expression> {
+        Box::new(Expression::Variable(
+            "x".into(),
+            vec![PathSegment::Key(key.into())],
+        ))
+    }
+
+    fn quantities() -> Box<Expression> {
+        function(Function::Map(items(), lambda(*x("qty"))))
+    }
+
+    #[test]
+    fn test_evaluate_map() {
+        let amounts = Function::Map(
+            items(),
+            lambda(Expression::BinOp {
+                lhs: x("qty"),
+                op: Operation::Multiply,
+                rhs: x("price"),
+            }),
+        );
+        evaluate_and_compare(
+            Expression::Function(amounts),
+            &nested_event(),
+            ExpressionValue::Array(vec![
+                ExpressionValue::Number("3.0".parse().unwrap()),
+                ExpressionValue::Null,
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_filter() {
+        let expr = Function::Count(function(Function::Filter(
+            items(),
+            lambda(Expression::BinOp {
+                lhs: x("qty"),
+                op: Operation::GreaterThan,
+                rhs: decimal("1"),
+            }),
+        )));
+        evaluate_and_compare(
+            Expression::Function(expr),
+            &nested_event(),
+            ExpressionValue::Number(1.into()),
+        );
+
+        // Elements for which the predicate is null are left out
+        let expr = Function::Count(function(Function::Filter(
+            items(),
+            lambda(Expression::BinOp {
+                lhs: x("price"),
+                op: Operation::GreaterThan,
+                rhs: decimal("0"),
+            }),
+        )));
+        evaluate_and_compare(
+            Expression::Function(expr),
+            &nested_event(),
+            ExpressionValue::Number(1.into()),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_nested_lambdas() {
+        // map(items, x -> sum(map(items, y -> y.qty)) - x.qty)
+        let total = function(Function::Sum(function(Function::Map(
+            items(),
+            Lambda {
+                parameter: "y".into(),
+                body: Box::new(Expression::Variable(
+                    "y".into(),
+                    vec![PathSegment::Key("qty".into())],
+                )),
+            },
+        ))));
+        let expr = Function::Map(
+            items(),
+            lambda(Expression::BinOp {
+                lhs: total,
+                op: Operation::Subtract,
+                rhs: x("qty"),
+            }),
+        );
+        evaluate_and_compare(
+            Expression::Function(expr),
+            &nested_event(),
+            ExpressionValue::Array(vec![
+                ExpressionValue::Number(1.into()),
+                ExpressionValue::Number(2.into()),
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_aggregate_functions() {
+        let prices = || function(Function::Map(items(), lambda(*x("price"))));
+        let cases = [
+            (
+                Function::Sum(quantities()),
+                ExpressionValue::Number(3.into()),
+            ),
+            (
+                Function::Count(quantities()),
+                ExpressionValue::Number(2.into()),
+            ),
+            (
+                Function::Avg(quantities()),
+                ExpressionValue::Number("1.5".parse().unwrap()),
+            ),
+            (
+                Function::Min(quantities()),
+                ExpressionValue::Number(1.into()),
+            ),
+            (
+                Function::Max(quantities()),
+                ExpressionValue::Number(2.into()),
+            ),
+            // Null elements are skipped
+            (
+                Function::Sum(prices()),
+                ExpressionValue::Number("1.5".parse().unwrap()),
+            ),
+            (
+                Function::Avg(prices()),
+                ExpressionValue::Number("1.5".parse().unwrap()),
+            ),
+            (Function::Count(prices()), ExpressionValue::Number(1.into())),
+        ];
+        for (f, expected) in cases {
+            evaluate_and_compare(Expression::Function(f), &nested_event(), expected);
+        }
+    }
+
+    #[test]
+    fn test_evaluate_aggregate_of_empty_array() {
+        let mut event = Event::default();
+        event
+            .properties
+            .insert("items".into(), PropertyValue::Array(vec![]));
+        let cases = [
+            (Function::Sum(items()), ExpressionValue::Number(0.into())),
+            (Function::Count(items()), ExpressionValue::Number(0.into())),
+            (Function::Avg(items()), ExpressionValue::Null),
+            (Function::Min(items()), ExpressionValue::Null),
+            (Function::Max(items()), ExpressionValue::Null),
+        ];
+        for (f, expected) in cases {
+            evaluate_and_compare(Expression::Function(f), &event, expected);
+        }
+    }
+
+    #[test]
+    fn test_evaluate_sum_of_durations() {
+        let mut event = Event::default();
+        event.properties.insert(
+            "items".into(),
+            PropertyValue::Array(vec![PropertyValue::Null, 1u64.into()]),
+        );
+        let durations = function(Function::Map(
+            items(),
+            lambda(Expression::BinOp {
+                lhs: Box::new(Expression::Variable("x".into(), vec![])),
+                op: Operation::Multiply,
+                rhs: Box::new(Expression::Duration(60.into())),
+            }),
+        ));
+        evaluate_and_compare(
+            Expression::Function(Function::Sum(durations)),
+            &event,
+            duration("60"),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_array_function_errors() {
+        let expr = Expression::Function(Function::Sum(function(Function::Map(
+            items(),
+            Lambda {
+                parameter: "x".into(),
+                body: Box::new(Expression::Variable("x".into(), vec![])),
+            },
+        ))));
+        assert!(matches!(
+            expr.evaluate(&nested_event()),
+            Err(ExpressionError::ExpectedDecimal)
+        ));
+
+        let expr = Expression::Function(Function::Count(Box::new(path("my-key", vec![]))));
+        assert!(matches!(
+            expr.evaluate(&nested_event()),
+            Err(ExpressionError::ExpectedArray)
+        ));
+
+        let expr = Expression::Function(Function::Max(items()));
+        assert!(matches!(
+            expr.evaluate(&nested_event()),
+            Err(ExpressionError::IncomparableTypes("object", "object"))
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_array_functions_of_null() {
+        let missing = || Box::new(path("missing", vec![]));
+        for f in [
+            Function::Sum(missing()),
+            Function::Count(missing()),
+            Function::Map(missing(), lambda(*x("qty"))),
+            Function::Contains(missing(), decimal("1")),
+            Function::Join(missing(), string(",")),
+        ] {
+            evaluate_and_compare(
+                Expression::Function(f),
+                &nested_event(),
+                ExpressionValue::Null,
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_contains() {
+        let cases = [
+            (Function::Contains(quantities(), decimal("2")), true),
+            (Function::Contains(quantities(), decimal("3")), false),
+            (Function::Contains(quantities(), string("a")), false),
+            (Function::Contains(string("compute"), string("put")), true),
+        ];
+        for (f, expected) in cases {
+            evaluate_and_compare(
+                Expression::Function(f),
+                &nested_event(),
+                ExpressionValue::Boolean(expected),
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_join() {
+        let prices = function(Function::Map(items(), lambda(*x("price"))));
+        evaluate_and_compare(
+            Expression::Function(Function::Join(quantities(), string("-"))),
+            &nested_event(),
+            ExpressionValue::String("2-1".into()),
+        );
+        evaluate_and_compare(
+            Expression::Function(Function::Join(prices, string("-"))),
+            &nested_event(),
+            ExpressionValue::String("1.5".into()),
+        );
+    }
+
+    #[test]
+    fn test_display_object() {
+        let expr = Function::ToString(Box::new(path("items", vec![PathSegment::Index(0)])));
+        evaluate_and_compare(
+            Expression::Function(expr),
+            &nested_event(),
+            ExpressionValue::String(r#"{"price":1.5,"qty":2}"#.into()),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_strict_types_in_arrays() {
+        let mut event = Event::default();
+        event
+            .properties
+            .insert("ids".into(), PropertyValue::Array(vec!["007".into()]));
+        let context = EvaluationContext {
+            strict_types: true,
+            ..Default::default()
+        };
+        let expr = Expression::EventAttribute(EventAttribute::Properties(
+            "ids".into(),
+            vec![PathSegment::Index(0)],
+        ));
+        assert_eq!(
+            expr.evaluate_with_context(&event, &context).unwrap(),
+            ExpressionValue::String("007".into())
+        );
+    }
+
+    fn array(values: Vec<Expression>) -> Box<Expression> {
+        Box::new(Expression::Array(values))
+    }
+
+    fn is_in(value: Box<Expression>, values: Vec<Expression>) -> Expression {
+        Expression::BinOp {
+            lhs: value,
+            op: Operation::In,
+            rhs: array(values),
+        }
+    }
+
+    #[test]
+    fn test_evaluate_array_literal() {
+        evaluate_and_compare(
+            *array(vec![*decimal("1"), *string("a"), Expression::Null]),
+            &Event::default(),
+            ExpressionValue::Array(vec![
+                ExpressionValue::Number(1.into()),
+                ExpressionValue::String("a".into()),
+                ExpressionValue::Null,
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_in() {
+        let regions = || vec![*string("eu"), *string("uk")];
+        evaluate_and_compare(
+            is_in(string("uk"), regions()),
+            &Event::default(),
+            ExpressionValue::Boolean(true),
+        );
+        evaluate_and_compare(
+            is_in(string("us"), regions()),
+            &Event::default(),
+            ExpressionValue::Boolean(false),
+        );
+        evaluate_and_compare(
+            is_in(decimal("1"), regions()),
+            &Event::default(),
+            ExpressionValue::Boolean(false),
+        );
+        evaluate_and_compare(
+            is_in(decimal("1"), vec![]),
+            &Event::default(),
+            ExpressionValue::Boolean(false),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_in_with_nulls() {
+        evaluate_and_compare(
+            is_in(Box::new(Expression::Null), vec![*decimal("1")]),
+            &Event::default(),
+            ExpressionValue::Null,
+        );
+        evaluate_and_compare(
+            is_in(decimal("2"), vec![*decimal("1"), Expression::Null]),
+            &Event::default(),
+            ExpressionValue::Null,
+        );
+        evaluate_and_compare(
+            is_in(decimal("1"), vec![*decimal("1"), Expression::Null]),
+            &Event::default(),
+            ExpressionValue::Boolean(true),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_in_agrees_with_equal() {
+        let event = Event::default();
+        let pairs = [
+            (string("1"), decimal("1")),
+            (decimal("1"), decimal("1.0")),
+            (string("eu"), string("uk")),
+            (Box::new(Expression::Boolean(true)), string("true")),
+            (array(vec![*decimal("1")]), array(vec![*decimal("1")])),
+        ];
+        for (x, y) in pairs {
+            let equal = binop(x.clone(), Operation::Equal, y.clone()).evaluate(&event);
+            let found = is_in(x, vec![*y]).evaluate(&event);
+            assert_eq!(found.unwrap(), equal.unwrap());
+        }
+    }
+
+    #[test]
+    fn test_evaluate_in_requires_array() {
+        let expr = Expression::BinOp {
+            lhs: string("u"),
+            op: Operation::In,
+            rhs: string("uk"),
+        };
+        assert!(matches!(
+            expr.evaluate(&Event::default()),
+            Err(ExpressionError::ExpectedArray)
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_in_array_property() {
+        evaluate_and_compare(
+            Expression::BinOp {
+                lhs: decimal("2"),
+                op: Operation::In,
+                rhs: quantities(),
+            },
+            &nested_event(),
+            ExpressionValue::Boolean(true),
+        );
+    }
+
+    fn rates() -> Box<Expression> {
+        Box::new(Expression::Map(vec![
+            ("eu".into(), *decimal("1.2")),
+            ("us".into(), *decimal("1.0")),
+        ]))
+    }
+
+    fn rate_of(region: Box<Expression>) -> Expression {
+        Expression::BinOp {
+            lhs: Box::new(Expression::Index(rates(), region)),
+            op: Operation::Fallback,
+            rhs: decimal("1"),
+        }
+    }
+
+    #[test]
+    fn test_evaluate_map_literal() {
+        let mut expected = HashMap::new();
+        expected.insert(
+            "eu".to_owned(),
+            ExpressionValue::Number("1.2".parse().unwrap()),
+        );
+        expected.insert(
+            "us".to_owned(),
+            ExpressionValue::Number("1.0".parse().unwrap()),
+        );
+        evaluate_and_compare(
+            *rates(),
+            &Event::default(),
+            ExpressionValue::Object(expected),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_map_lookup_with_fallback() {
+        let cases = [
+            (string("eu"), "1.2"),
+            (string("uk"), "1"),
+            (Box::new(Expression::Null), "1"),
+            (property("region"), "1"),
+        ];
+        for (region, expected) in cases {
+            evaluate_and_compare(
+                rate_of(region),
+                &Event::default(),
+                ExpressionValue::Number(expected.parse().unwrap()),
+            );
+        }
+        evaluate_and_compare(
+            rate_of(property("region")),
+            &property_event("region", "us"),
+            ExpressionValue::Number("1.0".parse().unwrap()),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_fallback_short_circuits() {
+        let expr = Expression::BinOp {
+            lhs: decimal("1"),
+            op: Operation::Fallback,
+            rhs: Box::new(Expression::BinOp {
+                lhs: decimal("1"),
+                op: Operation::Divide,
+                rhs: decimal("0"),
+            }),
+        };
+        evaluate_and_compare(expr, &Event::default(), ExpressionValue::Number(1.into()));
+    }
+
+    #[test]
+    fn test_evaluate_array_index() {
+        let values = || array(vec![*string("a"), *string("b")]);
+        let cases = [
+            (decimal("1"), ExpressionValue::String("b".into())),
+            (decimal("2"), ExpressionValue::Null),
+            (
+                Box::new(Expression::UnaryMinus(decimal("1"))),
+                ExpressionValue::Null,
+            ),
+        ];
+        for (index, expected) in cases {
+            evaluate_and_compare(
+                Expression::Index(values(), index),
+                &Event::default(),
+                expected,
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_index_errors() {
+        let expr = Expression::Index(decimal("1"), decimal("0"));
+        assert!(matches!(
+            expr.evaluate(&Event::default()),
+            Err(ExpressionError::NotIndexable("number"))
+        ));
+
+        let expr = Expression::Index(array(vec![]), string("a"));
+        assert!(matches!(
+            expr.evaluate(&Event::default()),
+            Err(ExpressionError::ExpectedDecimal)
+        ));
+    }
+
+    fn variable(name: &str) -> Box<Expression> {
+        Box::new(Expression::Variable(name.into(), vec![]))
+    }
+
+    #[test]
+    fn test_evaluate_let() {
+        // let gb = event.properties.bytes / 1024; round(gb * 0.02, 2)
+        let expr = Expression::Let {
+            name: "gb".into(),
+            value: Box::new(Expression::BinOp {
+                lhs: property("bytes"),
+                op: Operation::Divide,
+                rhs: decimal("1024"),
+            }),
+            body: Box::new(Expression::Function(Function::Round(
+                Box::new(Expression::BinOp {
+                    lhs: variable("gb"),
+                    op: Operation::Multiply,
+                    rhs: decimal("0.02"),
+                }),
+                Some(decimal("2")),
+            ))),
+        };
+        evaluate_and_compare(
+            expr,
+            &property_event("bytes", "51200"),
+            ExpressionValue::Number(1.into()),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_let_in_lambda() {
+        // map(items, x -> let total = x.qty * 2; total + x.qty)
+        let expr = Function::Map(
+            items(),
+            lambda(Expression::Let {
+                name: "total".into(),
+                value: Box::new(Expression::BinOp {
+                    lhs: x("qty"),
+                    op: Operation::Multiply,
+                    rhs: decimal("2"),
+                }),
+                body: Box::new(Expression::BinOp {
+                    lhs: variable("total"),
+                    op: Operation::Add,
+                    rhs: x("qty"),
+                }),
+            }),
+        );
+        evaluate_and_compare(
+            Expression::Function(expr),
+            &nested_event(),
+            ExpressionValue::Array(vec![
+                ExpressionValue::Number(6.into()),
+                ExpressionValue::Number(3.into()),
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_let_of_object() {
+        // let item = items[0]; item.qty
+        let expr = Expression::Let {
+            name: "item".into(),
+            value: Box::new(path("items", vec![PathSegment::Index(0)])),
+            body: Box::new(Expression::Variable(
+                "item".into(),
+                vec![PathSegment::Key("qty".into())],
+            )),
+        };
+        evaluate_and_compare(expr, &nested_event(), ExpressionValue::Number(2.into()));
+    }
+
+    #[test]
+    fn test_evaluate_let_error() {
+        let expr = Expression::Let {
+            name: "a".into(),
+            value: Box::new(Expression::BinOp {
+                lhs: decimal("1"),
+                op: Operation::Divide,
+                rhs: decimal("0"),
+            }),
+            body: decimal("1"),
+        };
+        assert!(matches!(
+            expr.evaluate(&Event::default()),
+            Err(ExpressionError::DivisionByZero)
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_unbound_variable() {
+        // Variables are resolved while parsing, but expressions can also be built
+        assert!(matches!(
+            variable("gb").evaluate(&Event::default()),
+            Err(ExpressionError::UnknownVariable(name)) if name == "gb"
+        ));
+    }
+
+    fn params(json: &str) -> HashMap<String, PropertyValue> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn parameter(name: &str) -> Box<Expression> {
+        Box::new(Expression::Parameter(name.into(), vec![]))
+    }
+
+    #[test]
+    fn test_evaluate_with_params() {
+        let expr = Expression::BinOp {
+            lhs: property("units"),
+            op: Operation::Multiply,
+            rhs: parameter("rate"),
+        };
+        assert_eq!(
+            expr.evaluate_with_params(&property_event("units", "10"), params(r#"{"rate": 0.5}"#))
+                .unwrap(),
+            ExpressionValue::Number("5.0".parse().unwrap())
+        );
+        assert_eq!(
+            expr.evaluate_with_params(&property_event("units", "10"), params(r#"{"rate": "2"}"#))
+                .unwrap(),
+            ExpressionValue::Number(20.into())
+        );
+    }
+
+    #[test]
+    fn test_evaluate_nested_parameter() {
+        let params = params(r#"{"rates": {"eu": 1.2}, "enabled": null}"#);
+        let rate = |region: &str| {
+            Expression::Parameter("rates".into(), vec![PathSegment::Key(region.into())])
+        };
+        assert_eq!(
+            rate("eu")
+                .evaluate_with_params(&Event::default(), params.clone())
+                .unwrap(),
+            ExpressionValue::Number("1.2".parse().unwrap())
+        );
+        // Values missing from a parameter that is given are null
+        assert_eq!(
+            rate("us")
+                .evaluate_with_params(&Event::default(), params.clone())
+                .unwrap(),
+            ExpressionValue::Null
+        );
+        assert_eq!(
+            parameter("enabled")
+                .evaluate_with_params(&Event::default(), params)
+                .unwrap(),
+            ExpressionValue::Null
+        );
+    }
+
+    #[test]
+    fn test_evaluate_missing_parameter() {
+        let expr = Expression::BinOp {
+            lhs: decimal("1"),
+            op: Operation::Add,
+            rhs: parameter("rate"),
+        };
+        assert!(matches!(
+            expr.evaluate(&Event::default()),
+            Err(ExpressionError::MissingParameter(name)) if name == "rate"
+        ));
+        assert!(matches!(
+            expr.evaluate_with_params(&Event::default(), params(r#"{"fee": 1}"#)),
+            Err(ExpressionError::MissingParameter(name)) if name == "rate"
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_parameter_in_strict_mode() {
+        let context = EvaluationContext {
+            strict_types: true,
+            params: params(r#"{"id": "007"}"#),
+            ..Default::default()
+        };
+        assert_eq!(
+            parameter("id")
+                .evaluate_with_context(&Event::default(), &context)
+                .unwrap(),
+            ExpressionValue::String("007".into())
+        );
+    }
+
+    #[test]
+    fn test_evaluate_user_function() {
+        let mut functions = FunctionRegistry::new();
+        functions
+            .register("double", &[ArgumentType::Number], |args| {
+                Ok(ExpressionValue::Number(args[0].to_decimal()? * 2))
+            })
+            .unwrap();
+        let double = |expr: Box<Expression>| {
+            Expression::Function(Function::User(
+                functions.get("double").unwrap().clone(),
+                vec![*expr],
+            ))
+        };
+        let event = Event {
+            properties: HashMap::from([
+                ("value".into(), PropertyValue::String("1.5".into())),
+                ("code".into(), PropertyValue::String("abc".into())),
+            ]),
+            ..Default::default()
+        };
+
+        evaluate_and_compare(
+            double(property("value")),
+            &event,
+            ExpressionValue::Number(3.into()),
+        );
+        evaluate_and_compare(double(property("missing")), &event, ExpressionValue::Null);
+        assert!(matches!(
+            double(property("code")).evaluate(&event),
+            Err(ExpressionError::ExpectedDecimal)
+        ));
+    }
+
+    /// `[[0, 100, 0.5], [100, null, 0.3, 10]]`
+    fn pricing_tiers() -> Box<Expression> {
+        array(vec![
+            *array(vec![*decimal("0"), *decimal("100"), *decimal("0.5")]),
+            *array(vec![
+                *decimal("100"),
+                Expression::Null,
+                *decimal("0.3"),
+                *decimal("10"),
+            ]),
+        ])
+    }
+
+    #[test]
+    fn test_evaluate_graduated() {
+        let expr = Function::Graduated(decimal("150"), pricing_tiers());
+        evaluate_and_compare(
+            Expression::Function(expr),
+            &Default::default(),
+            ExpressionValue::Number("75".parse().unwrap()),
+        );
+
+        let expr = Function::Graduated(property("missing"), pricing_tiers());
+        evaluate_and_compare(
+            Expression::Function(expr),
+            &Default::default(),
+            ExpressionValue::Null,
+        );
+    }
+
+    #[test]
+    fn test_evaluate_volume() {
+        let expr = Function::Volume(decimal("150"), pricing_tiers());
+        evaluate_and_compare(
+            Expression::Function(expr),
+            &Default::default(),
+            ExpressionValue::Number("55".parse().unwrap()),
+        );
+    }
+
+    #[test]
+    fn test_evaluate_invalid_tiers() {
+        for tiers in [
+            decimal("1"),
+            array(vec![*decimal("1")]),
+            array(vec![*array(vec![*decimal("0"), Expression::Null])]),
+            array(vec![*array(vec![
+                Expression::String("0".into()),
+                Expression::Null,
+                *decimal("1"),
+            ])]),
+        ] {
+            let expr = Expression::Function(Function::Volume(decimal("1"), tiers));
+            assert!(
+                matches!(
+                    expr.evaluate(&Default::default()),
+                    Err(ExpressionError::InvalidTier(_) | ExpressionError::ExpectedArray)
+                ),
+                "{expr:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_package() {
+        let expr = Function::Package(decimal("250"), decimal("100"), decimal("5"), None);
+        evaluate_and_compare(
+            Expression::Function(expr),
+            &Default::default(),
+            ExpressionValue::Number(15.into()),
+        );
+
+        let expr = Function::Package(
+            decimal("250"),
+            decimal("100"),
+            decimal("5"),
+            Some(decimal("200")),
+        );
+        evaluate_and_compare(
+            Expression::Function(expr),
+            &Default::default(),
+            ExpressionValue::Number(5.into()),
+        );
+
+        let expr = Function::Package(decimal("250"), property("size"), decimal("5"), None);
+        evaluate_and_compare(
+            Expression::Function(expr),
+            &Default::default(),
+            ExpressionValue::Null,
+        );
+    }
+
+    #[test]
+    fn test_evaluate_percentage() {
+        let expr = Function::Percentage(
+            decimal("1000"),
+            decimal("1.5"),
+            Some(decimal("0.25")),
+            Some(Box::new(Expression::Null)),
+            Some(decimal("10")),
+        );
+        evaluate_and_compare(
+            Expression::Function(expr),
+            &Default::default(),
+            ExpressionValue::Number(10.into()),
+        );
+
+        let expr = Function::Percentage(decimal("1000"), decimal("1.5"), None, None, None);
+        evaluate_and_compare(
+            Expression::Function(expr),
+            &Default::default(),
+            ExpressionValue::Number(15.into()),
+        );
+    }
+
+    fn money(amount: &str, currency: &str) -> Box<Expression> {
+        function(Function::Money(
+            decimal(amount),
+            Box::new(Expression::String(currency.into())),
+        ))
+    }
+
+    fn money_value(amount: &str, currency: &str) -> ExpressionValue {
+        ExpressionValue::Money(
+            amount.parse().unwrap(),
+            Currency::from_code(currency).unwrap(),
+        )
+    }
+
+    fn binop(lhs: Box<Expression>, op: Operation, rhs: Box<Expression>) -> Expression {
+        Expression::BinOp { lhs, op, rhs }
+    }
+
+    #[test]
+    fn test_evaluate_money() {
+        let event = Default::default();
+        evaluate_and_compare(
+            *money("12.345", "eur"),
+            &event,
+            money_value("12.345", "EUR"),
+        );
+        assert_eq!(
+            money("12.345", "EUR").evaluate(&event).unwrap().to_string(),
+            "12.345 EUR"
+        );
+
+        let expr = Function::Money(
+            property("missing"),
+            Box::new(Expression::String("EUR".into())),
+        );
+        evaluate_and_compare(Expression::Function(expr), &event, ExpressionValue::Null);
+
+        assert!(matches!(
+            money("1", "EURO").evaluate(&event),
+            Err(ExpressionError::UnknownCurrency(code)) if code == "EURO"
+        ));
+        let expr = Function::Money(decimal("1"), decimal("978"));
+        assert!(matches!(
+            Expression::Function(expr).evaluate(&event),
+            Err(ExpressionError::ExpectedString)
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_money_arithmetic() {
+        let event = Default::default();
+        for (expr, expected) in [
+            (
+                binop(money("1.5", "EUR"), Operation::Add, money("2.25", "EUR")),
+                money_value("3.75", "EUR"),
+            ),
+            (
+                binop(
+                    money("1.5", "EUR"),
+                    Operation::Subtract,
+                    money("2.25", "EUR"),
+                ),
+                money_value("-0.75", "EUR"),
+            ),
+            (
+                binop(decimal("3"), Operation::Multiply, money("0.1", "USD")),
+                money_value("0.3", "USD"),
+            ),
+            (
+                binop(money("10", "JPY"), Operation::Divide, decimal("4")),
+                money_value("2.5", "JPY"),
+            ),
+            (
+                binop(money("10", "EUR"), Operation::Divide, money("4", "EUR")),
+                ExpressionValue::Number("2.5".parse().unwrap()),
+            ),
+            (
+                binop(
+                    money("10", "EUR"),
+                    Operation::GreaterThan,
+                    money("4", "EUR"),
+                ),
+                ExpressionValue::Boolean(true),
+            ),
+            (
+                Expression::UnaryMinus(money("10", "EUR")),
+                money_value("-10", "EUR"),
+            ),
+        ] {
+            evaluate_and_compare(expr, &event, expected);
+        }
+    }
+
+    #[test]
+    fn test_evaluate_money_currency_mismatch() {
+        let event = Default::default();
+        for op in [
+            Operation::Add,
+            Operation::Subtract,
+            Operation::Divide,
+            Operation::Equal,
+            Operation::LessThan,
+        ] {
+            let expr = binop(money("1", "EUR"), op, money("1", "USD"));
+            assert!(
+                matches!(
+                    expr.evaluate(&event),
+                    Err(ExpressionError::CurrencyMismatch(l, r)) if l.code() == "EUR" && r.code() == "USD"
+                ),
+                "{expr:?}"
+            );
+        }
+
+        let expr = binop(money("1", "EUR"), Operation::Add, decimal("1"));
+        assert!(matches!(
+            expr.evaluate(&event),
+            Err(ExpressionError::ExpectedMoney)
+        ));
+        let expr = binop(money("1", "EUR"), Operation::Multiply, money("1", "EUR"));
+        assert!(matches!(
+            expr.evaluate(&event),
+            Err(ExpressionError::ExpectedDecimal)
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_money_rounding() {
+        let event = Default::default();
+        for (expr, expected) in [
+            (
+                Function::Round(money("12.345", "EUR"), None),
+                money_value("12.35", "EUR"),
+            ),
+            (
+                Function::Round(money("12.5", "JPY"), None),
+                money_value("13", "JPY"),
+            ),
+            (
+                Function::Round(money("1.23456", "KWD"), None),
+                money_value("1.235", "KWD"),
+            ),
+            (
+                Function::Floor(money("12.349", "EUR"), None),
+                money_value("12.34", "EUR"),
+            ),
+            (
+                Function::Round(money("12.345", "EUR"), Some(decimal("1"))),
+                money_value("12.3", "EUR"),
+            ),
+        ] {
+            evaluate_and_compare(Expression::Function(expr), &event, expected);
+        }
+    }
+
+    #[test]
+    fn test_evaluate_money_division() {
+        let event = Default::default();
+        let third = |amount: &str, currency: &str| {
+            binop(money(amount, currency), Operation::Divide, decimal("3"))
+        };
+
+        // Amounts aren't rounded until they're rounded explicitly
+        evaluate_and_compare(
+            third("100", "JPY"),
+            &event,
+            ExpressionValue::Money(
+                BigDecimal::from(100) / BigDecimal::from(3),
+                Currency::from_code("JPY").unwrap(),
+            ),
+        );
+        for (expr, expected) in [
+            (
+                Function::Round(Box::new(third("100", "JPY")), None),
+                money_value("33", "JPY"),
+            ),
+            (
+                Function::Round(Box::new(third("10", "KWD")), None),
+                money_value("3.333", "KWD"),
+            ),
+            (
+                Function::Ceil(Box::new(third("10", "KWD")), None),
+                money_value("3.334", "KWD"),
+            ),
+            (
+                Function::ToCents(Box::new(third("200", "JPY"))),
+                ExpressionValue::Number(67.into()),
+            ),
+            (
+                Function::ToCents(Box::new(third("10", "KWD"))),
+                ExpressionValue::Number(3333.into()),
+            ),
+        ] {
+            evaluate_and_compare(Expression::Function(expr), &event, expected);
+        }
+    }
+
+    fn convert(amount: Box<Expression>, from: &str, to: &str) -> Expression {
+        Expression::Function(Function::Convert(
+            amount,
+            Box::new(Expression::String(from.into())),
+            Box::new(Expression::String(to.into())),
+        ))
+    }
+
+    #[test]
+    fn test_evaluate_convert() {
+        let event = Default::default();
+        let currency = |code| Currency::from_code(code).unwrap();
+        let mut exchange_rates = ExchangeRates::with_base(currency("EUR"));
+        for (to, rate) in [("USD", "1.08"), ("JPY", "161.5"), ("KWD", "0.3312")] {
+            exchange_rates
+                .insert(currency("EUR"), currency(to), rate.parse().unwrap())
+                .unwrap();
+        }
+        let context = EvaluationContext {
+            exchange_rates,
+            ..Default::default()
+        };
+
+        for (expr, expected) in [
+            (
+                convert(decimal("10"), "EUR", "USD"),
+                money_value("10.80", "USD"),
+            ),
+            (
+                convert(money("10", "EUR"), "EUR", "JPY"),
+                money_value("1615", "JPY"),
+            ),
+            (
+                convert(decimal("10.8"), "USD", "EUR"),
+                money_value("10", "EUR"),
+            ),
+            // Through EUR, 10 / 1.08 * 0.3312
+            (
+                convert(decimal("10"), "USD", "KWD"),
+                money_value("3.067", "KWD"),
+            ),
+            (
+                convert(decimal("12.345"), "EUR", "EUR"),
+                money_value("12.35", "EUR"),
+            ),
+            (
+                convert(property("missing"), "EUR", "USD"),
+                ExpressionValue::Null,
+            ),
+        ] {
+            assert_eq!(
+                expr.evaluate_with_context(&event, &context).unwrap(),
+                expected,
+                "{expr:?}"
+            );
+        }
+
+        assert!(matches!(
+            convert(decimal("1"), "EUR", "GBP").evaluate_with_context(&event, &context),
+            Err(ExpressionError::MissingExchangeRate { from, to })
+                if from.code() == "EUR" && to.code() == "GBP"
+        ));
+        assert!(matches!(
+            convert(decimal("1"), "EUR", "USD").evaluate(&event),
+            Err(ExpressionError::MissingExchangeRate { .. })
+        ));
+        assert!(matches!(
+            convert(money("1", "USD"), "EUR", "JPY").evaluate_with_context(&event, &context),
+            Err(ExpressionError::CurrencyMismatch(l, r)) if l.code() == "USD" && r.code() == "EUR"
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_cents() {
+        let event = Default::default();
+        for (expr, expected) in [
+            (
+                Function::ToCents(money("12.345", "EUR")),
+                ExpressionValue::Number(1235.into()),
+            ),
+            (
+                Function::ToCents(money("1234", "JPY")),
+                ExpressionValue::Number(1234.into()),
+            ),
+            (
+                Function::FromCents(decimal("1234"), Box::new(Expression::String("KWD".into()))),
+                money_value("1.234", "KWD"),
+            ),
+            (
+                Function::ToCents(property("missing")),
+                ExpressionValue::Null,
+            ),
+        ] {
+            evaluate_and_compare(Expression::Function(expr), &event, expected);
+        }
+
+        let expr = Function::ToCents(decimal("12"));
+        assert!(matches!(
+            Expression::Function(expr).evaluate(&event),
+            Err(ExpressionError::ExpectedMoney)
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_sum_money() {
+        let expr = Function::Sum(array(vec![
+            *money("1.5", "EUR"),
+            Expression::Null,
+            *money("2", "EUR"),
+        ]));
+        evaluate_and_compare(
+            Expression::Function(expr),
+            &Default::default(),
+            money_value("3.5", "EUR"),
+        );
+
+        let expr = Function::Sum(array(vec![*money("1.5", "EUR"), *decimal("2")]));
+        assert!(matches!(
+            Expression::Function(expr).evaluate(&Default::default()),
+            Err(ExpressionError::ExpectedMoney)
+        ));
+    }
 }
@@ -1,20 +1,30 @@
 use std::collections::HashMap;
 
 use bigdecimal::{BigDecimal, FromPrimitive};
-use serde::Deserialize;
+use chrono::DateTime;
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Debug, Default, PartialEq, Deserialize)]
+use crate::datetime;
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct Event {
     pub code: String,
+    #[serde(deserialize_with = "deserialize_timestamp")]
     pub timestamp: PropertyValue,
     pub properties: HashMap<String, PropertyValue>,
 }
 
-#[derive(Debug, PartialEq, Clone, Deserialize)]
+/// Any JSON value. Variants are tried in order when deserializing, so
+/// strings are never parsed as numbers.
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum PropertyValue {
     String(String),
-    Number(BigDecimal),
+    Number(#[serde(serialize_with = "bigdecimal::serde::json_num::serialize")] BigDecimal),
+    Boolean(bool),
+    Null,
+    Array(Vec<PropertyValue>),
+    Object(HashMap<String, PropertyValue>),
 }
 
 impl Default for PropertyValue {
@@ -23,6 +33,22 @@ impl Default for PropertyValue {
     }
 }
 
+/// Timestamps are numbers of seconds or milliseconds since the epoch, RFC 3339
+/// strings are converted to seconds
+fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<PropertyValue, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let timestamp = match PropertyValue::deserialize(deserializer)? {
+        PropertyValue::String(s) => match DateTime::parse_from_rfc3339(&s) {
+            Ok(dt) => PropertyValue::Number(datetime::to_timestamp(&dt)),
+            Err(_) => PropertyValue::String(s),
+        },
+        value => value,
+    };
+    Ok(timestamp)
+}
+
 impl From<&str> for PropertyValue {
     fn from(value: &str) -> Self {
         PropertyValue::String(value.into())
@@ -40,6 +66,12 @@ impl From<u64> for PropertyValue {
     }
 }
 
+impl From<bool> for PropertyValue {
+    fn from(value: bool) -> Self {
+        PropertyValue::Boolean(value)
+    }
+}
+
 impl From<f64> for PropertyValue {
     fn from(value: f64) -> Self {
         let number = BigDecimal::from_f64(value).expect("valid floating point timestamp given");
@@ -183,4 +215,97 @@ mod tests {
             }
         )
     }
+
+    #[test]
+    fn test_deserialize_timestamp_rfc3339() {
+        let json = json!({
+            "code": "testing",
+            "timestamp": "2024-06-01T12:00:00.5+02:00",
+            "properties": {}
+        });
+
+        let event = serde_json::from_value::<Event>(json).expect("expected json to parse");
+
+        assert_eq!(
+            event.timestamp,
+            PropertyValue::Number("1717236000.5".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_deserialize_json_data_model() {
+        let json = json!({
+            "code": "testing",
+            "timestamp": 1717243200,
+            "properties": {
+                "enabled": true,
+                "region": null,
+                "items": [{"qty": 2, "price": 1.5}, "other"],
+                "usage": {"cpu": {"seconds": 30}}
+            }
+        });
+
+        let event = serde_json::from_value::<Event>(json).expect("expected json to parse");
+
+        let item = HashMap::from([
+            ("qty".to_owned(), PropertyValue::Number(2.into())),
+            (
+                "price".to_owned(),
+                PropertyValue::Number("1.5".parse().unwrap()),
+            ),
+        ]);
+        let cpu = HashMap::from([("seconds".to_owned(), PropertyValue::Number(30.into()))]);
+        let usage = HashMap::from([("cpu".to_owned(), PropertyValue::Object(cpu))]);
+        let properties = HashMap::from([
+            ("enabled".to_owned(), PropertyValue::Boolean(true)),
+            ("region".to_owned(), PropertyValue::Null),
+            (
+                "items".to_owned(),
+                PropertyValue::Array(vec![PropertyValue::Object(item), "other".into()]),
+            ),
+            ("usage".to_owned(), PropertyValue::Object(usage)),
+        ]);
+
+        assert_eq!(
+            event,
+            Event {
+                code: "testing".to_owned(),
+                timestamp: 1717243200.into(),
+                properties
+            }
+        )
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let json = r#"{
+            "code": "testing",
+            "timestamp": 1717243200.5,
+            "properties": {
+                "text": "007",
+                "number": 3.20,
+                "enabled": false,
+                "missing": null,
+                "items": [1, [2, "3"], {"nested": true}]
+            }
+        }"#;
+
+        let event = serde_json::from_str::<Event>(json).expect("expected json to parse");
+        let serialized = serde_json::to_string(&event).expect("expected event to serialize");
+
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&serialized).unwrap(),
+            serde_json::from_str::<serde_json::Value>(json).unwrap()
+        );
+        assert_eq!(
+            serde_json::from_str::<Event>(&serialized).expect("expected json to parse"),
+            event
+        );
+    }
+
+    #[test]
+    fn test_serialize_numbers_as_json_numbers() {
+        let value = PropertyValue::Number("123.400".parse().unwrap());
+        assert_eq!(serde_json::to_string(&value).unwrap(), "123.400");
+    }
 }
@@ -0,0 +1,244 @@
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc};
+
+use pest::Parser;
+
+use crate::{
+    evaluate::{EvaluationResult, ExpressionError, ExpressionValue},
+    parser::{ExpressionParser, ParseError, ParseResult, Rule, BUILTIN_FUNCTIONS},
+};
+
+/// Domain functions added to the language, e.g. `tier(event.properties.plan)`
+///
+/// Names are case-insensitive and can't be the name of a built-in function.
+#[derive(Debug, Clone, Default)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, Arc<UserFunction>>,
+}
+
+/// The type of an argument to a user-defined function, arguments are checked
+/// before the function is called
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgumentType {
+    Number,
+    String,
+    Boolean,
+    DateTime,
+    Duration,
+    Array,
+    Object,
+    /// Any value, including null
+    Any,
+}
+
+type Implementation = dyn Fn(&[ExpressionValue]) -> EvaluationResult<ExpressionValue> + Send + Sync;
+
+pub struct UserFunction {
+    name: String,
+    arguments: Vec<ArgumentType>,
+    implementation: Box<Implementation>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces the function `name`, which is called with one value
+    /// per declared argument
+    pub fn register<F>(&mut self, name: &str, arguments: &[ArgumentType], f: F) -> ParseResult<()>
+    where
+        F: Fn(&[ExpressionValue]) -> EvaluationResult<ExpressionValue> + Send + Sync + 'static,
+    {
+        let key = name.to_lowercase();
+        if ExpressionParser::parse(Rule::definition_name, &key).is_err() {
+            return Err(ParseError::InvalidFunctionName(name.to_owned()));
+        }
+        if BUILTIN_FUNCTIONS.contains(&key.as_str()) {
+            return Err(ParseError::BuiltinFunction(name.to_owned()));
+        }
+
+        let function = UserFunction {
+            name: key.clone(),
+            arguments: arguments.to_vec(),
+            implementation: Box::new(f),
+        };
+        self.functions.insert(key, Arc::new(function));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<UserFunction>> {
+        self.functions.get(&name.to_lowercase())
+    }
+}
+
+impl UserFunction {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arguments(&self) -> &[ArgumentType] {
+        &self.arguments
+    }
+
+    /// Calls the function after checking the types of the arguments, a null
+    /// argument that isn't declared as `Any` results in null
+    pub(crate) fn call(&self, args: &[ExpressionValue]) -> EvaluationResult<ExpressionValue> {
+        for (arg, argument_type) in args.iter().zip(&self.arguments) {
+            if arg.is_null() && *argument_type != ArgumentType::Any {
+                return Ok(ExpressionValue::Null);
+            }
+            argument_type.check(arg)?;
+        }
+        (self.implementation)(args)
+    }
+}
+
+impl fmt::Debug for UserFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserFunction")
+            .field("name", &self.name)
+            .field("arguments", &self.arguments)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Functions are only equal to themselves, closures can't be compared
+impl PartialEq for UserFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl ArgumentType {
+    fn check(&self, value: &ExpressionValue) -> EvaluationResult<()> {
+        let error = match (self, value) {
+            (ArgumentType::Any, _)
+            | (ArgumentType::Number, ExpressionValue::Number(_))
+            | (ArgumentType::String, ExpressionValue::String(_))
+            | (ArgumentType::Boolean, ExpressionValue::Boolean(_))
+            | (ArgumentType::DateTime, ExpressionValue::DateTime(_))
+            | (ArgumentType::Duration, ExpressionValue::Duration(_))
+            | (ArgumentType::Array, ExpressionValue::Array(_))
+            | (ArgumentType::Object, ExpressionValue::Object(_)) => return Ok(()),
+            (ArgumentType::Number, _) => ExpressionError::ExpectedDecimal,
+            (ArgumentType::String, _) => ExpressionError::ExpectedString,
+            (ArgumentType::Boolean, _) => ExpressionError::ExpectedBoolean,
+            (ArgumentType::DateTime, _) => ExpressionError::ExpectedTimestamp,
+            (ArgumentType::Duration, _) => ExpressionError::ExpectedDuration,
+            (ArgumentType::Array, _) => ExpressionError::ExpectedArray,
+            (ArgumentType::Object, _) => ExpressionError::ExpectedObject,
+        };
+        Err(error)
+    }
+}
+
+/// Parses the lowercase name of a type, e.g. `number`, so bindings can declare
+/// the arguments of functions
+impl FromStr for ArgumentType {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "number" => Ok(ArgumentType::Number),
+            "string" => Ok(ArgumentType::String),
+            "boolean" => Ok(ArgumentType::Boolean),
+            "datetime" => Ok(ArgumentType::DateTime),
+            "duration" => Ok(ArgumentType::Duration),
+            "array" => Ok(ArgumentType::Array),
+            "object" => Ok(ArgumentType::Object),
+            "any" => Ok(ArgumentType::Any),
+            _ => Err(ParseError::InvalidArgumentType(s.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> FunctionRegistry {
+        let mut functions = FunctionRegistry::new();
+        functions
+            .register("Double", &[ArgumentType::Number], |args| {
+                Ok(ExpressionValue::Number(args[0].to_decimal()? * 2))
+            })
+            .unwrap();
+        functions
+            .register("is_missing", &[ArgumentType::Any], |args| {
+                Ok(ExpressionValue::Boolean(args[0].is_null()))
+            })
+            .unwrap();
+        functions
+    }
+
+    #[test]
+    fn test_get() {
+        let functions = registry();
+        let function = functions.get("DOUBLE").unwrap();
+
+        assert_eq!(function.name(), "double");
+        assert_eq!(function.arguments(), &[ArgumentType::Number]);
+        assert!(functions.get("triple").is_none());
+    }
+
+    #[test]
+    fn test_call() {
+        let functions = registry();
+        let double = functions.get("double").unwrap();
+        let is_missing = functions.get("is_missing").unwrap();
+
+        assert_eq!(
+            double.call(&[ExpressionValue::Number(2.into())]).unwrap(),
+            ExpressionValue::Number(4.into())
+        );
+        assert_eq!(
+            double.call(&[ExpressionValue::Null]).unwrap(),
+            ExpressionValue::Null
+        );
+        assert!(matches!(
+            double.call(&["2".to_owned().into()]),
+            Err(ExpressionError::ExpectedDecimal)
+        ));
+        assert_eq!(
+            is_missing.call(&[ExpressionValue::Null]).unwrap(),
+            ExpressionValue::Boolean(true)
+        );
+    }
+
+    #[test]
+    fn test_parse_argument_type() {
+        assert_eq!(
+            "number".parse::<ArgumentType>().unwrap(),
+            ArgumentType::Number
+        );
+        assert_eq!(
+            "datetime".parse::<ArgumentType>().unwrap(),
+            ArgumentType::DateTime
+        );
+        assert_eq!("any".parse::<ArgumentType>().unwrap(), ArgumentType::Any);
+        assert!(matches!(
+            "decimal".parse::<ArgumentType>(),
+            Err(ParseError::InvalidArgumentType(name)) if name == "decimal"
+        ));
+    }
+
+    #[test]
+    fn test_register_errors() {
+        let mut functions = FunctionRegistry::new();
+        let f = |_: &[ExpressionValue]| Ok(ExpressionValue::Null);
+
+        assert!(matches!(
+            functions.register("ROUND", &[], f),
+            Err(ParseError::BuiltinFunction(name)) if name == "ROUND"
+        ));
+        for name in ["", "1st", "tier-price", "@tier", "IF"] {
+            assert!(
+                matches!(
+                    functions.register(name, &[], f),
+                    Err(ParseError::InvalidFunctionName(_))
+                ),
+                "{name}"
+            );
+        }
+    }
+}
@@ -1,8 +1,24 @@
-pub use evaluate::{EvaluationResult, ExpressionValue};
+pub use chrono_tz::Tz;
+pub use context::EvaluationContext;
+pub use currency::{Currency, ExchangeRates};
+pub use evaluate::{EvaluationResult, ExpressionError, ExpressionValue};
 pub use event::{Event, PropertyValue};
+pub use function::{ArgumentType, FunctionRegistry, UserFunction};
 pub use parser::{Expression, ExpressionParser, ParseError};
 pub use pest::Parser;
+pub use snippet::{Snippet, SnippetRegistry};
+#[cfg(feature = "wasm")]
+pub use wasm::{WasmError, WasmLimits, WasmModule};
 
+mod context;
+mod currency;
+mod datetime;
 mod evaluate;
 mod event;
+mod function;
+mod math;
 mod parser;
+mod pricing;
+mod snippet;
+#[cfg(feature = "wasm")]
+mod wasm;
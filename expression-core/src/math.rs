@@ -0,0 +1,357 @@
+use std::num::NonZeroU64;
+
+use bigdecimal::{BigDecimal, One, RoundingMode, Signed, ToPrimitive, Zero};
+
+use crate::evaluate::{EvaluationResult, ExpressionError};
+
+/// Number of significant digits of results that can't be represented
+/// exactly, e.g. `2 ^ 0.5`
+pub const PRECISION: u64 = 50;
+
+/// Intermediate results are kept with extra digits, so the rounding errors
+/// of the range reductions don't show up in the final result
+const WORKING_PRECISION: u64 = PRECISION + 20;
+
+/// Results with an order of magnitude above this are rejected, instead of
+/// spending an unbounded amount of time and memory computing them
+const MAX_MAGNITUDE: u64 = 1_000_000_000;
+
+/// Floored division, returning the quotient and the remainder. The quotient
+/// is rounded towards negative infinity, so the remainder always has the
+/// sign of the divisor, e.g. `-7 // 2 == -4` and `-7 % 2 == 1`.
+pub fn floored_div_rem(
+    lhs: &BigDecimal,
+    rhs: &BigDecimal,
+) -> EvaluationResult<(BigDecimal, BigDecimal)> {
+    if rhs.is_zero() {
+        return Err(ExpressionError::DivisionByZero);
+    }
+
+    // `%` on decimals truncates, so the remainder has the sign of the dividend
+    let mut remainder = lhs % rhs;
+    let mut quotient = ((lhs - &remainder) / rhs).with_scale(0);
+
+    if !remainder.is_zero() && remainder.is_negative() != rhs.is_negative() {
+        quotient -= 1;
+        remainder += rhs;
+    }
+
+    Ok((quotient, remainder))
+}
+
+/// Raises `base` to the power `exponent`. Integer exponents are computed
+/// with 100 significant digits, like divisions, so they're exact unless the
+/// result has more digits, e.g. `2 ^ 400`. Fractional exponents are computed
+/// as `exp(exponent * ln(base))` and rounded to `PRECISION` significant digits.
+pub fn pow(base: &BigDecimal, exponent: &BigDecimal) -> EvaluationResult<BigDecimal> {
+    if exponent.is_integer() {
+        let n = exponent.to_i64().ok_or(ExpressionError::Overflow)?;
+        if n == 0 {
+            return Ok(BigDecimal::one());
+        }
+        if base.is_zero() {
+            return if n > 0 {
+                Ok(BigDecimal::zero())
+            } else {
+                Err(ExpressionError::DivisionByZero)
+            };
+        }
+
+        let magnitude = base.order_of_magnitude().unsigned_abs() + 1;
+        if n.unsigned_abs().saturating_mul(magnitude) > MAX_MAGNITUDE {
+            return Err(ExpressionError::Overflow);
+        }
+        return Ok(trim_fraction(base.powi(n)));
+    }
+
+    if base.is_zero() {
+        return if exponent.is_positive() {
+            Ok(BigDecimal::zero())
+        } else {
+            Err(ExpressionError::DivisionByZero)
+        };
+    }
+    if base.is_negative() {
+        return Err(ExpressionError::Undefined(
+            "fractional power".to_owned(),
+            base.clone(),
+        ));
+    }
+
+    let result = exp_with_working_precision(&(exponent * ln_with_working_precision(base)?))?;
+    Ok(round_to_precision(result))
+}
+
+/// Natural exponential function, rounded to `PRECISION` significant digits
+pub fn exp(x: &BigDecimal) -> EvaluationResult<BigDecimal> {
+    exp_with_working_precision(x).map(round_to_precision)
+}
+
+/// Natural logarithm, rounded to `PRECISION` significant digits
+pub fn ln(x: &BigDecimal) -> EvaluationResult<BigDecimal> {
+    ln_with_working_precision(x).map(round_to_precision)
+}
+
+/// Base 10 logarithm, rounded to `PRECISION` significant digits. Powers of
+/// ten result in exact integers.
+pub fn log10(x: &BigDecimal) -> EvaluationResult<BigDecimal> {
+    if !x.is_positive() {
+        return Err(ExpressionError::Undefined("log10".to_owned(), x.clone()));
+    }
+
+    let (digits, scale) = x.normalized().into_bigint_and_scale();
+    if digits.is_one() {
+        return Ok(BigDecimal::from(-scale));
+    }
+
+    let result = ln_with_working_precision(x)? / ln_with_working_precision(&BigDecimal::from(10))?;
+    Ok(round_to_precision(result))
+}
+
+/// Square root, rounded to `PRECISION` significant digits
+pub fn sqrt(x: &BigDecimal) -> EvaluationResult<BigDecimal> {
+    x.sqrt()
+        .map(round_to_precision)
+        .ok_or_else(|| ExpressionError::Undefined("sqrt".to_owned(), x.clone()))
+}
+
+/// Removes the trailing zeros of the fractional part, e.g. `2 ^ -3` is
+/// computed as `0.1250...0`. Integers keep their digits so they aren't
+/// displayed in scientific notation.
+fn trim_fraction(x: BigDecimal) -> BigDecimal {
+    let x = x.normalized();
+    if x.fractional_digit_count() < 0 {
+        x.with_scale(0)
+    } else {
+        x
+    }
+}
+
+fn round_to_precision(x: BigDecimal) -> BigDecimal {
+    let precision = NonZeroU64::new(PRECISION).expect("non-zero precision");
+    x.with_precision_round(precision, RoundingMode::HalfEven)
+        .normalized()
+}
+
+fn exp_with_working_precision(x: &BigDecimal) -> EvaluationResult<BigDecimal> {
+    if x.is_zero() {
+        return Ok(BigDecimal::one());
+    }
+    // e ^ (2.3 * 10^9) ~= 10 ^ 10^9
+    if x.abs() > 2 * MAX_MAGNITUDE + MAX_MAGNITUDE / 3 {
+        return if x.is_negative() {
+            Ok(BigDecimal::zero())
+        } else {
+            Err(ExpressionError::Overflow)
+        };
+    }
+
+    // Bring the argument close to zero where the series converges quickly,
+    // and square the result back: e^x = (e^(x / 2^n))^(2^n)
+    let half = BigDecimal::new(5.into(), 1);
+    let mut reduced = x.clone();
+    let mut halvings = 0;
+    while reduced.abs() > half {
+        reduced = reduced.half();
+        halvings += 1;
+    }
+
+    // e^x = sum(x^n / n!)
+    let epsilon = BigDecimal::new(1.into(), WORKING_PRECISION as i64);
+    let mut result = BigDecimal::one() + &reduced;
+    let mut term = reduced.clone();
+    for n in 2u32.. {
+        term = (term * &reduced / BigDecimal::from(n)).with_prec(WORKING_PRECISION);
+        if term.abs() < epsilon {
+            break;
+        }
+        result += &term;
+    }
+
+    for _ in 0..halvings {
+        result = result.square().with_prec(WORKING_PRECISION);
+    }
+
+    Ok(result)
+}
+
+fn ln_with_working_precision(x: &BigDecimal) -> EvaluationResult<BigDecimal> {
+    if !x.is_positive() {
+        return Err(ExpressionError::Undefined("ln".to_owned(), x.clone()));
+    }
+
+    // Bring the argument close to one where the series converges quickly:
+    // ln(x) = 2^n * ln(x^(1 / 2^n))
+    let limit = BigDecimal::new(1.into(), 2);
+    let mut reduced = x.clone();
+    let mut square_roots = 0;
+    while (&reduced - BigDecimal::one()).abs() > limit {
+        reduced = reduced
+            .sqrt()
+            .expect("square root of a positive number")
+            .with_prec(WORKING_PRECISION);
+        square_roots += 1;
+    }
+
+    // ln(x) = 2 * atanh(z) = 2 * sum(z^(2n+1) / (2n+1)), with z = (x - 1) / (x + 1)
+    let z = (&reduced - BigDecimal::one()) / (&reduced + BigDecimal::one());
+    let z_squared = z.square().with_prec(WORKING_PRECISION);
+    let epsilon = BigDecimal::new(1.into(), WORKING_PRECISION as i64 + 2);
+    let mut sum = z.clone();
+    let mut power = z;
+    for n in (3u32..).step_by(2) {
+        power = (power * &z_squared).with_prec(WORKING_PRECISION);
+        let term = &power / BigDecimal::from(n);
+        if term.abs() < epsilon {
+            break;
+        }
+        sum += term;
+    }
+
+    Ok(sum * BigDecimal::from(2u64.pow(square_roots + 1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(s: &str) -> BigDecimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_floored_div_rem() {
+        let cases = [
+            ("7", "2", "3", "1"),
+            ("-7", "2", "-4", "1"),
+            ("7", "-2", "-4", "-1"),
+            ("-7", "-2", "3", "-1"),
+            ("7.5", "2", "3", "1.5"),
+            ("250", "100", "2", "50"),
+        ];
+        for (lhs, rhs, quotient, remainder) in cases {
+            let result = floored_div_rem(&decimal(lhs), &decimal(rhs)).unwrap();
+            assert_eq!(result, (decimal(quotient), decimal(remainder)));
+        }
+    }
+
+    #[test]
+    fn test_floored_div_rem_by_zero() {
+        assert!(matches!(
+            floored_div_rem(&decimal("1"), &decimal("0")),
+            Err(ExpressionError::DivisionByZero)
+        ));
+    }
+
+    #[test]
+    fn test_pow_integer() {
+        assert_eq!(pow(&decimal("2"), &decimal("10")).unwrap(), decimal("1024"));
+        assert_eq!(
+            pow(&decimal("1.1"), &decimal("2")).unwrap(),
+            decimal("1.21")
+        );
+        assert_eq!(pow(&decimal("2"), &decimal("-2")).unwrap(), decimal("0.25"));
+        assert_eq!(pow(&decimal("-2"), &decimal("3")).unwrap(), decimal("-8"));
+        assert_eq!(pow(&decimal("0"), &decimal("0")).unwrap(), decimal("1"));
+
+        // Results are displayed without trailing zeros
+        for (base, exponent, expected) in [
+            ("2", "-3", "0.125"),
+            ("0.5", "-2", "4"),
+            ("10", "20", "100000000000000000000"),
+            (
+                "2",
+                "400",
+                "2582249878086908589655919172003011874329705792829223512830659356540647622016841194629645353280137831000000000000000000000",
+            ),
+        ] {
+            assert_eq!(
+                pow(&decimal(base), &decimal(exponent)).unwrap().to_string(),
+                expected,
+                "{base} ^ {exponent}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_pow_fractional() {
+        assert_eq!(pow(&decimal("4"), &decimal("0.5")).unwrap(), decimal("2"));
+        assert_eq!(
+            pow(&decimal("2"), &decimal("0.5")).unwrap(),
+            decimal("1.4142135623730950488016887242096980785696718753769")
+        );
+        assert_eq!(pow(&decimal("0"), &decimal("0.5")).unwrap(), decimal("0"));
+    }
+
+    #[test]
+    fn test_pow_errors() {
+        assert!(matches!(
+            pow(&decimal("-8"), &decimal("0.5")),
+            Err(ExpressionError::Undefined(_, _))
+        ));
+        assert!(matches!(
+            pow(&decimal("0"), &decimal("-1")),
+            Err(ExpressionError::DivisionByZero)
+        ));
+        assert!(matches!(
+            pow(&decimal("10"), &decimal("10000000000")),
+            Err(ExpressionError::Overflow)
+        ));
+    }
+
+    #[test]
+    fn test_exp() {
+        assert_eq!(
+            exp(&decimal("1")).unwrap(),
+            decimal("2.7182818284590452353602874713526624977572470937000")
+        );
+        assert_eq!(
+            exp(&decimal("-10")).unwrap(),
+            decimal("0.000045399929762484851535591515560550610237918088866565")
+        );
+    }
+
+    #[test]
+    fn test_ln() {
+        assert_eq!(ln(&decimal("1")).unwrap(), decimal("0"));
+        assert_eq!(
+            ln(&decimal("10")).unwrap(),
+            decimal("2.3025850929940456840179914546843642076011014886288")
+        );
+        assert_eq!(
+            ln(&decimal("0.001")).unwrap(),
+            decimal("-6.9077552789821370520539743640530926228033044658863")
+        );
+        assert!(matches!(
+            ln(&decimal("0")),
+            Err(ExpressionError::Undefined(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_log10() {
+        assert_eq!(log10(&decimal("1000")).unwrap(), decimal("3"));
+        assert_eq!(log10(&decimal("0.01")).unwrap(), decimal("-2"));
+        assert_eq!(
+            log10(&decimal("2")).unwrap(),
+            decimal("0.30102999566398119521373889472449302676818988146211")
+        );
+        assert!(matches!(
+            log10(&decimal("-1")),
+            Err(ExpressionError::Undefined(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_sqrt() {
+        assert_eq!(sqrt(&decimal("2.25")).unwrap(), decimal("1.5"));
+        assert_eq!(
+            sqrt(&decimal("2")).unwrap(),
+            decimal("1.4142135623730950488016887242096980785696718753769")
+        );
+        assert!(matches!(
+            sqrt(&decimal("-1")),
+            Err(ExpressionError::Undefined(_, _))
+        ));
+    }
+}
@@ -1,26 +1,134 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
+
 use bigdecimal::BigDecimal;
-use pest::{iterators::Pairs, pratt_parser::PrattParser};
+use chrono::NaiveTime;
+use pest::{
+    iterators::{Pair, Pairs},
+    pratt_parser::PrattParser,
+};
 
 use pest::Parser;
+use regex::{Regex, RegexBuilder};
 use thiserror::Error;
 
+use crate::{
+    datetime::{self, DateUnit},
+    function::{FunctionRegistry, UserFunction},
+    snippet::{Snippet, SnippetRegistry},
+};
+
 #[derive(pest_derive::Parser)]
 #[grammar = "grammar.pest"]
 pub struct ExpressionParser;
 
 impl ExpressionParser {
     pub fn parse_expression(input: &str) -> ParseResult<Expression> {
-        let mut pairs =
-            Self::parse(Rule::root, input).map_err(|e| ParseError::FailedToParse(e.to_string()))?;
+        Self::parse_expression_with_registries(
+            input,
+            &SnippetRegistry::default(),
+            &FunctionRegistry::default(),
+        )
+    }
+
+    /// Parses an expression that can use the snippets of the registry, e.g.
+    /// `@bytes_to_gb(event.properties.bytes)`
+    pub fn parse_expression_with_snippets(
+        input: &str,
+        snippets: &SnippetRegistry,
+    ) -> ParseResult<Expression> {
+        Self::parse_expression_with_registries(input, snippets, &FunctionRegistry::default())
+    }
+
+    /// Parses an expression that can call the functions of the registry
+    pub fn parse_expression_with_functions(
+        input: &str,
+        functions: &FunctionRegistry,
+    ) -> ParseResult<Expression> {
+        Self::parse_expression_with_registries(input, &SnippetRegistry::default(), functions)
+    }
+
+    pub fn parse_expression_with_registries(
+        input: &str,
+        snippets: &SnippetRegistry,
+        functions: &FunctionRegistry,
+    ) -> ParseResult<Expression> {
+        let expansions = Expansions::default();
+        parse_root(input, &Scope::new(snippets, functions, &expansions))
+    }
+}
+
+fn parse_root(input: &str, scope: &Scope) -> ParseResult<Expression> {
+    let mut pairs = ExpressionParser::parse(Rule::root, input)
+        .map_err(|e| ParseError::FailedToParse(e.to_string()))?;
+
+    let inner = pairs.next().unwrap().into_inner();
+    parse_expr(inner, scope)
+}
+
+/// What names are resolved against while parsing
+#[derive(Clone)]
+struct Scope<'a> {
+    /// Variables bound by the enclosing lambdas and `let`s
+    variables: Vec<String>,
+    snippets: &'a SnippetRegistry,
+    functions: &'a FunctionRegistry,
+    /// Snippets being expanded, outermost first, to detect cycles
+    expanding: Vec<String>,
+    expansions: &'a Expansions,
+}
+
+/// The snippets expanded while parsing an expression
+#[derive(Default)]
+struct Expansions {
+    /// Expanded bodies and their sizes by snippet name, so a body is only
+    /// parsed the first time the snippet is used. Failures are kept as well,
+    /// the other uses are still parsed after an error.
+    bodies: RefCell<HashMap<String, ParseResult<(Expression, usize)>>>,
+    /// Number of nodes of all the expanded bodies, see [`MAX_EXPANDED_NODES`]
+    nodes: Cell<usize>,
+}
+
+/// Snippets can use each other this many levels deep
+const MAX_SNIPPET_DEPTH: usize = 32;
+
+/// Bodies are copied at every use of a snippet, so a few snippets using each
+/// other twice can expand to an expression that is exponentially large
+const MAX_EXPANDED_NODES: usize = 100_000;
+
+impl<'a> Scope<'a> {
+    fn new(
+        snippets: &'a SnippetRegistry,
+        functions: &'a FunctionRegistry,
+        expansions: &'a Expansions,
+    ) -> Self {
+        Self {
+            variables: Vec::new(),
+            snippets,
+            functions,
+            expanding: Vec::new(),
+            expansions,
+        }
+    }
 
-        let inner = pairs.next().unwrap().into_inner();
-        parse_expr(inner)
+    /// The scope with `name` added, names can't be reused in nested scopes so
+    /// a variable always refers to the same value
+    fn with_variable(&self, name: &str) -> ParseResult<Scope<'a>> {
+        if self.variables.iter().any(|v| v == name) {
+            return Err(ParseError::ShadowedVariable(name.to_owned()));
+        }
+        let mut scope = self.clone();
+        scope.variables.push(name.to_owned());
+        Ok(scope)
     }
 }
 
 pub type ParseResult<T> = Result<T, ParseError>;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Function {
     Concat(Vec<Expression>),
     Ceil(Box<Expression>, Option<Box<Expression>>),
@@ -28,30 +136,342 @@ pub enum Function {
     Floor(Box<Expression>, Option<Box<Expression>>),
     Least(Vec<Expression>),
     Greatest(Vec<Expression>),
+    Coalesce(Vec<Expression>),
+    IsNull(Box<Expression>),
+    HasProperty(Box<Expression>),
+    Abs(Box<Expression>),
+    Sign(Box<Expression>),
+    Sqrt(Box<Expression>),
+    Ln(Box<Expression>),
+    Log10(Box<Expression>),
+    Exp(Box<Expression>),
+    Clamp(Box<Expression>, Box<Expression>, Box<Expression>),
+    Trunc(Box<Expression>, Option<Box<Expression>>),
+    Upper(Box<Expression>),
+    Lower(Box<Expression>),
+    Trim(Box<Expression>),
+    Length(Box<Expression>),
+    /// String, 1-based start position and optional length, in characters
+    Substring(Box<Expression>, Box<Expression>, Option<Box<Expression>>),
+    Replace(Box<Expression>, Box<Expression>, Box<Expression>),
+    /// String, delimiter and 1-based index of the part
+    SplitPart(Box<Expression>, Box<Expression>, Box<Expression>),
+    /// Separator and the values to concatenate
+    ConcatWs(Box<Expression>, Vec<Expression>),
+    Matches(Box<Expression>, Pattern),
+    /// String, pattern and the index of the capture group to return
+    RegexExtract(Box<Expression>, Pattern, Box<Expression>),
+    RegexReplace(Box<Expression>, Pattern, Box<Expression>),
+    Year(Box<Expression>),
+    Month(Box<Expression>),
+    Day(Box<Expression>),
+    Hour(Box<Expression>),
+    /// ISO day of the week, from 1 for Monday to 7 for Sunday
+    DayOfWeek(Box<Expression>),
+    /// Truncates a date and time, or a timestamp in which case the result is a
+    /// timestamp in the same unit, seconds or milliseconds
+    DateTrunc(DateUnit, Box<Expression>),
+    /// Whether the time of day is in `[start, end)`, wrapping around midnight
+    /// when `end` is before `start`
+    TimeBetween(Box<Expression>, NaiveTime, NaiveTime),
+    /// Parses a string with an optional strftime format, by default RFC 3339
+    ParseDateTime(Box<Expression>, Option<Box<Expression>>),
+    FormatDateTime(Box<Expression>, Box<Expression>),
+    /// Seconds since the epoch
+    ToEpoch(Box<Expression>),
+    ToHours(Box<Expression>),
+    ToMinutes(Box<Expression>),
+    ToSeconds(Box<Expression>),
+    ToNumber(Box<Expression>),
+    /// Converts to a number, truncating towards zero
+    ToInteger(Box<Expression>),
+    ToString(Box<Expression>),
+    Sum(Box<Expression>),
+    Count(Box<Expression>),
+    Avg(Box<Expression>),
+    Min(Box<Expression>),
+    Max(Box<Expression>),
+    /// Whether an array has an element equal to the value, or a string contains
+    /// the substring
+    Contains(Box<Expression>, Box<Expression>),
+    /// Array and separator
+    Join(Box<Expression>, Box<Expression>),
+    Map(Box<Expression>, Lambda),
+    Filter(Box<Expression>, Lambda),
+    /// Units and tiers, as arrays of `[from, to, per_unit, flat]` with an
+    /// optional flat fee and a null `to` for the last tier
+    Graduated(Box<Expression>, Box<Expression>),
+    Volume(Box<Expression>, Box<Expression>),
+    /// Units, package size, price per package and free units
+    Package(
+        Box<Expression>,
+        Box<Expression>,
+        Box<Expression>,
+        Option<Box<Expression>>,
+    ),
+    /// Amount, rate in percent, fixed fee, minimum and maximum
+    Percentage(
+        Box<Expression>,
+        Box<Expression>,
+        Option<Box<Expression>>,
+        Option<Box<Expression>>,
+        Option<Box<Expression>>,
+    ),
+    /// Amount and ISO 4217 currency code, the amount isn't rounded to the
+    /// minor unit of the currency until it's rounded explicitly
+    Money(Box<Expression>, Box<Expression>),
+    /// The amount of money in the minor unit of its currency, e.g. cents for EUR
+    ToCents(Box<Expression>),
+    /// Amount in the minor unit and currency code
+    FromCents(Box<Expression>, Box<Expression>),
+    /// Amount, source and destination currency codes, at the rates of the context
+    Convert(Box<Expression>, Box<Expression>, Box<Expression>),
+    /// A function of the function registry and its arguments
+    User(Arc<UserFunction>, Vec<Expression>),
+}
+
+/// A function of one variable, e.g. `x -> x.qty * x.price`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lambda {
+    pub parameter: String,
+    pub body: Box<Expression>,
+}
+
+/// Maximum size of a compiled regular expression
+const PATTERN_SIZE_LIMIT: usize = 1 << 20;
+
+/// A regular expression, compiled while parsing. Matching runs in time linear
+/// to the input, patterns can't cause catastrophic backtracking.
+#[derive(Debug, Clone)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn new(pattern: &str) -> ParseResult<Self> {
+        let regex = RegexBuilder::new(pattern)
+            .size_limit(PATTERN_SIZE_LIMIT)
+            .build()?;
+        Ok(Self(regex))
+    }
+
+    pub fn regex(&self) -> &Regex {
+        &self.0
+    }
 }
 
-#[derive(Debug, PartialEq)]
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum EventAttribute {
     Code,
     Timestamp,
-    Properties(String),
+    /// A property, followed by the path to a value nested in it
+    Properties(String, Vec<PathSegment>),
+}
+
+/// A step into a nested property value
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    /// Value of a key of an object
+    Key(String),
+    /// Element of an array, starting at 0
+    Index(usize),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expression {
     EventAttribute(EventAttribute),
     Function(Function),
     String(String),
     Decimal(BigDecimal),
+    /// A length of time in seconds, written with a unit, e.g. `15m`
+    Duration(BigDecimal),
+    Boolean(bool),
+    Null,
+    Array(Vec<Expression>),
+    /// An object with literal keys, which are unique
+    Map(Vec<(String, Expression)>),
+    /// Element of an array or value of an object, e.g. `{'eu': 1.2}[region]`
+    Index(Box<Expression>, Box<Expression>),
+    /// A parameter given to the evaluation, followed by the path to a value
+    /// nested in it
+    Parameter(String, Vec<PathSegment>),
+    /// A variable bound by a lambda or `let`, followed by the path to a value
+    /// nested in it
+    Variable(String, Vec<PathSegment>),
+    /// `body`, with `name` bound to the value of `value`
+    Let {
+        name: String,
+        value: Box<Expression>,
+        body: Box<Expression>,
+    },
     UnaryMinus(Box<Expression>),
+    Not(Box<Expression>),
     BinOp {
         lhs: Box<Expression>,
         op: Operation,
         rhs: Box<Expression>,
     },
+    /// The value of the first branch whose condition is true, or `otherwise`
+    /// when none of them are. Both `if(..)` and `case .. end` parse into this.
+    Conditional {
+        branches: Vec<(Expression, Expression)>,
+        otherwise: Box<Expression>,
+    },
+}
+
+impl Expression {
+    /// Names of the parameters used by the expression, sorted and without duplicates
+    pub fn parameters(&self) -> Vec<String> {
+        let mut parameters = BTreeSet::new();
+        self.visit(&mut |expr| {
+            if let Expression::Parameter(name, _) = expr {
+                parameters.insert(name.clone());
+            }
+        });
+        parameters.into_iter().collect()
+    }
+
+    /// Number of expressions in the tree, including this one
+    fn size(&self) -> usize {
+        let mut size = 0;
+        self.visit(&mut |_| size += 1);
+        size
+    }
+
+    /// Calls `f` with this expression and all the expressions nested in it
+    fn visit<F>(&self, f: &mut F)
+    where
+        F: FnMut(&Expression),
+    {
+        f(self);
+        for child in self.children() {
+            child.visit(f);
+        }
+    }
+
+    fn children(&self) -> Vec<&Expression> {
+        match self {
+            Expression::EventAttribute(_)
+            | Expression::String(_)
+            | Expression::Decimal(_)
+            | Expression::Duration(_)
+            | Expression::Boolean(_)
+            | Expression::Null
+            | Expression::Parameter(_, _)
+            | Expression::Variable(_, _) => vec![],
+            Expression::Function(f) => f.arguments(),
+            Expression::Array(exprs) => exprs.iter().collect(),
+            Expression::Map(entries) => entries.iter().map(|(_, expr)| expr).collect(),
+            Expression::Index(expr, key) => vec![expr, key],
+            Expression::Let { value, body, .. } => vec![value, body],
+            Expression::UnaryMinus(expr) | Expression::Not(expr) => vec![expr],
+            Expression::BinOp { lhs, rhs, .. } => vec![lhs, rhs],
+            Expression::Conditional {
+                branches,
+                otherwise,
+            } => branches
+                .iter()
+                .flat_map(|(condition, value)| [condition, value])
+                .chain([otherwise.as_ref()])
+                .collect(),
+        }
+    }
+}
+
+impl Function {
+    /// The expressions the function is applied to, including the bodies of lambdas
+    fn arguments(&self) -> Vec<&Expression> {
+        match self {
+            Function::Concat(args)
+            | Function::Least(args)
+            | Function::Greatest(args)
+            | Function::Coalesce(args)
+            | Function::User(_, args) => args.iter().collect(),
+            Function::Ceil(expr, optional)
+            | Function::Round(expr, optional)
+            | Function::Floor(expr, optional)
+            | Function::Trunc(expr, optional)
+            | Function::ParseDateTime(expr, optional) => [expr.as_ref()]
+                .into_iter()
+                .chain(optional.as_deref())
+                .collect(),
+            Function::Substring(expr, start, length) => [expr.as_ref(), start]
+                .into_iter()
+                .chain(length.as_deref())
+                .collect(),
+            Function::IsNull(expr)
+            | Function::HasProperty(expr)
+            | Function::Abs(expr)
+            | Function::Sign(expr)
+            | Function::Sqrt(expr)
+            | Function::Ln(expr)
+            | Function::Log10(expr)
+            | Function::Exp(expr)
+            | Function::Upper(expr)
+            | Function::Lower(expr)
+            | Function::Trim(expr)
+            | Function::Length(expr)
+            | Function::Matches(expr, _)
+            | Function::Year(expr)
+            | Function::Month(expr)
+            | Function::Day(expr)
+            | Function::Hour(expr)
+            | Function::DayOfWeek(expr)
+            | Function::DateTrunc(_, expr)
+            | Function::TimeBetween(expr, _, _)
+            | Function::ToEpoch(expr)
+            | Function::ToHours(expr)
+            | Function::ToMinutes(expr)
+            | Function::ToSeconds(expr)
+            | Function::ToNumber(expr)
+            | Function::ToInteger(expr)
+            | Function::ToString(expr)
+            | Function::Sum(expr)
+            | Function::Count(expr)
+            | Function::Avg(expr)
+            | Function::Min(expr)
+            | Function::Max(expr)
+            | Function::ToCents(expr) => vec![expr],
+            Function::RegexExtract(expr, _, other)
+            | Function::RegexReplace(expr, _, other)
+            | Function::FormatDateTime(expr, other)
+            | Function::Contains(expr, other)
+            | Function::Join(expr, other)
+            | Function::Graduated(expr, other)
+            | Function::Volume(expr, other)
+            | Function::Money(expr, other)
+            | Function::FromCents(expr, other) => vec![expr, other],
+            Function::Clamp(a, b, c)
+            | Function::Convert(a, b, c)
+            | Function::Replace(a, b, c)
+            | Function::SplitPart(a, b, c) => {
+                vec![a, b, c]
+            }
+            Function::ConcatWs(separator, args) => {
+                [separator.as_ref()].into_iter().chain(args).collect()
+            }
+            Function::Map(expr, lambda) | Function::Filter(expr, lambda) => {
+                vec![expr, &lambda.body]
+            }
+            Function::Package(units, size, price, free_units) => [units.as_ref(), size, price]
+                .into_iter()
+                .chain(free_units.as_deref())
+                .collect(),
+            Function::Percentage(amount, rate, fixed_fee, min, max) => [amount.as_ref(), rate]
+                .into_iter()
+                .chain(fixed_fee.as_deref())
+                .chain(min.as_deref())
+                .chain(max.as_deref())
+                .collect(),
+        }
+    }
 }
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum ParseError {
     #[error("{0}")]
     FailedToParse(String),
@@ -61,56 +481,583 @@ pub enum ParseError {
 
     #[error("bigdecimal parsing error: {0}")]
     FailedToParseBigDecimal(#[from] ::bigdecimal::ParseBigDecimalError),
+
+    #[error("Expected a string literal as the pattern of function {0}")]
+    ExpectedPatternLiteral(String),
+
+    #[error("Expected a string literal argument to function {0}")]
+    ExpectedStringLiteral(String),
+
+    #[error("Invalid regular expression: {0}")]
+    InvalidRegex(#[from] regex::Error),
+
+    #[error("Invalid date unit: {0}, expected one of minute, hour, day, week, month or year")]
+    InvalidDateUnit(String),
+
+    #[error("Invalid time of day: {0}, expected HH:MM or HH:MM:SS")]
+    InvalidTimeOfDay(String),
+
+    #[error("Invalid array index: {0}")]
+    InvalidIndex(String),
+
+    #[error("Duplicate key: {0}")]
+    DuplicateKey(String),
+
+    #[error("Unknown variable: {0}")]
+    UnknownVariable(String),
+
+    #[error("Variable {0} is already defined")]
+    ShadowedVariable(String),
+
+    #[error("Expected a lambda as the last argument to function {0}")]
+    ExpectedLambda(String),
+
+    #[error("Lambdas are only allowed as the last argument to map and filter")]
+    UnexpectedLambda,
+
+    #[error("Undefined snippet: {0}")]
+    UndefinedSnippet(String),
+
+    #[error("Recursive snippet: {0}")]
+    RecursiveSnippet(String),
+
+    #[error("Snippets are nested too deeply: {0}")]
+    SnippetTooDeep(String),
+
+    #[error("Snippet {0} expands to an expression that is too large")]
+    SnippetTooLarge(String),
+
+    #[error("Unknown function: {0}")]
+    UnknownFunction(String),
+
+    #[error("Invalid function name: {0}")]
+    InvalidFunctionName(String),
+
+    #[error("{0} is a built-in function")]
+    BuiltinFunction(String),
+
+    #[error("Invalid argument type: {0}, expected one of number, string, boolean, datetime, duration, array, object or any")]
+    InvalidArgumentType(String),
+
+    #[cfg(feature = "wasm")]
+    #[error(transparent)]
+    Wasm(#[from] crate::wasm::WasmError),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Operation {
     Add,
     Subtract,
     Multiply,
     Divide,
+    /// Division rounded towards negative infinity
+    IntegerDivide,
+    /// Remainder of `IntegerDivide`, has the sign of the divisor
+    Modulo,
+    Power,
+    Equal,
+    NotEqual,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    /// Membership of the left hand side in the array on the right hand side
+    In,
+    /// `??`, the right hand side when the left hand side is null
+    Fallback,
+    And,
+    Or,
 }
 
-fn parse_function(pairs: Pairs<Rule>) -> ParseResult<Function> {
-    let mut iter = pairs.into_iter();
-    let name = iter.next().unwrap();
-    let function = match name.as_rule() {
-        Rule::concat => {
-            let args = iter
-                .map(|r| parse_expr(r.into_inner()))
-                .collect::<ParseResult<Vec<Expression>>>()?;
+/// Names of the built-in functions, in lowercase
+pub(crate) const BUILTIN_FUNCTIONS: &[&str] = &[
+    "concat",
+    "ceil",
+    "round",
+    "floor",
+    "trunc",
+    "least",
+    "greatest",
+    "coalesce",
+    "is_null",
+    "has_property",
+    "abs",
+    "sign",
+    "sqrt",
+    "ln",
+    "log10",
+    "exp",
+    "clamp",
+    "upper",
+    "lower",
+    "trim",
+    "length",
+    "substring",
+    "replace",
+    "split_part",
+    "concat_ws",
+    "matches",
+    "regex_extract",
+    "regex_replace",
+    "year",
+    "month",
+    "day",
+    "hour",
+    "day_of_week",
+    "date_trunc",
+    "time_between",
+    "parse_datetime",
+    "format_datetime",
+    "to_epoch",
+    "to_hours",
+    "to_minutes",
+    "to_seconds",
+    "to_number",
+    "to_integer",
+    "to_string",
+    "sum",
+    "count",
+    "avg",
+    "min",
+    "max",
+    "contains",
+    "join",
+    "map",
+    "filter",
+    "graduated",
+    "volume",
+    "package",
+    "percentage",
+    "money",
+    "to_cents",
+    "from_cents",
+    "convert",
+];
 
-            Function::Concat(args)
+fn parse_function(pairs: Pairs<Rule>, scope: &Scope) -> ParseResult<Function> {
+    let mut iter = pairs.into_iter();
+    let name = iter.next().unwrap().as_str().to_lowercase();
+    let function = match name.as_str() {
+        "concat" => Function::Concat(parse_args(iter, scope)?),
+        "ceil" => parse_function_with_args("ceil", Function::Ceil, iter, scope)?,
+        "round" => parse_function_with_args("round", Function::Round, iter, scope)?,
+        "floor" => parse_function_with_args("floor", Function::Floor, iter, scope)?,
+        "trunc" => parse_function_with_args("trunc", Function::Trunc, iter, scope)?,
+        "least" => Function::Least(parse_args(iter, scope)?),
+        "greatest" => Function::Greatest(parse_args(iter, scope)?),
+        "coalesce" => Function::Coalesce(parse_args(iter, scope)?),
+        "is_null" => parse_function_with_arg("is_null", Function::IsNull, iter, scope)?,
+        "has_property" => {
+            parse_function_with_arg("has_property", Function::HasProperty, iter, scope)?
         }
-        Rule::ceil => parse_function_with_args(Function::Ceil, iter)?,
-        Rule::round => parse_function_with_args(Function::Round, iter)?,
-        Rule::floor => parse_function_with_args(Function::Floor, iter)?,
-        Rule::least => {
-            let args = iter
-                .map(|r| parse_expr(r.into_inner()))
-                .collect::<ParseResult<Vec<Expression>>>()?;
-
-            Function::Least(args)
+        "abs" => parse_function_with_arg("abs", Function::Abs, iter, scope)?,
+        "sign" => parse_function_with_arg("sign", Function::Sign, iter, scope)?,
+        "sqrt" => parse_function_with_arg("sqrt", Function::Sqrt, iter, scope)?,
+        "ln" => parse_function_with_arg("ln", Function::Ln, iter, scope)?,
+        "log10" => parse_function_with_arg("log10", Function::Log10, iter, scope)?,
+        "exp" => parse_function_with_arg("exp", Function::Exp, iter, scope)?,
+        "clamp" => {
+            let [expr, min, max] = parse_fixed_args("clamp", iter, scope)?;
+            Function::Clamp(expr, min, max)
         }
-        Rule::greatest => {
-            let args = iter
-                .map(|r| parse_expr(r.into_inner()))
-                .collect::<ParseResult<Vec<Expression>>>()?;
-
-            Function::Greatest(args)
+        "upper" => parse_function_with_arg("upper", Function::Upper, iter, scope)?,
+        "lower" => parse_function_with_arg("lower", Function::Lower, iter, scope)?,
+        "trim" => parse_function_with_arg("trim", Function::Trim, iter, scope)?,
+        "length" => parse_function_with_arg("length", Function::Length, iter, scope)?,
+        "substring" => {
+            let mut args = parse_args(iter, scope)?.into_iter().map(Box::new);
+            match args.len() {
+                2 => Function::Substring(args.next().unwrap(), args.next().unwrap(), None),
+                3 => Function::Substring(args.next().unwrap(), args.next().unwrap(), args.next()),
+                n => {
+                    return Err(ParseError::WrongNumberOfArguments(
+                        "substring".to_owned(),
+                        "2..3".to_owned(),
+                        n,
+                    ))
+                }
+            }
+        }
+        "replace" => {
+            let [expr, from, to] = parse_fixed_args("replace", iter, scope)?;
+            Function::Replace(expr, from, to)
+        }
+        "split_part" => {
+            let [expr, delimiter, index] = parse_fixed_args("split_part", iter, scope)?;
+            Function::SplitPart(expr, delimiter, index)
+        }
+        "concat_ws" => {
+            let mut args = parse_args(iter, scope)?;
+            if args.is_empty() {
+                return Err(ParseError::WrongNumberOfArguments(
+                    "concat_ws".to_owned(),
+                    "1..".to_owned(),
+                    0,
+                ));
+            }
+            let separator = args.remove(0);
+            Function::ConcatWs(Box::new(separator), args)
+        }
+        "matches" => {
+            let [expr, pattern] = parse_fixed_args("matches", iter, scope)?;
+            Function::Matches(expr, parse_pattern("matches", *pattern)?)
+        }
+        "regex_extract" => {
+            let [expr, pattern, group] = parse_fixed_args("regex_extract", iter, scope)?;
+            Function::RegexExtract(expr, parse_pattern("regex_extract", *pattern)?, group)
+        }
+        "regex_replace" => {
+            let [expr, pattern, replacement] = parse_fixed_args("regex_replace", iter, scope)?;
+            Function::RegexReplace(expr, parse_pattern("regex_replace", *pattern)?, replacement)
+        }
+        "year" => parse_function_with_arg("year", Function::Year, iter, scope)?,
+        "month" => parse_function_with_arg("month", Function::Month, iter, scope)?,
+        "day" => parse_function_with_arg("day", Function::Day, iter, scope)?,
+        "hour" => parse_function_with_arg("hour", Function::Hour, iter, scope)?,
+        "day_of_week" => parse_function_with_arg("day_of_week", Function::DayOfWeek, iter, scope)?,
+        "date_trunc" => {
+            let [unit, expr] = parse_fixed_args("date_trunc", iter, scope)?;
+            let unit = parse_string_literal("date_trunc", *unit)?.parse()?;
+            Function::DateTrunc(unit, expr)
+        }
+        "time_between" => {
+            let [expr, start, end] = parse_fixed_args("time_between", iter, scope)?;
+            let start =
+                datetime::parse_time_of_day(&parse_string_literal("time_between", *start)?)?;
+            let end = datetime::parse_time_of_day(&parse_string_literal("time_between", *end)?)?;
+            Function::TimeBetween(expr, start, end)
+        }
+        "parse_datetime" => {
+            parse_function_with_args("parse_datetime", Function::ParseDateTime, iter, scope)?
+        }
+        "format_datetime" => {
+            let [expr, format] = parse_fixed_args("format_datetime", iter, scope)?;
+            Function::FormatDateTime(expr, format)
+        }
+        "to_epoch" => parse_function_with_arg("to_epoch", Function::ToEpoch, iter, scope)?,
+        "to_hours" => parse_function_with_arg("to_hours", Function::ToHours, iter, scope)?,
+        "to_minutes" => parse_function_with_arg("to_minutes", Function::ToMinutes, iter, scope)?,
+        "to_seconds" => parse_function_with_arg("to_seconds", Function::ToSeconds, iter, scope)?,
+        "to_number" => parse_function_with_arg("to_number", Function::ToNumber, iter, scope)?,
+        "to_integer" => parse_function_with_arg("to_integer", Function::ToInteger, iter, scope)?,
+        "to_string" => parse_function_with_arg("to_string", Function::ToString, iter, scope)?,
+        "sum" => parse_function_with_arg("sum", Function::Sum, iter, scope)?,
+        "count" => parse_function_with_arg("count", Function::Count, iter, scope)?,
+        "avg" => parse_function_with_arg("avg", Function::Avg, iter, scope)?,
+        "min" => parse_function_with_arg("min", Function::Min, iter, scope)?,
+        "max" => parse_function_with_arg("max", Function::Max, iter, scope)?,
+        "contains" => {
+            let [expr, value] = parse_fixed_args("contains", iter, scope)?;
+            Function::Contains(expr, value)
+        }
+        "join" => {
+            let [expr, separator] = parse_fixed_args("join", iter, scope)?;
+            Function::Join(expr, separator)
+        }
+        "map" => {
+            let (expr, lambda) = parse_lambda_args("map", iter, scope)?;
+            Function::Map(expr, lambda)
+        }
+        "filter" => {
+            let (expr, lambda) = parse_lambda_args("filter", iter, scope)?;
+            Function::Filter(expr, lambda)
+        }
+        "graduated" => {
+            let [units, tiers] = parse_fixed_args("graduated", iter, scope)?;
+            Function::Graduated(units, tiers)
+        }
+        "volume" => {
+            let [units, tiers] = parse_fixed_args("volume", iter, scope)?;
+            Function::Volume(units, tiers)
+        }
+        "package" => {
+            let [units, size, price, free_units] = parse_optional_args("package", 3, iter, scope)?;
+            Function::Package(units.unwrap(), size.unwrap(), price.unwrap(), free_units)
+        }
+        "money" => {
+            let [amount, currency] = parse_fixed_args("money", iter, scope)?;
+            Function::Money(amount, currency)
+        }
+        "to_cents" => parse_function_with_arg("to_cents", Function::ToCents, iter, scope)?,
+        "from_cents" => {
+            let [cents, currency] = parse_fixed_args("from_cents", iter, scope)?;
+            Function::FromCents(cents, currency)
+        }
+        "convert" => {
+            let [amount, from, to] = parse_fixed_args("convert", iter, scope)?;
+            Function::Convert(amount, from, to)
+        }
+        "percentage" => {
+            let [amount, rate, fixed_fee, min, max] =
+                parse_optional_args("percentage", 2, iter, scope)?;
+            Function::Percentage(amount.unwrap(), rate.unwrap(), fixed_fee, min, max)
+        }
+        _ => {
+            let function = scope
+                .functions
+                .get(&name)
+                .ok_or_else(|| ParseError::UnknownFunction(name.clone()))?;
+            let args = parse_args(iter, scope)?;
+            if args.len() != function.arguments().len() {
+                return Err(ParseError::WrongNumberOfArguments(
+                    name,
+                    function.arguments().len().to_string(),
+                    args.len(),
+                ));
+            }
+            Function::User(function.clone(), args)
         }
-        rule => unreachable!("Expected function name, got :{:?}", rule),
     };
     Ok(function)
 }
 
-fn parse_function_with_args<F>(f: F, iter: Pairs<Rule>) -> ParseResult<Function>
+fn parse_duration(mut iter: Pairs<Rule>) -> ParseResult<Expression> {
+    let amount: BigDecimal = iter.next().expect("duration amount").as_str().parse()?;
+    let unit_seconds = match iter.next().expect("duration unit").as_str() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        unit => unreachable!("Expected duration unit, got: {}", unit),
+    };
+    Ok(Expression::Duration(
+        amount * BigDecimal::from(unit_seconds),
+    ))
+}
+
+fn parse_pattern(name: &str, expr: Expression) -> ParseResult<Pattern> {
+    match expr {
+        Expression::String(pattern) => Pattern::new(&pattern),
+        _ => Err(ParseError::ExpectedPatternLiteral(name.to_owned())),
+    }
+}
+
+fn parse_string_literal(name: &str, expr: Expression) -> ParseResult<String> {
+    match expr {
+        Expression::String(s) => Ok(s),
+        _ => Err(ParseError::ExpectedStringLiteral(name.to_owned())),
+    }
+}
+
+fn parse_args(iter: Pairs<Rule>, scope: &Scope) -> ParseResult<Vec<Expression>> {
+    iter.map(|r| parse_arg(r, scope)).collect()
+}
+
+fn parse_arg(pair: Pair<Rule>, scope: &Scope) -> ParseResult<Expression> {
+    match pair.as_rule() {
+        Rule::expr => parse_expr(pair.into_inner(), scope),
+        _ => Err(ParseError::UnexpectedLambda),
+    }
+}
+
+/// Arguments of a higher-order function: an array and a lambda applied to its elements
+fn parse_lambda_args(
+    name: &str,
+    iter: Pairs<Rule>,
+    scope: &Scope,
+) -> ParseResult<(Box<Expression>, Lambda)> {
+    let [expr, lambda]: [Pair<Rule>; 2] =
+        iter.collect::<Vec<_>>()
+            .try_into()
+            .map_err(|args: Vec<_>| {
+                ParseError::WrongNumberOfArguments(name.to_owned(), "2".to_owned(), args.len())
+            })?;
+    if lambda.as_rule() != Rule::lambda {
+        return Err(ParseError::ExpectedLambda(name.to_owned()));
+    }
+    Ok((
+        Box::new(parse_arg(expr, scope)?),
+        parse_lambda(lambda, scope)?,
+    ))
+}
+
+/// Parses the body of the lambda with its parameter in scope
+fn parse_lambda(pair: Pair<Rule>, scope: &Scope) -> ParseResult<Lambda> {
+    let mut inner = pair.into_inner();
+    let parameter = inner.next().unwrap().as_str().to_owned();
+    let body_scope = scope.with_variable(&parameter)?;
+
+    let body = parse_expr(inner.next().unwrap().into_inner(), &body_scope)?;
+    Ok(Lambda {
+        parameter,
+        body: Box::new(body),
+    })
+}
+
+fn parse_let(pairs: Pairs<Rule>, scope: &Scope) -> ParseResult<Expression> {
+    let mut inner = pairs.filter(|r| r.as_rule() != Rule::let_keyword);
+    let name = inner.next().unwrap().as_str().to_owned();
+    // The variable is only in scope in the body, not in its own value
+    let value = parse_expr(inner.next().unwrap().into_inner(), scope)?;
+    let body_scope = scope.with_variable(&name)?;
+    let body = parse_expr(inner.next().unwrap().into_inner(), &body_scope)?;
+
+    Ok(Expression::Let {
+        name,
+        value: Box::new(value),
+        body: Box::new(body),
+    })
+}
+
+/// Replaces `@name(args)` with the body of the snippet, binding each argument
+/// to a parameter with `let` so it's evaluated once, e.g. `@gb(event.properties.bytes)`
+/// with `gb(x) = x / 1024 ^ 3` becomes
+/// `let @gb.x = event.properties.bytes; let x = @gb.x; x / 1024 ^ 3`
+///
+/// The arguments are bound to names that can't be written in an expression
+/// first, so they can't refer to the parameters of the snippet.
+fn expand_snippet(mut pairs: Pairs<Rule>, scope: &Scope) -> ParseResult<Expression> {
+    let name = pairs.next().unwrap().into_inner().as_str().to_owned();
+    let snippet = scope
+        .snippets
+        .get(&name)
+        .ok_or_else(|| ParseError::UndefinedSnippet(name.clone()))?;
+
+    let chain = || {
+        let mut chain = scope.expanding.clone();
+        chain.push(name.clone());
+        chain.join(" -> ")
+    };
+    if scope.expanding.contains(&name) {
+        return Err(ParseError::RecursiveSnippet(chain()));
+    }
+    if scope.expanding.len() == MAX_SNIPPET_DEPTH {
+        return Err(ParseError::SnippetTooDeep(chain()));
+    }
+
+    let args = parse_args(pairs, scope)?;
+    if args.len() != snippet.parameters.len() {
+        return Err(ParseError::WrongNumberOfArguments(
+            format!("@{name}"),
+            snippet.parameters.len().to_string(),
+            args.len(),
+        ));
+    }
+
+    let mut expr = expand_snippet_body(&name, snippet, scope)?;
+
+    let hidden = |parameter: &str| format!("@{name}.{parameter}");
+    for parameter in snippet.parameters.iter().rev() {
+        expr = Expression::Let {
+            name: parameter.clone(),
+            value: Box::new(Expression::Variable(hidden(parameter), vec![])),
+            body: Box::new(expr),
+        };
+    }
+    for (parameter, arg) in snippet.parameters.iter().zip(args).rev() {
+        expr = Expression::Let {
+            name: hidden(parameter),
+            value: Box::new(arg),
+            body: Box::new(expr),
+        };
+    }
+    Ok(expr)
+}
+
+/// The body of the snippet with the snippets it uses expanded, it only depends
+/// on the registries so it's parsed once and copied for the later uses
+fn expand_snippet_body(name: &str, snippet: &Snippet, scope: &Scope) -> ParseResult<Expression> {
+    let cached = scope.expansions.bodies.borrow().get(name).cloned();
+    let (body, size) = match cached {
+        Some(cached) => cached?,
+        None => {
+            // The body only sees the parameters, not the variables of the caller
+            let mut body_scope = Scope::new(scope.snippets, scope.functions, scope.expansions);
+            body_scope.variables = snippet.parameters.clone();
+            body_scope.expanding = scope.expanding.clone();
+            body_scope.expanding.push(name.to_owned());
+            let expanded = parse_root(&snippet.body, &body_scope).map(|body| {
+                let size = body.size();
+                (body, size)
+            });
+            scope
+                .expansions
+                .bodies
+                .borrow_mut()
+                .insert(name.to_owned(), expanded.clone());
+            expanded?
+        }
+    };
+
+    let nodes = scope.expansions.nodes.get() + size;
+    if nodes > MAX_EXPANDED_NODES {
+        return Err(ParseError::SnippetTooLarge(name.to_owned()));
+    }
+    scope.expansions.nodes.set(nodes);
+    Ok(body)
+}
+
+/// Variables are resolved while parsing, so unknown names are parse errors
+fn parse_variable_reference(mut pairs: Pairs<Rule>, scope: &Scope) -> ParseResult<Expression> {
+    let name = pairs.next().unwrap().as_str().to_owned();
+    if !scope.variables.contains(&name) {
+        return Err(ParseError::UnknownVariable(name));
+    }
+    let path = pairs.map(parse_path_segment).collect::<ParseResult<_>>()?;
+    Ok(Expression::Variable(name, path))
+}
+
+fn parse_fixed_args<const N: usize>(
+    name: &str,
+    iter: Pairs<Rule>,
+    scope: &Scope,
+) -> ParseResult<[Box<Expression>; N]> {
+    let args = parse_args(iter, scope)?;
+    let provided = args.len();
+
+    args.into_iter()
+        .map(Box::new)
+        .collect::<Vec<_>>()
+        .try_into()
+        .map_err(|_| ParseError::WrongNumberOfArguments(name.to_owned(), N.to_string(), provided))
+}
+
+fn parse_function_with_arg<F>(
+    name: &str,
+    f: F,
+    iter: Pairs<Rule>,
+    scope: &Scope,
+) -> ParseResult<Function>
+where
+    F: Fn(Box<Expression>) -> Function,
+{
+    let [arg] = parse_fixed_args(name, iter, scope)?;
+    Ok(f(arg))
+}
+
+/// Arguments of a function of which only the first `required` ones have to be given
+fn parse_optional_args<const N: usize>(
+    name: &str,
+    required: usize,
+    iter: Pairs<Rule>,
+    scope: &Scope,
+) -> ParseResult<[Option<Box<Expression>>; N]> {
+    let args = parse_args(iter, scope)?;
+    let provided = args.len();
+    if !(required..=N).contains(&provided) {
+        return Err(ParseError::WrongNumberOfArguments(
+            name.to_owned(),
+            format!("{required}..{N}"),
+            provided,
+        ));
+    }
+
+    let mut args = args.into_iter().map(Box::new);
+    Ok(std::array::from_fn(|_| args.next()))
+}
+
+fn parse_function_with_args<F>(
+    name: &str,
+    f: F,
+    iter: Pairs<Rule>,
+    scope: &Scope,
+) -> ParseResult<Function>
 where
     F: Fn(Box<Expression>, Option<Box<Expression>>) -> Function,
 {
     let mut args = iter
         .map(|r| {
-            let expr = parse_expr(r.into_inner())?;
+            let expr = parse_arg(r, scope)?;
             Ok(Box::new(expr))
         })
         .collect::<Vec<ParseResult<Box<Expression>>>>();
@@ -119,23 +1066,108 @@ where
         1 => Ok(f(args.remove(0)?, None)),
         2 => Ok(f(args.remove(0)?, Some(args.remove(0)?))),
         n => Err(ParseError::WrongNumberOfArguments(
-            "round".to_owned(),
+            name.to_owned(),
             "1..2".to_owned(),
             n,
         )),
     }
 }
 
-fn parse_event_attribute(mut pairs: Pairs<Rule>) -> EventAttribute {
+fn parse_if(pairs: Pairs<Rule>, scope: &Scope) -> ParseResult<Expression> {
+    let mut args = pairs
+        .map(|r| parse_expr(r.into_inner(), scope))
+        .collect::<ParseResult<Vec<Expression>>>()?;
+
+    let otherwise = args.pop().unwrap();
+    let then = args.pop().unwrap();
+    let condition = args.pop().unwrap();
+
+    Ok(Expression::Conditional {
+        branches: vec![(condition, then)],
+        otherwise: Box::new(otherwise),
+    })
+}
+
+fn parse_case(pairs: Pairs<Rule>, scope: &Scope) -> ParseResult<Expression> {
+    let mut branches = Vec::new();
+    let mut otherwise = None;
+
+    for pair in pairs {
+        match pair.as_rule() {
+            Rule::case_when => {
+                let mut exprs = pair
+                    .into_inner()
+                    .filter(|r| r.as_rule() == Rule::expr)
+                    .map(|r| parse_expr(r.into_inner(), scope));
+                let condition = exprs.next().unwrap()?;
+                let value = exprs.next().unwrap()?;
+                branches.push((condition, value));
+            }
+            Rule::expr => otherwise = Some(parse_expr(pair.into_inner(), scope)?),
+            _ => {}
+        }
+    }
+
+    Ok(Expression::Conditional {
+        branches,
+        otherwise: Box::new(otherwise.unwrap()),
+    })
+}
+
+fn parse_map(pairs: Pairs<Rule>, scope: &Scope) -> ParseResult<Expression> {
+    let mut entries: Vec<(String, Expression)> = Vec::new();
+    for entry in pairs {
+        let mut inner = entry.into_inner();
+        let key = inner.next().unwrap().into_inner().as_str().to_owned();
+        if entries.iter().any(|(k, _)| *k == key) {
+            return Err(ParseError::DuplicateKey(key));
+        }
+        let value = parse_expr(inner.next().unwrap().into_inner(), scope)?;
+        entries.push((key, value));
+    }
+    Ok(Expression::Map(entries))
+}
+
+fn parse_event_attribute(mut pairs: Pairs<Rule>) -> ParseResult<EventAttribute> {
     let mut inner = pairs.next().unwrap().into_inner();
-    match inner.next().unwrap().as_rule() {
+    let attribute = match inner.next().unwrap().as_rule() {
         Rule::event_code => EventAttribute::Code,
         Rule::event_timestamp => EventAttribute::Timestamp,
         Rule::event_properties => {
-            EventAttribute::Properties(inner.next().unwrap().as_str().to_owned())
+            let mut path = inner.map(parse_path_segment);
+            let Some(PathSegment::Key(name)) = path.next().transpose()? else {
+                unreachable!("expected a property name")
+            };
+            EventAttribute::Properties(name, path.collect::<ParseResult<_>>()?)
         }
         rule => unreachable!("expected an event attribute, got: {rule:?}"),
-    }
+    };
+    Ok(attribute)
+}
+
+fn parse_parameter(pairs: Pairs<Rule>) -> ParseResult<Expression> {
+    let mut path = pairs.map(parse_path_segment);
+    let Some(PathSegment::Key(name)) = path.next().transpose()? else {
+        unreachable!("expected a parameter name")
+    };
+    Ok(Expression::Parameter(
+        name,
+        path.collect::<ParseResult<_>>()?,
+    ))
+}
+
+fn parse_path_segment(pair: Pair<Rule>) -> ParseResult<PathSegment> {
+    let segment = match pair.as_rule() {
+        Rule::property_name => PathSegment::Key(pair.as_str().to_owned()),
+        Rule::string => PathSegment::Key(pair.into_inner().as_str().to_owned()),
+        Rule::property_index => PathSegment::Index(
+            pair.as_str()
+                .parse()
+                .map_err(|_| ParseError::InvalidIndex(pair.as_str().to_owned()))?,
+        ),
+        rule => unreachable!("expected a property path segment, got: {rule:?}"),
+    };
+    Ok(segment)
 }
 
 lazy_static::lazy_static! {
@@ -145,24 +1177,55 @@ lazy_static::lazy_static! {
 
         // Precedence is defined lowest to highest
         PrattParser::new()
+            .op(Op::infix(fallback, Left))
+            .op(Op::infix(or, Left))
+            .op(Op::infix(and, Left))
+            .op(Op::prefix(not))
+            .op(Op::infix(eq, Left)
+                | Op::infix(neq, Left)
+                | Op::infix(lt, Left)
+                | Op::infix(lte, Left)
+                | Op::infix(gt, Left)
+                | Op::infix(gte, Left)
+                | Op::infix(in_op, Left))
             // Addition and subtract have equal precedence
             .op(Op::infix(add, Left) | Op::infix(subtract, Left))
-            .op(Op::infix(multiply, Left) | Op::infix(divide, Left))
+            .op(Op::infix(multiply, Left)
+                | Op::infix(divide, Left)
+                | Op::infix(integer_divide, Left)
+                | Op::infix(modulo, Left))
+            // Unary minus binds less tightly than exponentiation, so -2 ^ 2 == -4
             .op(Op::prefix(unary_minus))
+            .op(Op::infix(power, Right))
+            .op(Op::postfix(index))
     };
 }
 
-fn parse_expr(pairs: Pairs<Rule>) -> ParseResult<Expression> {
+fn parse_expr(pairs: Pairs<Rule>, scope: &Scope) -> ParseResult<Expression> {
     PRATT_PARSER
         .map_primary(|primary| {
             let value = match primary.as_rule() {
-                Rule::function => Expression::Function(parse_function(primary.into_inner())?),
+                Rule::function => {
+                    Expression::Function(parse_function(primary.into_inner(), scope)?)
+                }
+                Rule::let_expr => parse_let(primary.into_inner(), scope)?,
+                Rule::snippet => expand_snippet(primary.into_inner(), scope)?,
+                Rule::if_expr => parse_if(primary.into_inner(), scope)?,
+                Rule::case_expr => parse_case(primary.into_inner(), scope)?,
                 Rule::decimal => Expression::Decimal(primary.as_str().parse()?),
-                Rule::expr => parse_expr(primary.into_inner())?,
+                Rule::duration => parse_duration(primary.into_inner())?,
+                Rule::expr => parse_expr(primary.into_inner(), scope)?,
                 Rule::variable => {
-                    Expression::EventAttribute(parse_event_attribute(primary.into_inner()))
+                    Expression::EventAttribute(parse_event_attribute(primary.into_inner())?)
                 }
+                Rule::parameter => parse_parameter(primary.into_inner())?,
                 Rule::string => Expression::String(primary.into_inner().as_str().to_owned()),
+                Rule::boolean_true => Expression::Boolean(true),
+                Rule::boolean_false => Expression::Boolean(false),
+                Rule::null => Expression::Null,
+                Rule::array => Expression::Array(parse_args(primary.into_inner(), scope)?),
+                Rule::map_literal => parse_map(primary.into_inner(), scope)?,
+                Rule::variable_reference => parse_variable_reference(primary.into_inner(), scope)?,
                 rule => unreachable!("Expr::parse expected atom, found {:?}", rule),
             };
             Ok(value)
@@ -173,6 +1236,19 @@ fn parse_expr(pairs: Pairs<Rule>) -> ParseResult<Expression> {
                 Rule::subtract => Operation::Subtract,
                 Rule::multiply => Operation::Multiply,
                 Rule::divide => Operation::Divide,
+                Rule::integer_divide => Operation::IntegerDivide,
+                Rule::modulo => Operation::Modulo,
+                Rule::power => Operation::Power,
+                Rule::eq => Operation::Equal,
+                Rule::neq => Operation::NotEqual,
+                Rule::lt => Operation::LessThan,
+                Rule::lte => Operation::LessThanOrEqual,
+                Rule::gt => Operation::GreaterThan,
+                Rule::gte => Operation::GreaterThanOrEqual,
+                Rule::in_op => Operation::In,
+                Rule::fallback => Operation::Fallback,
+                Rule::and => Operation::And,
+                Rule::or => Operation::Or,
                 rule => unreachable!("Expr::parse expected infix operation, found {:?}", rule),
             };
             Ok(Expression::BinOp {
@@ -181,8 +1257,16 @@ fn parse_expr(pairs: Pairs<Rule>) -> ParseResult<Expression> {
                 rhs: Box::new(rhs?),
             })
         })
+        .map_postfix(|lhs, op| match op.as_rule() {
+            Rule::index => {
+                let key = parse_expr(op.into_inner().next().unwrap().into_inner(), scope)?;
+                Ok(Expression::Index(Box::new(lhs?), Box::new(key)))
+            }
+            rule => unreachable!("Expr::parse expected postfix operation, found {:?}", rule),
+        })
         .map_prefix(|op, rhs| match op.as_rule() {
             Rule::unary_minus => Ok(Expression::UnaryMinus(Box::new(rhs?))),
+            Rule::not => Ok(Expression::Not(Box::new(rhs?))),
             rule => unreachable!("Expr::parse expected operation, found {:?}", rule),
         })
         .parse(pairs)
@@ -191,6 +1275,7 @@ fn parse_expr(pairs: Pairs<Rule>) -> ParseResult<Expression> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{function::ArgumentType, ExpressionValue};
 
     fn parse_and_compare(input: &str, expected_expr: Expression) {
         match ExpressionParser::parse_expression(input) {
@@ -228,7 +1313,7 @@ mod tests {
     fn test_parse_event_properties() {
         parse_and_compare(
             "event.properties.blah",
-            Expression::EventAttribute(EventAttribute::Properties("blah".to_owned())),
+            Expression::EventAttribute(EventAttribute::Properties("blah".to_owned(), vec![])),
         );
     }
 
@@ -356,4 +1441,1359 @@ mod tests {
             ])),
         );
     }
+
+    #[test]
+    fn test_parse_boolean() {
+        parse_and_compare("true", Expression::Boolean(true));
+        parse_and_compare("FALSE", Expression::Boolean(false));
+    }
+
+    #[test]
+    fn test_parse_comparison() {
+        parse_and_compare(
+            "event.properties.region == 'eu'",
+            Expression::BinOp {
+                lhs: Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                    "region".to_owned(),
+                    vec![],
+                ))),
+                op: Operation::Equal,
+                rhs: Box::new(Expression::String("eu".to_owned())),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_comparison_precedence() {
+        parse_and_compare(
+            "1 + 2 <= 3 * 4",
+            Expression::BinOp {
+                lhs: Box::new(Expression::BinOp {
+                    lhs: Box::new(Expression::Decimal(1.into())),
+                    op: Operation::Add,
+                    rhs: Box::new(Expression::Decimal(2.into())),
+                }),
+                op: Operation::LessThanOrEqual,
+                rhs: Box::new(Expression::BinOp {
+                    lhs: Box::new(Expression::Decimal(3.into())),
+                    op: Operation::Multiply,
+                    rhs: Box::new(Expression::Decimal(4.into())),
+                }),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_logical_precedence() {
+        parse_and_compare(
+            "not 1 > 2 or true and false",
+            Expression::BinOp {
+                lhs: Box::new(Expression::Not(Box::new(Expression::BinOp {
+                    lhs: Box::new(Expression::Decimal(1.into())),
+                    op: Operation::GreaterThan,
+                    rhs: Box::new(Expression::Decimal(2.into())),
+                }))),
+                op: Operation::Or,
+                rhs: Box::new(Expression::BinOp {
+                    lhs: Box::new(Expression::Boolean(true)),
+                    op: Operation::And,
+                    rhs: Box::new(Expression::Boolean(false)),
+                }),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_keyword_requires_boundary() {
+        let result = ExpressionParser::parse_expression("true and1");
+        assert!(matches!(result, Err(ParseError::FailedToParse(_))));
+    }
+
+    #[test]
+    fn test_parse_if() {
+        parse_and_compare(
+            "if(event.properties.region == 'eu', 2, 1)",
+            Expression::Conditional {
+                branches: vec![(
+                    Expression::BinOp {
+                        lhs: Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                            "region".to_owned(),
+                            vec![],
+                        ))),
+                        op: Operation::Equal,
+                        rhs: Box::new(Expression::String("eu".to_owned())),
+                    },
+                    Expression::Decimal(2.into()),
+                )],
+                otherwise: Box::new(Expression::Decimal(1.into())),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_if_wrong_number_of_arguments() {
+        let result = ExpressionParser::parse_expression("if(true, 1)");
+        assert!(matches!(result, Err(ParseError::FailedToParse(_))));
+    }
+
+    #[test]
+    fn test_parse_case() {
+        parse_and_compare(
+            "CASE WHEN false THEN 1 when true then 2 ELSE 3 END",
+            Expression::Conditional {
+                branches: vec![
+                    (Expression::Boolean(false), Expression::Decimal(1.into())),
+                    (Expression::Boolean(true), Expression::Decimal(2.into())),
+                ],
+                otherwise: Box::new(Expression::Decimal(3.into())),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_case_requires_else() {
+        let result = ExpressionParser::parse_expression("case when true then 1 end");
+        assert!(matches!(result, Err(ParseError::FailedToParse(_))));
+    }
+
+    #[test]
+    fn test_parse_null() {
+        parse_and_compare("NULL", Expression::Null);
+    }
+
+    #[test]
+    fn test_parse_coalesce() {
+        parse_and_compare(
+            "coalesce(event.properties.value, 0)",
+            Expression::Function(Function::Coalesce(vec![
+                Expression::EventAttribute(EventAttribute::Properties("value".to_owned(), vec![])),
+                Expression::Decimal(0.into()),
+            ])),
+        );
+    }
+
+    #[test]
+    fn test_parse_is_null() {
+        parse_and_compare(
+            "is_null(event.properties.value)",
+            Expression::Function(Function::IsNull(Box::new(Expression::EventAttribute(
+                EventAttribute::Properties("value".to_owned(), vec![]),
+            )))),
+        );
+    }
+
+    #[test]
+    fn test_parse_has_property() {
+        parse_and_compare(
+            "has_property('value')",
+            Expression::Function(Function::HasProperty(Box::new(Expression::String(
+                "value".to_owned(),
+            )))),
+        );
+    }
+
+    #[test]
+    fn test_parse_has_property_wrong_number_of_arguments() {
+        let result = ExpressionParser::parse_expression("has_property('a', 'b')");
+        assert!(matches!(
+            result,
+            Err(ParseError::WrongNumberOfArguments(name, _, 2)) if name == "has_property"
+        ));
+    }
+
+    #[test]
+    fn test_parse_modulo_and_integer_divide() {
+        parse_and_compare(
+            "7 // 2 % 3",
+            Expression::BinOp {
+                lhs: Box::new(Expression::BinOp {
+                    lhs: Box::new(Expression::Decimal(7.into())),
+                    op: Operation::IntegerDivide,
+                    rhs: Box::new(Expression::Decimal(2.into())),
+                }),
+                op: Operation::Modulo,
+                rhs: Box::new(Expression::Decimal(3.into())),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_power_is_right_associative() {
+        parse_and_compare(
+            "2 ^ 3 ^ 2",
+            Expression::BinOp {
+                lhs: Box::new(Expression::Decimal(2.into())),
+                op: Operation::Power,
+                rhs: Box::new(Expression::BinOp {
+                    lhs: Box::new(Expression::Decimal(3.into())),
+                    op: Operation::Power,
+                    rhs: Box::new(Expression::Decimal(2.into())),
+                }),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_power_precedence() {
+        parse_and_compare(
+            "-2 * 3 ^ 2",
+            Expression::BinOp {
+                lhs: Box::new(Expression::UnaryMinus(Box::new(Expression::Decimal(
+                    2.into(),
+                )))),
+                op: Operation::Multiply,
+                rhs: Box::new(Expression::BinOp {
+                    lhs: Box::new(Expression::Decimal(3.into())),
+                    op: Operation::Power,
+                    rhs: Box::new(Expression::Decimal(2.into())),
+                }),
+            },
+        );
+        parse_and_compare(
+            "-2 ^ 2",
+            Expression::UnaryMinus(Box::new(Expression::BinOp {
+                lhs: Box::new(Expression::Decimal(2.into())),
+                op: Operation::Power,
+                rhs: Box::new(Expression::Decimal(2.into())),
+            })),
+        );
+    }
+
+    #[test]
+    fn test_parse_round_wrong_number_of_arguments() {
+        for function in ["round", "ceil", "floor", "trunc"] {
+            let result = ExpressionParser::parse_expression(&format!("{function}(1, 2, 3)"));
+            assert!(
+                matches!(
+                    result,
+                    Err(ParseError::WrongNumberOfArguments(ref name, _, 3)) if name == function
+                ),
+                "{function}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_math_functions() {
+        let arg = || Box::new(Expression::Decimal(2.into()));
+        parse_and_compare("abs(2)", Expression::Function(Function::Abs(arg())));
+        parse_and_compare("SIGN(2)", Expression::Function(Function::Sign(arg())));
+        parse_and_compare("sqrt(2)", Expression::Function(Function::Sqrt(arg())));
+        parse_and_compare("ln(2)", Expression::Function(Function::Ln(arg())));
+        parse_and_compare("Log10(2)", Expression::Function(Function::Log10(arg())));
+        parse_and_compare("exp(2)", Expression::Function(Function::Exp(arg())));
+        parse_and_compare(
+            "trunc(2, 2)",
+            Expression::Function(Function::Trunc(arg(), Some(arg()))),
+        );
+    }
+
+    #[test]
+    fn test_parse_clamp() {
+        parse_and_compare(
+            "clamp(event.properties.value, 0, 100)",
+            Expression::Function(Function::Clamp(
+                Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                    "value".to_owned(),
+                    vec![],
+                ))),
+                Box::new(Expression::Decimal(0.into())),
+                Box::new(Expression::Decimal(100.into())),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_clamp_wrong_number_of_arguments() {
+        let result = ExpressionParser::parse_expression("clamp(1, 2)");
+        assert!(matches!(
+            result,
+            Err(ParseError::WrongNumberOfArguments(name, expected, 2)) if name == "clamp" && expected == "3"
+        ));
+    }
+
+    #[test]
+    fn test_parse_string_functions() {
+        let arg = || Box::new(Expression::String("a".to_owned()));
+        parse_and_compare("upper('a')", Expression::Function(Function::Upper(arg())));
+        parse_and_compare("LOWER('a')", Expression::Function(Function::Lower(arg())));
+        parse_and_compare("trim('a')", Expression::Function(Function::Trim(arg())));
+        parse_and_compare("length('a')", Expression::Function(Function::Length(arg())));
+        parse_and_compare(
+            "replace('a', 'a', 'a')",
+            Expression::Function(Function::Replace(arg(), arg(), arg())),
+        );
+        parse_and_compare(
+            "split_part('a', 'a', 'a')",
+            Expression::Function(Function::SplitPart(arg(), arg(), arg())),
+        );
+    }
+
+    #[test]
+    fn test_parse_substring() {
+        parse_and_compare(
+            "substring('abc', 2)",
+            Expression::Function(Function::Substring(
+                Box::new(Expression::String("abc".to_owned())),
+                Box::new(Expression::Decimal(2.into())),
+                None,
+            )),
+        );
+        parse_and_compare(
+            "substring('abc', 2, 1)",
+            Expression::Function(Function::Substring(
+                Box::new(Expression::String("abc".to_owned())),
+                Box::new(Expression::Decimal(2.into())),
+                Some(Box::new(Expression::Decimal(1.into()))),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_substring_wrong_number_of_arguments() {
+        let result = ExpressionParser::parse_expression("substring('abc')");
+        assert!(matches!(
+            result,
+            Err(ParseError::WrongNumberOfArguments(name, _, 1)) if name == "substring"
+        ));
+    }
+
+    #[test]
+    fn test_parse_concat_ws() {
+        parse_and_compare(
+            "concat_ws('-', 'a', 'b')",
+            Expression::Function(Function::ConcatWs(
+                Box::new(Expression::String("-".to_owned())),
+                vec![
+                    Expression::String("a".to_owned()),
+                    Expression::String("b".to_owned()),
+                ],
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_matches() {
+        parse_and_compare(
+            "matches(event.code, '^api_[a-z]+$')",
+            Expression::Function(Function::Matches(
+                Box::new(Expression::EventAttribute(EventAttribute::Code)),
+                Pattern::new("^api_[a-z]+$").unwrap(),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_regex_extract() {
+        parse_and_compare(
+            "regex_extract('a1', '([a-z])([0-9])', 2)",
+            Expression::Function(Function::RegexExtract(
+                Box::new(Expression::String("a1".to_owned())),
+                Pattern::new("([a-z])([0-9])").unwrap(),
+                Box::new(Expression::Decimal(2.into())),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_regex_replace() {
+        parse_and_compare(
+            "regex_replace('a1', '[0-9]', 'x')",
+            Expression::Function(Function::RegexReplace(
+                Box::new(Expression::String("a1".to_owned())),
+                Pattern::new("[0-9]").unwrap(),
+                Box::new(Expression::String("x".to_owned())),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_regex() {
+        let result = ExpressionParser::parse_expression("matches('a', '(unclosed')");
+        assert!(matches!(result, Err(ParseError::InvalidRegex(_))));
+    }
+
+    #[test]
+    fn test_parse_backreferences_are_not_supported() {
+        let result = ExpressionParser::parse_expression("matches('aa', '(a)\\1')");
+        assert!(matches!(result, Err(ParseError::InvalidRegex(_))));
+    }
+
+    #[test]
+    fn test_parse_non_literal_pattern() {
+        let result = ExpressionParser::parse_expression("matches('a', event.code)");
+        assert!(matches!(
+            result,
+            Err(ParseError::ExpectedPatternLiteral(name)) if name == "matches"
+        ));
+    }
+
+    #[test]
+    fn test_parse_calendar_functions() {
+        let arg = || Box::new(Expression::EventAttribute(EventAttribute::Timestamp));
+        parse_and_compare(
+            "year(event.timestamp)",
+            Expression::Function(Function::Year(arg())),
+        );
+        parse_and_compare(
+            "month(event.timestamp)",
+            Expression::Function(Function::Month(arg())),
+        );
+        parse_and_compare(
+            "day(event.timestamp)",
+            Expression::Function(Function::Day(arg())),
+        );
+        parse_and_compare(
+            "hour(event.timestamp)",
+            Expression::Function(Function::Hour(arg())),
+        );
+        parse_and_compare(
+            "day_of_week(event.timestamp)",
+            Expression::Function(Function::DayOfWeek(arg())),
+        );
+    }
+
+    #[test]
+    fn test_parse_date_trunc() {
+        parse_and_compare(
+            "date_trunc('month', event.timestamp)",
+            Expression::Function(Function::DateTrunc(
+                DateUnit::Month,
+                Box::new(Expression::EventAttribute(EventAttribute::Timestamp)),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_date_trunc_invalid_unit() {
+        let result = ExpressionParser::parse_expression("date_trunc('decade', event.timestamp)");
+        assert!(matches!(result, Err(ParseError::InvalidDateUnit(_))));
+
+        let result = ExpressionParser::parse_expression("date_trunc(event.code, event.timestamp)");
+        assert!(matches!(
+            result,
+            Err(ParseError::ExpectedStringLiteral(name)) if name == "date_trunc"
+        ));
+    }
+
+    #[test]
+    fn test_parse_time_between() {
+        parse_and_compare(
+            "time_between(event.timestamp, '22:00', '06:00')",
+            Expression::Function(Function::TimeBetween(
+                Box::new(Expression::EventAttribute(EventAttribute::Timestamp)),
+                NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_time_between_invalid_time() {
+        let result =
+            ExpressionParser::parse_expression("time_between(event.timestamp, '9am', '17:00')");
+        assert!(matches!(result, Err(ParseError::InvalidTimeOfDay(_))));
+    }
+
+    #[test]
+    fn test_parse_datetime_functions() {
+        parse_and_compare(
+            "parse_datetime(event.properties.started_at)",
+            Expression::Function(Function::ParseDateTime(
+                Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                    "started_at".into(),
+                    vec![],
+                ))),
+                None,
+            )),
+        );
+        parse_and_compare(
+            "format_datetime(to_epoch(event.timestamp), '%Y-%m')",
+            Expression::Function(Function::FormatDateTime(
+                Box::new(Expression::Function(Function::ToEpoch(Box::new(
+                    Expression::EventAttribute(EventAttribute::Timestamp),
+                )))),
+                Box::new(Expression::String("%Y-%m".into())),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_format_datetime_requires_format() {
+        let result = ExpressionParser::parse_expression("format_datetime(event.timestamp)");
+        assert!(matches!(
+            result,
+            Err(ParseError::WrongNumberOfArguments(name, _, 1)) if name == "format_datetime"
+        ));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let cases = [("30s", 30), ("15m", 900), ("1h", 3600), ("7d", 604800)];
+        for (input, seconds) in cases {
+            parse_and_compare(input, Expression::Duration(seconds.into()));
+        }
+        parse_and_compare("1.5h", Expression::Duration(5400.into()));
+    }
+
+    #[test]
+    fn test_parse_duration_arithmetic() {
+        parse_and_compare(
+            "event.timestamp - 1h",
+            Expression::BinOp {
+                lhs: Box::new(Expression::EventAttribute(EventAttribute::Timestamp)),
+                op: Operation::Subtract,
+                rhs: Box::new(Expression::Duration(3600.into())),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_duration_unknown_unit() {
+        let result = ExpressionParser::parse_expression("1w");
+        assert!(matches!(result, Err(ParseError::FailedToParse(_))));
+    }
+
+    #[test]
+    fn test_parse_duration_conversions() {
+        parse_and_compare(
+            "to_hours(30m)",
+            Expression::Function(Function::ToHours(Box::new(Expression::Duration(
+                1800.into(),
+            )))),
+        );
+    }
+
+    #[test]
+    fn test_parse_conversion_functions() {
+        let property = || {
+            Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                "id".into(),
+                vec![],
+            )))
+        };
+        parse_and_compare(
+            "to_number(event.properties.id)",
+            Expression::Function(Function::ToNumber(property())),
+        );
+        parse_and_compare(
+            "TO_INTEGER(event.properties.id)",
+            Expression::Function(Function::ToInteger(property())),
+        );
+        parse_and_compare(
+            "to_string(event.properties.id)",
+            Expression::Function(Function::ToString(property())),
+        );
+    }
+
+    #[test]
+    fn test_parse_bracket_property() {
+        parse_and_compare(
+            "event.properties['my-key. 1']",
+            Expression::EventAttribute(EventAttribute::Properties("my-key. 1".to_owned(), vec![])),
+        );
+    }
+
+    #[test]
+    fn test_parse_nested_property() {
+        parse_and_compare(
+            "event.properties.usage.cpu['total seconds']",
+            Expression::EventAttribute(EventAttribute::Properties(
+                "usage".to_owned(),
+                vec![
+                    PathSegment::Key("cpu".to_owned()),
+                    PathSegment::Key("total seconds".to_owned()),
+                ],
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_array_index() {
+        parse_and_compare(
+            "event.properties.items[0].qty * 2",
+            Expression::BinOp {
+                lhs: Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                    "items".to_owned(),
+                    vec![PathSegment::Index(0), PathSegment::Key("qty".to_owned())],
+                ))),
+                op: Operation::Multiply,
+                rhs: Box::new(Expression::Decimal(2.into())),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_property_path_errors() {
+        for input in ["event.properties[0]", "event.properties.items."] {
+            assert!(
+                matches!(
+                    ExpressionParser::parse_expression(input),
+                    Err(ParseError::FailedToParse(_))
+                ),
+                "{input}"
+            );
+        }
+        assert!(matches!(
+            ExpressionParser::parse_expression("event.properties.items[99999999999999999999999]"),
+            Err(ParseError::InvalidIndex(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_aggregate_functions() {
+        let items = || {
+            Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                "items".into(),
+                vec![],
+            )))
+        };
+        parse_and_compare(
+            "sum(event.properties.items)",
+            Expression::Function(Function::Sum(items())),
+        );
+        parse_and_compare(
+            "JOIN(event.properties.items, ',')",
+            Expression::Function(Function::Join(
+                items(),
+                Box::new(Expression::String(",".into())),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_lambda() {
+        let variable = |name: &str, key: &str| {
+            Box::new(Expression::Variable(
+                name.into(),
+                vec![PathSegment::Key(key.into())],
+            ))
+        };
+        parse_and_compare(
+            "map(event.properties.items, x -> x.qty * x.price)",
+            Expression::Function(Function::Map(
+                Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                    "items".into(),
+                    vec![],
+                ))),
+                Lambda {
+                    parameter: "x".into(),
+                    body: Box::new(Expression::BinOp {
+                        lhs: variable("x", "qty"),
+                        op: Operation::Multiply,
+                        rhs: variable("x", "price"),
+                    }),
+                },
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_nested_lambdas() {
+        let result = ExpressionParser::parse_expression(
+            "map(event.properties.orders, order -> sum(filter(order.items, item -> item.qty > order.min)))",
+        );
+        assert!(result.is_ok(), "{result:?}");
+    }
+
+    #[test]
+    fn test_parse_unknown_variable() {
+        for input in [
+            "x + 1",
+            "map(event.properties.items, x -> y)",
+            "sum(map(event.properties.items, x -> x)) + x",
+        ] {
+            assert!(
+                matches!(
+                    ExpressionParser::parse_expression(input),
+                    Err(ParseError::UnknownVariable(_))
+                ),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_lambda_errors() {
+        assert!(matches!(
+            ExpressionParser::parse_expression("sum(x -> x)"),
+            Err(ParseError::UnexpectedLambda)
+        ));
+        assert!(matches!(
+            ExpressionParser::parse_expression("round(x -> x, 2)"),
+            Err(ParseError::UnexpectedLambda)
+        ));
+        assert!(matches!(
+            ExpressionParser::parse_expression("map(event.properties.items, 1)"),
+            Err(ParseError::ExpectedLambda(_))
+        ));
+        assert!(matches!(
+            ExpressionParser::parse_expression("filter(event.properties.items)"),
+            Err(ParseError::WrongNumberOfArguments(_, _, 1))
+        ));
+        for input in [
+            "map(event.properties.items, null -> 1)",
+            "map(event.properties.items, event -> 1)",
+            "map(event.properties.items, 1x -> 1)",
+        ] {
+            assert!(
+                matches!(
+                    ExpressionParser::parse_expression(input),
+                    Err(ParseError::FailedToParse(_))
+                ),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_array_literal() {
+        parse_and_compare("[]", Expression::Array(vec![]));
+        parse_and_compare(
+            "[1, 'a', [null]]",
+            Expression::Array(vec![
+                Expression::Decimal(1.into()),
+                Expression::String("a".into()),
+                Expression::Array(vec![Expression::Null]),
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_parse_in() {
+        parse_and_compare(
+            "event.properties.region in ['eu', 'uk'] and true",
+            Expression::BinOp {
+                lhs: Box::new(Expression::BinOp {
+                    lhs: Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                        "region".into(),
+                        vec![],
+                    ))),
+                    op: Operation::In,
+                    rhs: Box::new(Expression::Array(vec![
+                        Expression::String("eu".into()),
+                        Expression::String("uk".into()),
+                    ])),
+                }),
+                op: Operation::And,
+                rhs: Box::new(Expression::Boolean(true)),
+            },
+        );
+        parse_and_compare(
+            "1 + 1 IN [2]",
+            Expression::BinOp {
+                lhs: Box::new(Expression::BinOp {
+                    lhs: Box::new(Expression::Decimal(1.into())),
+                    op: Operation::Add,
+                    rhs: Box::new(Expression::Decimal(1.into())),
+                }),
+                op: Operation::In,
+                rhs: Box::new(Expression::Array(vec![Expression::Decimal(2.into())])),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_array_literal_errors() {
+        for input in ["[1,]", "[1 2]", "1 in", "1 inside [1]"] {
+            assert!(
+                matches!(
+                    ExpressionParser::parse_expression(input),
+                    Err(ParseError::FailedToParse(_))
+                ),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_map_lookup() {
+        parse_and_compare(
+            "{'eu': 1.2, 'us': 1}[event.properties.region] ?? 1",
+            Expression::BinOp {
+                lhs: Box::new(Expression::Index(
+                    Box::new(Expression::Map(vec![
+                        ("eu".into(), Expression::Decimal("1.2".parse().unwrap())),
+                        ("us".into(), Expression::Decimal(1.into())),
+                    ])),
+                    Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                        "region".into(),
+                        vec![],
+                    ))),
+                )),
+                op: Operation::Fallback,
+                rhs: Box::new(Expression::Decimal(1.into())),
+            },
+        );
+        parse_and_compare("{}", Expression::Map(vec![]));
+    }
+
+    #[test]
+    fn test_parse_index_of_property() {
+        // With spaces or an expression, the brackets index the value instead of
+        // being part of the path
+        parse_and_compare(
+            "event.properties.items[ 0 ]",
+            Expression::Index(
+                Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                    "items".into(),
+                    vec![],
+                ))),
+                Box::new(Expression::Decimal(0.into())),
+            ),
+        );
+    }
+
+    #[test]
+    fn test_parse_index_precedence() {
+        parse_and_compare(
+            "-[1][0] ^ 2",
+            Expression::UnaryMinus(Box::new(Expression::BinOp {
+                lhs: Box::new(Expression::Index(
+                    Box::new(Expression::Array(vec![Expression::Decimal(1.into())])),
+                    Box::new(Expression::Decimal(0.into())),
+                )),
+                op: Operation::Power,
+                rhs: Box::new(Expression::Decimal(2.into())),
+            })),
+        );
+        // `??` binds less tightly than any other operator
+        parse_and_compare(
+            "null ?? 1 + 1",
+            Expression::BinOp {
+                lhs: Box::new(Expression::Null),
+                op: Operation::Fallback,
+                rhs: Box::new(Expression::BinOp {
+                    lhs: Box::new(Expression::Decimal(1.into())),
+                    op: Operation::Add,
+                    rhs: Box::new(Expression::Decimal(1.into())),
+                }),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_map_duplicate_keys() {
+        assert!(matches!(
+            ExpressionParser::parse_expression("{'eu': 1, 'us': 2, 'eu': 3}"),
+            Err(ParseError::DuplicateKey(key)) if key == "eu"
+        ));
+    }
+
+    #[test]
+    fn test_parse_map_literal_errors() {
+        for input in ["{eu: 1}", "{'eu' 1}", "{'eu': 1,}", "{1: 1}"] {
+            assert!(
+                matches!(
+                    ExpressionParser::parse_expression(input),
+                    Err(ParseError::FailedToParse(_))
+                ),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_let() {
+        parse_and_compare(
+            "let gb = event.properties.bytes / 1024; round(gb * 0.02, 2)",
+            Expression::Let {
+                name: "gb".into(),
+                value: Box::new(Expression::BinOp {
+                    lhs: Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                        "bytes".into(),
+                        vec![],
+                    ))),
+                    op: Operation::Divide,
+                    rhs: Box::new(Expression::Decimal(1024.into())),
+                }),
+                body: Box::new(Expression::Function(Function::Round(
+                    Box::new(Expression::BinOp {
+                        lhs: Box::new(Expression::Variable("gb".into(), vec![])),
+                        op: Operation::Multiply,
+                        rhs: Box::new(Expression::Decimal("0.02".parse().unwrap())),
+                    }),
+                    Some(Box::new(Expression::Decimal(2.into()))),
+                ))),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_nested_let() {
+        parse_and_compare(
+            "LET a = 1; let b = a + 1; b",
+            Expression::Let {
+                name: "a".into(),
+                value: Box::new(Expression::Decimal(1.into())),
+                body: Box::new(Expression::Let {
+                    name: "b".into(),
+                    value: Box::new(Expression::BinOp {
+                        lhs: Box::new(Expression::Variable("a".into(), vec![])),
+                        op: Operation::Add,
+                        rhs: Box::new(Expression::Decimal(1.into())),
+                    }),
+                    body: Box::new(Expression::Variable("b".into(), vec![])),
+                }),
+            },
+        );
+        assert!(ExpressionParser::parse_expression(
+            "(let a = 1; a) + (let a = 2; a) + sum(map(event.properties.items, a -> a))"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_parse_let_unknown_variable() {
+        for input in [
+            "let a = a; 1",
+            "(let a = 1; a) + a",
+            "let a = b; let b = 1; a",
+        ] {
+            assert!(
+                matches!(
+                    ExpressionParser::parse_expression(input),
+                    Err(ParseError::UnknownVariable(_))
+                ),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_shadowed_variable() {
+        for input in [
+            "let a = 1; let a = 2; a",
+            "let x = 1; map(event.properties.items, x -> x)",
+            "map(event.properties.items, x -> let x = 1; x)",
+            "map(event.properties.items, x -> map(x.items, x -> x))",
+        ] {
+            assert!(
+                matches!(
+                    ExpressionParser::parse_expression(input),
+                    Err(ParseError::ShadowedVariable(name)) if name == "x" || name == "a"
+                ),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_let_errors() {
+        for input in [
+            "let a = 1 a",
+            "let a = 1;",
+            "let 1 = 1; 1",
+            "let let = 1; 1",
+            "let a == 1; a",
+        ] {
+            assert!(
+                matches!(
+                    ExpressionParser::parse_expression(input),
+                    Err(ParseError::FailedToParse(_))
+                ),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_parameter() {
+        parse_and_compare(
+            "params.rate * 2",
+            Expression::BinOp {
+                lhs: Box::new(Expression::Parameter("rate".into(), vec![])),
+                op: Operation::Multiply,
+                rhs: Box::new(Expression::Decimal(2.into())),
+            },
+        );
+        parse_and_compare(
+            "params['regional rates'].eu",
+            Expression::Parameter("regional rates".into(), vec![PathSegment::Key("eu".into())]),
+        );
+    }
+
+    #[test]
+    fn test_parse_parameter_errors() {
+        for input in [
+            "params",
+            "params.",
+            "map(event.properties.items, params -> 1)",
+        ] {
+            assert!(
+                matches!(
+                    ExpressionParser::parse_expression(input),
+                    Err(ParseError::FailedToParse(_))
+                ),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parameters() {
+        let expr = ExpressionParser::parse_expression(
+            "let base = params.base; \
+             sum(map(event.properties.items, x -> x.qty * params.rates[x.region] ?? params.rate)) \
+             + if(params.enabled, base, 0) + coalesce(params.base, 1)",
+        )
+        .unwrap();
+        assert_eq!(expr.parameters(), vec!["base", "enabled", "rate", "rates"]);
+
+        let expr = ExpressionParser::parse_expression("event.properties.rate").unwrap();
+        assert!(expr.parameters().is_empty());
+    }
+
+    fn snippets() -> SnippetRegistry {
+        let mut snippets = SnippetRegistry::new();
+        snippets
+            .define("bytes_to_gb", &["x"], "x / 1024 ^ 3")
+            .unwrap();
+        snippets.define("add", &["x", "y"], "x + y").unwrap();
+        snippets
+            .define(
+                "storage_fee",
+                &["bytes"],
+                "round(@bytes_to_gb(bytes) * params.rate, 2)",
+            )
+            .unwrap();
+        snippets.define("a", &[], "@b() + 1").unwrap();
+        snippets.define("b", &[], "@c()").unwrap();
+        snippets.define("c", &[], "@a()").unwrap();
+        snippets.define("itself", &["x"], "@itself(x)").unwrap();
+        snippets.define("caller", &[], "total").unwrap();
+        snippets
+    }
+
+    fn parse_with_snippets(input: &str) -> ParseResult<Expression> {
+        ExpressionParser::parse_expression_with_snippets(input, &snippets())
+    }
+
+    fn let_expr(name: &str, value: Expression, body: Expression) -> Expression {
+        Expression::Let {
+            name: name.into(),
+            value: Box::new(value),
+            body: Box::new(body),
+        }
+    }
+
+    fn variable(name: &str) -> Expression {
+        Expression::Variable(name.into(), vec![])
+    }
+
+    #[test]
+    fn test_parse_snippet() {
+        assert_eq!(
+            parse_with_snippets("@bytes_to_gb(event.properties.bytes)").unwrap(),
+            let_expr(
+                "@bytes_to_gb.x",
+                Expression::EventAttribute(EventAttribute::Properties("bytes".into(), vec![])),
+                let_expr(
+                    "x",
+                    variable("@bytes_to_gb.x"),
+                    Expression::BinOp {
+                        lhs: Box::new(variable("x")),
+                        op: Operation::Divide,
+                        rhs: Box::new(Expression::BinOp {
+                            lhs: Box::new(Expression::Decimal(1024.into())),
+                            op: Operation::Power,
+                            rhs: Box::new(Expression::Decimal(3.into())),
+                        }),
+                    },
+                ),
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_snippet_arguments_are_not_captured() {
+        // The argument `x` of the caller is not the parameter `x` of the snippet
+        assert_eq!(
+            parse_with_snippets("let x = 2; @add(1, x)").unwrap(),
+            let_expr(
+                "x",
+                Expression::Decimal(2.into()),
+                let_expr(
+                    "@add.x",
+                    Expression::Decimal(1.into()),
+                    let_expr(
+                        "@add.y",
+                        variable("x"),
+                        let_expr(
+                            "x",
+                            variable("@add.x"),
+                            let_expr(
+                                "y",
+                                variable("@add.y"),
+                                Expression::BinOp {
+                                    lhs: Box::new(variable("x")),
+                                    op: Operation::Add,
+                                    rhs: Box::new(variable("y")),
+                                },
+                            ),
+                        ),
+                    ),
+                ),
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_nested_snippets() {
+        let expr =
+            parse_with_snippets("@storage_fee(event.properties.bytes) + @add(1, @add(2, 3))")
+                .unwrap();
+        assert_eq!(expr.parameters(), vec!["rate"]);
+    }
+
+    #[test]
+    fn test_parse_snippet_errors() {
+        assert!(matches!(
+            parse_with_snippets("@unknown(1)"),
+            Err(ParseError::UndefinedSnippet(name)) if name == "unknown"
+        ));
+        assert!(matches!(
+            ExpressionParser::parse_expression("@bytes_to_gb(1)"),
+            Err(ParseError::UndefinedSnippet(name)) if name == "bytes_to_gb"
+        ));
+        assert!(matches!(
+            parse_with_snippets("1 + @a()"),
+            Err(ParseError::RecursiveSnippet(chain)) if chain == "a -> b -> c -> a"
+        ));
+        assert!(matches!(
+            parse_with_snippets("@itself(1)"),
+            Err(ParseError::RecursiveSnippet(chain)) if chain == "itself -> itself"
+        ));
+        assert!(matches!(
+            parse_with_snippets("@add(1)"),
+            Err(ParseError::WrongNumberOfArguments(name, expected, 1)) if name == "@add" && expected == "2"
+        ));
+        // Snippets can't see the variables of the caller
+        assert!(matches!(
+            parse_with_snippets("let total = 1; @caller()"),
+            Err(ParseError::UnknownVariable(name)) if name == "total"
+        ));
+
+        for input in ["@add", "@ add(1, 2)", "@add(1,)", "@if(1)"] {
+            assert!(
+                matches!(
+                    parse_with_snippets(input),
+                    Err(ParseError::FailedToParse(_))
+                ),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_snippet_limits() {
+        // Every snippet uses the previous one twice, so @double30 expands to
+        // more than a billion nodes
+        let mut snippets = SnippetRegistry::new();
+        snippets.define("double0", &["x"], "x + 1").unwrap();
+        snippets.define("broken0", &["x"], "@missing(x)").unwrap();
+        for i in 1..=30 {
+            let body = format!("@broken{0}(x) + @broken{0}(x)", i - 1);
+            snippets
+                .define(&format!("broken{i}"), &["x"], &body)
+                .unwrap();
+            let body = format!("@double{0}(x) + @double{0}(x)", i - 1);
+            snippets
+                .define(&format!("double{i}"), &["x"], &body)
+                .unwrap();
+        }
+        let parse = |input| ExpressionParser::parse_expression_with_snippets(input, &snippets);
+
+        assert!(matches!(
+            parse("@double8(1)").unwrap(),
+            Expression::Let { .. }
+        ));
+        assert!(matches!(
+            parse("@double30(1)"),
+            Err(ParseError::SnippetTooLarge(_))
+        ));
+        assert!(matches!(
+            parse("@broken30(1)"),
+            Err(ParseError::UndefinedSnippet(name)) if name == "missing"
+        ));
+
+        let mut snippets = SnippetRegistry::new();
+        snippets.define("next0", &["x"], "x + 1").unwrap();
+        for i in 1..=40 {
+            let body = format!("@next{}(x) + 1", i - 1);
+            snippets.define(&format!("next{i}"), &["x"], &body).unwrap();
+        }
+        let parse = |input| ExpressionParser::parse_expression_with_snippets(input, &snippets);
+
+        assert!(parse("@next31(1)").is_ok());
+        assert!(matches!(
+            parse("@next32(1)"),
+            Err(ParseError::SnippetTooDeep(chain)) if chain.starts_with("next32 -> next31") && chain.ends_with("-> next0")
+        ));
+    }
+
+    fn functions() -> FunctionRegistry {
+        let mut functions = FunctionRegistry::new();
+        functions
+            .register(
+                "tier_price",
+                &[ArgumentType::String, ArgumentType::Number],
+                |_| Ok(ExpressionValue::Null),
+            )
+            .unwrap();
+        functions
+    }
+
+    #[test]
+    fn test_parse_function_name_case_insensitive() {
+        parse_and_compare(
+            "rOuNd(1)",
+            Expression::Function(Function::Round(
+                Box::new(Expression::Decimal(1.into())),
+                None,
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_user_function() {
+        let functions = functions();
+        let expr = ExpressionParser::parse_expression_with_functions(
+            "Tier_Price(event.properties.plan, 2)",
+            &functions,
+        )
+        .unwrap();
+        assert_eq!(
+            expr,
+            Expression::Function(Function::User(
+                functions.get("tier_price").unwrap().clone(),
+                vec![
+                    Expression::EventAttribute(EventAttribute::Properties("plan".into(), vec![])),
+                    Expression::Decimal(2.into()),
+                ],
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_user_function_errors() {
+        let functions = functions();
+        let parse = |input| ExpressionParser::parse_expression_with_functions(input, &functions);
+
+        assert!(matches!(
+            parse("tier_prices('a', 1)"),
+            Err(ParseError::UnknownFunction(name)) if name == "tier_prices"
+        ));
+        assert!(matches!(
+            ExpressionParser::parse_expression("tier_price('a', 1)"),
+            Err(ParseError::UnknownFunction(name)) if name == "tier_price"
+        ));
+        assert!(matches!(
+            parse("tier_price('a')"),
+            Err(ParseError::WrongNumberOfArguments(name, expected, 1)) if name == "tier_price" && expected == "2"
+        ));
+        assert!(matches!(
+            parse("tier_price('a', x -> x)"),
+            Err(ParseError::UnexpectedLambda)
+        ));
+        assert!(matches!(
+            parse("ceil()"),
+            Err(ParseError::WrongNumberOfArguments(name, _, 0)) if name == "ceil"
+        ));
+    }
+
+    #[test]
+    fn test_parse_pricing_functions() {
+        let units = || {
+            Box::new(Expression::EventAttribute(EventAttribute::Properties(
+                "units".into(),
+                vec![],
+            )))
+        };
+        let decimal = |n: i32| Box::new(Expression::Decimal(n.into()));
+
+        parse_and_compare(
+            "graduated(event.properties.units, [[0, 100, 5], [100, null, 3]])",
+            Expression::Function(Function::Graduated(
+                units(),
+                Box::new(Expression::Array(vec![
+                    Expression::Array(vec![*decimal(0), *decimal(100), *decimal(5)]),
+                    Expression::Array(vec![*decimal(100), Expression::Null, *decimal(3)]),
+                ])),
+            )),
+        );
+        parse_and_compare(
+            "Package(event.properties.units, 100, 5)",
+            Expression::Function(Function::Package(units(), decimal(100), decimal(5), None)),
+        );
+        parse_and_compare(
+            "PERCENTAGE(event.properties.units, 2, 1, 5)",
+            Expression::Function(Function::Percentage(
+                units(),
+                decimal(2),
+                Some(decimal(1)),
+                Some(decimal(5)),
+                None,
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_pricing_functions_wrong_number_of_arguments() {
+        for (input, name, expected, provided) in [
+            ("volume(1)", "volume", "2", 1),
+            ("package(1, 2)", "package", "3..4", 2),
+            ("percentage(1, 2, 3, 4, 5, 6)", "percentage", "2..5", 6),
+        ] {
+            assert!(
+                matches!(
+                    ExpressionParser::parse_expression(input),
+                    Err(ParseError::WrongNumberOfArguments(n, e, p))
+                        if n == name && e == expected && p == provided
+                ),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_money_functions() {
+        let amount = || Box::new(Expression::Decimal("12.5".parse().unwrap()));
+        let currency = || Box::new(Expression::String("EUR".into()));
+
+        parse_and_compare(
+            "money(12.5, 'EUR')",
+            Expression::Function(Function::Money(amount(), currency())),
+        );
+        parse_and_compare(
+            "to_cents(money(12.5, 'EUR'))",
+            Expression::Function(Function::ToCents(Box::new(Expression::Function(
+                Function::Money(amount(), currency()),
+            )))),
+        );
+        parse_and_compare(
+            "FROM_CENTS(12.5, 'EUR')",
+            Expression::Function(Function::FromCents(amount(), currency())),
+        );
+        assert!(matches!(
+            ExpressionParser::parse_expression("money(12.5)"),
+            Err(ParseError::WrongNumberOfArguments(name, _, 1)) if name == "money"
+        ));
+    }
+
+    #[test]
+    fn test_parse_convert() {
+        parse_and_compare(
+            "convert(12.5, 'USD', 'EUR')",
+            Expression::Function(Function::Convert(
+                Box::new(Expression::Decimal("12.5".parse().unwrap())),
+                Box::new(Expression::String("USD".into())),
+                Box::new(Expression::String("EUR".into())),
+            )),
+        );
+        assert!(matches!(
+            ExpressionParser::parse_expression("convert(12.5, 'EUR')"),
+            Err(ParseError::WrongNumberOfArguments(name, _, 2)) if name == "convert"
+        ));
+    }
 }
@@ -0,0 +1,264 @@
+use bigdecimal::{BigDecimal, RoundingMode, Signed, Zero};
+
+use crate::evaluate::{EvaluationResult, ExpressionError};
+
+/// A range of units and its prices, ranges are contiguous so a tier starts
+/// where the previous one ends
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tier {
+    pub from: BigDecimal,
+    /// The end of the range, `None` for the last tier which is unbounded
+    pub to: Option<BigDecimal>,
+    pub per_unit: BigDecimal,
+    pub flat: BigDecimal,
+}
+
+/// Checks that the tiers are in order and contiguous, and that all units
+/// fall in a tier after the start of the first one
+pub fn validate_tiers(tiers: &[Tier]) -> EvaluationResult<()> {
+    if tiers.is_empty() {
+        return Err(ExpressionError::InvalidTier("no tiers".to_owned()));
+    }
+    for (i, tier) in tiers.iter().enumerate() {
+        match (&tier.to, tiers.get(i + 1)) {
+            (Some(to), _) if *to <= tier.from => {
+                return Err(ExpressionError::InvalidTier(format!(
+                    "tier {} ends before it starts",
+                    i + 1
+                )))
+            }
+            (Some(to), Some(next)) if next.from != *to => {
+                return Err(ExpressionError::InvalidTier(format!(
+                    "tier {} doesn't start where tier {} ends",
+                    i + 2,
+                    i + 1
+                )))
+            }
+            (Some(_), None) => {
+                return Err(ExpressionError::InvalidTier(
+                    "the last tier must be unbounded".to_owned(),
+                ))
+            }
+            (None, Some(_)) => {
+                return Err(ExpressionError::InvalidTier(format!(
+                    "only the last tier can be unbounded, not tier {}",
+                    i + 1
+                )))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Each tier prices the units that fall in its range, e.g. with
+/// `[[0, 100, 0.5], [100, null, 0.3]]` 150 units cost `100 * 0.5 + 50 * 0.3`.
+/// The flat fee of a tier is charged once any unit falls in it.
+pub fn graduated(units: &BigDecimal, tiers: &[Tier]) -> EvaluationResult<BigDecimal> {
+    validate_tiers(tiers)?;
+
+    let mut amount = BigDecimal::zero();
+    for tier in tiers {
+        if *units <= tier.from {
+            break;
+        }
+        let end = match &tier.to {
+            Some(to) if to < units => to,
+            _ => units,
+        };
+        amount += (end - &tier.from) * &tier.per_unit + &tier.flat;
+    }
+    Ok(amount)
+}
+
+/// All the units are priced by the tier the total falls in, e.g. with
+/// `[[0, 100, 0.5], [100, null, 0.3]]` 150 units cost `150 * 0.3`. The end
+/// of a tier is included in it, units before the first tier cost nothing.
+pub fn volume(units: &BigDecimal, tiers: &[Tier]) -> EvaluationResult<BigDecimal> {
+    validate_tiers(tiers)?;
+
+    if *units < tiers[0].from {
+        return Ok(BigDecimal::zero());
+    }
+    let tier = tiers
+        .iter()
+        .find(|tier| tier.to.as_ref().is_none_or(|to| units <= to))
+        .expect("the last tier is unbounded");
+    Ok(units * &tier.per_unit + &tier.flat)
+}
+
+/// Units are sold in packages of `size` at `price` each, a started package is
+/// charged in full. The first `free_units` are free.
+pub fn package(
+    units: &BigDecimal,
+    size: &BigDecimal,
+    price: &BigDecimal,
+    free_units: &BigDecimal,
+) -> EvaluationResult<BigDecimal> {
+    if size.is_zero() {
+        return Err(ExpressionError::DivisionByZero);
+    }
+    if size.is_negative() {
+        return Err(ExpressionError::Undefined(
+            "package".to_owned(),
+            size.clone(),
+        ));
+    }
+
+    let billable = units - free_units;
+    if !billable.is_positive() {
+        return Ok(BigDecimal::zero());
+    }
+    let packages = (billable / size).with_scale_round(0, RoundingMode::Ceiling);
+    Ok(packages * price)
+}
+
+/// `rate` percent of the amount plus a fixed fee, kept between the optional
+/// minimum and maximum
+pub fn percentage(
+    amount: &BigDecimal,
+    rate: &BigDecimal,
+    fixed_fee: &BigDecimal,
+    min: Option<&BigDecimal>,
+    max: Option<&BigDecimal>,
+) -> BigDecimal {
+    let mut fee = amount * rate / BigDecimal::from(100) + fixed_fee;
+    if let Some(min) = min {
+        fee = fee.max(min.clone());
+    }
+    if let Some(max) = max {
+        fee = fee.min(max.clone());
+    }
+    fee
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(s: &str) -> BigDecimal {
+        s.parse().unwrap()
+    }
+
+    fn tier(from: &str, to: Option<&str>, per_unit: &str, flat: &str) -> Tier {
+        Tier {
+            from: decimal(from),
+            to: to.map(decimal),
+            per_unit: decimal(per_unit),
+            flat: decimal(flat),
+        }
+    }
+
+    fn tiers() -> Vec<Tier> {
+        vec![
+            tier("0", Some("100"), "0.5", "0"),
+            tier("100", Some("1000"), "0.3", "10"),
+            tier("1000", None, "0.1", "0"),
+        ]
+    }
+
+    #[test]
+    fn test_graduated() {
+        for (units, expected) in [
+            ("0", "0"),
+            ("50", "25"),
+            ("100", "50"),
+            ("150", "75"),
+            ("1000", "330"),
+            ("1500.5", "380.05"),
+        ] {
+            assert_eq!(
+                graduated(&decimal(units), &tiers()).unwrap(),
+                decimal(expected),
+                "{units}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_volume() {
+        for (units, expected) in [
+            ("0", "0"),
+            ("100", "50"),
+            ("150", "55"),
+            ("1000", "310"),
+            ("1500", "150"),
+        ] {
+            assert_eq!(
+                volume(&decimal(units), &tiers()).unwrap(),
+                decimal(expected),
+                "{units}"
+            );
+        }
+
+        // Units before the first tier
+        let tiers = vec![tier("10", None, "1", "5")];
+        assert_eq!(volume(&decimal("5"), &tiers).unwrap(), decimal("0"));
+    }
+
+    #[test]
+    fn test_invalid_tiers() {
+        for tiers in [
+            vec![],
+            vec![tier("10", Some("10"), "1", "0"), tier("10", None, "1", "0")],
+            vec![tier("0", Some("10"), "1", "0")],
+            vec![tier("0", Some("10"), "1", "0"), tier("20", None, "1", "0")],
+            vec![tier("0", None, "1", "0"), tier("10", None, "1", "0")],
+        ] {
+            assert!(
+                matches!(
+                    graduated(&decimal("1"), &tiers),
+                    Err(ExpressionError::InvalidTier(_))
+                ),
+                "{tiers:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_package() {
+        let package = |units, free| {
+            package(
+                &decimal(units),
+                &decimal("100"),
+                &decimal("5"),
+                &decimal(free),
+            )
+        };
+        assert_eq!(package("0", "0").unwrap(), decimal("0"));
+        assert_eq!(package("1", "0").unwrap(), decimal("5"));
+        assert_eq!(package("100", "0").unwrap(), decimal("5"));
+        assert_eq!(package("100.5", "0").unwrap(), decimal("10"));
+        assert_eq!(package("150", "100").unwrap(), decimal("5"));
+        assert_eq!(package("50", "100").unwrap(), decimal("0"));
+
+        assert!(matches!(
+            super::package(&decimal("1"), &decimal("0"), &decimal("5"), &decimal("0")),
+            Err(ExpressionError::DivisionByZero)
+        ));
+        assert!(matches!(
+            super::package(&decimal("1"), &decimal("-1"), &decimal("5"), &decimal("0")),
+            Err(ExpressionError::Undefined(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_percentage() {
+        let amount = decimal("1000");
+        let rate = decimal("1.5");
+        let fixed_fee = decimal("0.25");
+
+        assert_eq!(
+            percentage(&amount, &rate, &fixed_fee, None, None),
+            decimal("15.25")
+        );
+        assert_eq!(
+            percentage(&amount, &rate, &fixed_fee, Some(&decimal("20")), None),
+            decimal("20")
+        );
+        assert_eq!(
+            percentage(&amount, &rate, &fixed_fee, None, Some(&decimal("10"))),
+            decimal("10")
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use pest::Parser;
+
+use crate::parser::{ExpressionParser, ParseError, ParseResult, Rule};
+
+/// Named sub-expressions shared between expressions, e.g. `bytes_to_gb(x)`
+/// defined as `x / 1024 ^ 3` and used as `@bytes_to_gb(event.properties.bytes)`
+///
+/// Snippets are expanded when an expression is parsed, so they can reference
+/// each other in any order as long as they don't form a cycle. Expansions are
+/// limited in depth and size, as snippets using each other several times can
+/// expand to exponentially large expressions.
+#[derive(Debug, Clone, Default)]
+pub struct SnippetRegistry {
+    snippets: HashMap<String, Snippet>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet {
+    pub parameters: Vec<String>,
+    pub body: String,
+}
+
+impl SnippetRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces the snippet `name`, the body is only checked for
+    /// syntax errors as the snippets it uses might not be defined yet
+    pub fn define(&mut self, name: &str, parameters: &[&str], body: &str) -> ParseResult<()> {
+        for name in std::iter::once(&name).chain(parameters) {
+            ExpressionParser::parse(Rule::definition_name, name)
+                .map_err(|e| ParseError::FailedToParse(e.to_string()))?;
+        }
+        for (i, parameter) in parameters.iter().enumerate() {
+            if parameters[..i].contains(parameter) {
+                return Err(ParseError::ShadowedVariable(parameter.to_string()));
+            }
+        }
+        ExpressionParser::parse(Rule::root, body)
+            .map_err(|e| ParseError::FailedToParse(e.to_string()))?;
+
+        self.snippets.insert(
+            name.to_owned(),
+            Snippet {
+                parameters: parameters.iter().map(|p| p.to_string()).collect(),
+                body: body.to_owned(),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Snippet> {
+        self.snippets.get(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_define() {
+        let mut snippets = SnippetRegistry::new();
+        snippets
+            .define("bytes_to_gb", &["x"], "x / 1024 ^ 3")
+            .unwrap();
+
+        assert_eq!(
+            snippets.get("bytes_to_gb"),
+            Some(&Snippet {
+                parameters: vec!["x".into()],
+                body: "x / 1024 ^ 3".into(),
+            })
+        );
+        assert_eq!(snippets.get("unknown"), None);
+    }
+
+    #[test]
+    fn test_define_errors() {
+        let mut snippets = SnippetRegistry::new();
+
+        assert!(matches!(
+            snippets.define("bytes to gb", &["x"], "x"),
+            Err(ParseError::FailedToParse(_))
+        ));
+        assert!(matches!(
+            snippets.define("f", &["if"], "1"),
+            Err(ParseError::FailedToParse(_))
+        ));
+        assert!(matches!(
+            snippets.define("f", &["x", "x"], "x"),
+            Err(ParseError::ShadowedVariable(name)) if name == "x"
+        ));
+        assert!(matches!(
+            snippets.define("f", &["x"], "x +"),
+            Err(ParseError::FailedToParse(_))
+        ));
+        assert_eq!(snippets.get("f"), None);
+    }
+}
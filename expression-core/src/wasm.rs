@@ -0,0 +1,322 @@
+use std::sync::Arc;
+
+use thiserror::Error;
+use wasmi::{
+    core::TrapCode, Config, Engine, Instance, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder, TypedFunc,
+};
+
+use crate::{
+    evaluate::{EvaluationResult, ExpressionValue},
+    function::{ArgumentType, FunctionRegistry},
+    parser::ParseResult,
+    PropertyValue,
+};
+
+/// Resources a call to a WebAssembly function can use
+#[derive(Debug, Clone, Copy)]
+pub struct WasmLimits {
+    /// Roughly the number of instructions executed
+    pub fuel: u64,
+    /// Size of the linear memory, in bytes
+    pub memory: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 10_000_000,
+            memory: 16 << 20,
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum WasmError {
+    #[error("Invalid WebAssembly module: {0}")]
+    InvalidModule(String),
+
+    #[error("WebAssembly modules can't import {0}")]
+    Import(String),
+
+    #[error("Missing WebAssembly export: {0}")]
+    MissingExport(String),
+
+    #[error("WebAssembly function {0} ran out of fuel")]
+    OutOfFuel(String),
+
+    #[error("WebAssembly function {0} failed: {1}")]
+    Trap(String, String),
+
+    #[error("Invalid result from WebAssembly function {0}: {1}")]
+    InvalidResult(String, String),
+}
+
+/// A compiled module that functions of the function registry can be loaded
+/// from. Every call runs in a new instance, without any imports, so functions
+/// can't keep state between calls or access anything outside of their memory.
+///
+/// Values are passed as JSON in the linear memory of the module, which must export:
+/// - `memory`
+/// - `alloc(len: i32) -> i32`, returning the address of `len` free bytes
+/// - the functions, as `(ptr: i32, len: i32) -> i64` taking a JSON array of the
+///   arguments and returning the address of the JSON result in the high 32 bits
+///   and its length in the low 32 bits
+///
+/// Date and times and durations are passed as strings.
+pub struct WasmModule {
+    engine: Engine,
+    module: Module,
+    limits: WasmLimits,
+}
+
+struct Call {
+    instance: Instance,
+    store: Store<StoreLimits>,
+    memory: Memory,
+}
+
+impl WasmModule {
+    pub fn new(wasm: &[u8], limits: WasmLimits) -> Result<Self, WasmError> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module =
+            Module::new(&engine, wasm).map_err(|e| WasmError::InvalidModule(e.to_string()))?;
+
+        if let Some(import) = module.imports().next() {
+            return Err(WasmError::Import(format!(
+                "{}.{}",
+                import.module(),
+                import.name()
+            )));
+        }
+
+        Ok(Self {
+            engine,
+            module,
+            limits,
+        })
+    }
+
+    fn instantiate(&self, name: &str) -> Result<Call, WasmError> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.limits.memory)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store
+            .set_fuel(self.limits.fuel)
+            .expect("fuel is enabled in the config");
+
+        let instance = Linker::new(&self.engine)
+            .instantiate(&mut store, &self.module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|e| trap(name, e))?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| WasmError::MissingExport("memory".to_owned()))?;
+
+        Ok(Call {
+            instance,
+            store,
+            memory,
+        })
+    }
+
+    /// Checks that the module exports what's needed to call the function `name`
+    fn check(&self, name: &str) -> Result<(), WasmError> {
+        let call = self.instantiate(name)?;
+        call.function::<i32, i32>("alloc")?;
+        call.function::<(i32, i32), i64>(name)?;
+        Ok(())
+    }
+
+    fn call(&self, name: &str, args: &[ExpressionValue]) -> Result<ExpressionValue, WasmError> {
+        let mut call = self.instantiate(name)?;
+        let alloc = call.function::<i32, i32>("alloc")?;
+        let function = call.function::<(i32, i32), i64>(name)?;
+
+        let input = serde_json::Value::Array(args.iter().map(ExpressionValue::to_json).collect())
+            .to_string()
+            .into_bytes();
+        let len = input.len() as i32;
+        let ptr = alloc
+            .call(&mut call.store, len)
+            .map_err(|e| trap(name, e))?;
+        call.memory
+            .write(&mut call.store, ptr as u32 as usize, &input)
+            .map_err(|e| WasmError::Trap(name.to_owned(), e.to_string()))?;
+
+        let result = function
+            .call(&mut call.store, (ptr, len))
+            .map_err(|e| trap(name, e))?;
+        let (ptr, len) = ((result >> 32) as u32 as usize, result as u32 as usize);
+        let output = ptr
+            .checked_add(len)
+            .and_then(|end| call.memory.data(&call.store).get(ptr..end))
+            .ok_or_else(|| WasmError::InvalidResult(name.to_owned(), "out of bounds".to_owned()))?;
+
+        let value: PropertyValue = serde_json::from_slice(output)
+            .map_err(|e| WasmError::InvalidResult(name.to_owned(), e.to_string()))?;
+        Ok(ExpressionValue::from_property(&value, true))
+    }
+}
+
+impl Call {
+    fn function<Params, Results>(&self, name: &str) -> Result<TypedFunc<Params, Results>, WasmError>
+    where
+        Params: wasmi::WasmParams,
+        Results: wasmi::WasmResults,
+    {
+        self.instance
+            .get_typed_func(&self.store, name)
+            .map_err(|_| WasmError::MissingExport(name.to_owned()))
+    }
+}
+
+fn trap(name: &str, error: wasmi::Error) -> WasmError {
+    match error.as_trap_code() {
+        Some(TrapCode::OutOfFuel) => WasmError::OutOfFuel(name.to_owned()),
+        _ => WasmError::Trap(name.to_owned(), error.to_string()),
+    }
+}
+
+impl FunctionRegistry {
+    /// Adds the function `name` exported by the module, see [`WasmModule`] for
+    /// how it's called
+    pub fn register_wasm(
+        &mut self,
+        name: &str,
+        arguments: &[ArgumentType],
+        module: &Arc<WasmModule>,
+    ) -> ParseResult<()> {
+        module.check(name)?;
+
+        let module = module.clone();
+        let export = name.to_owned();
+        self.register(name, arguments, move |args| -> EvaluationResult<_> {
+            Ok(module.call(&export, args)?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::ParseError, ExpressionError};
+
+    const MODULE: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (data (i32.const 0) "{\"tier\":2}")
+          (func (export "alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          ;; Returns the arguments, as an array
+          (func (export "echo") (param $ptr i32) (param $len i32) (result i64)
+            (i64.or
+              (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+              (i64.extend_i32_u (local.get $len))))
+          (func (export "tier") (param i32 i32) (result i64)
+            (i64.const 10))
+          (func (export "spin") (param i32 i32) (result i64)
+            (loop $loop (br $loop))
+            (i64.const 0))
+          (func (export "grow") (param i32 i32) (result i64)
+            (if (i32.eq (memory.grow (i32.const 1000)) (i32.const -1))
+              (then unreachable))
+            (i64.const 0))
+          (func (export "overflow") (param i32 i32) (result i64)
+            (i64.const 0xffff000000ff))
+          (func (export "wrong_signature") (param i32) (result i32)
+            (local.get 0)))
+    "#;
+
+    fn module() -> Arc<WasmModule> {
+        let wasm = wat::parse_str(MODULE).unwrap();
+        Arc::new(WasmModule::new(&wasm, WasmLimits::default()).unwrap())
+    }
+
+    fn registry(names: &[&str]) -> FunctionRegistry {
+        let module = module();
+        let mut functions = FunctionRegistry::new();
+        for name in names {
+            functions
+                .register_wasm(name, &[ArgumentType::Any], &module)
+                .unwrap();
+        }
+        functions
+    }
+
+    fn call(name: &str, arg: ExpressionValue) -> EvaluationResult<ExpressionValue> {
+        registry(&[name]).get(name).unwrap().call(&[arg])
+    }
+
+    #[test]
+    fn test_call() {
+        assert_eq!(
+            call("tier", ExpressionValue::Null).unwrap(),
+            ExpressionValue::Object([("tier".into(), ExpressionValue::Number(2.into()))].into())
+        );
+    }
+
+    #[test]
+    fn test_call_marshals_arguments() {
+        let arg = ExpressionValue::Array(vec![
+            ExpressionValue::Number("1.5".parse().unwrap()),
+            ExpressionValue::String("eu".into()),
+            ExpressionValue::Boolean(true),
+            ExpressionValue::Null,
+        ]);
+        assert_eq!(
+            call("echo", arg.clone()).unwrap(),
+            ExpressionValue::Array(vec![arg])
+        );
+    }
+
+    #[test]
+    fn test_call_limits() {
+        assert!(matches!(
+            call("spin", ExpressionValue::Null),
+            Err(ExpressionError::Wasm(WasmError::OutOfFuel(name))) if name == "spin"
+        ));
+        assert!(matches!(
+            call("grow", ExpressionValue::Null),
+            Err(ExpressionError::Wasm(WasmError::Trap(name, _))) if name == "grow"
+        ));
+        assert!(matches!(
+            call("overflow", ExpressionValue::Null),
+            Err(ExpressionError::Wasm(WasmError::InvalidResult(name, _))) if name == "overflow"
+        ));
+    }
+
+    #[test]
+    fn test_register_errors() {
+        let module = module();
+        let mut functions = FunctionRegistry::new();
+
+        for name in ["missing", "wrong_signature"] {
+            assert!(
+                matches!(
+                    functions.register_wasm(name, &[], &module),
+                    Err(ParseError::Wasm(WasmError::MissingExport(export))) if export == name
+                ),
+                "{name}"
+            );
+        }
+
+        let wasm = wat::parse_str(r#"(module (import "env" "now" (func)))"#).unwrap();
+        assert!(matches!(
+            WasmModule::new(&wasm, WasmLimits::default()),
+            Err(WasmError::Import(import)) if import == "env.now"
+        ));
+        assert!(matches!(
+            WasmModule::new(b"not wasm", WasmLimits::default()),
+            Err(WasmError::InvalidModule(_))
+        ));
+    }
+}
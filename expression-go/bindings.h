@@ -4,13 +4,57 @@
 #include <stdlib.h>
 
 /**
+ * Domain functions added to the language, e.g. `tier(event.properties.plan)`
+ *
+ * Names are case-insensitive and can't be the name of a built-in function.
+ */
+typedef struct FunctionRegistry FunctionRegistry;
+
+/**
+ * Returns the result as a string, `"null"` for a null result, or a null
+ * pointer if the expression can't be parsed or evaluated
+ *
  * # Safety
  * Pass in a valid strings
  */
 char *evaluate(const char *input, const char *event);
 
+/**
+ * # Safety
+ * Pass in a valid strings, `params` is a JSON object with the values of the
+ * `params.` used by the expression
+ */
+char *evaluate_with_params(const char *input, const char *event, const char *params);
+
+/**
+ * # Safety
+ * `wasm` must point to `len` bytes of a WebAssembly module and `functions` be
+ * a valid string, a JSON object with the argument types of the functions to
+ * load from the module, e.g. `{"tier": ["string", "number"]}`.
+ * Returns null if the module or a function can't be loaded, the registry must
+ * be freed with `free_function_registry`.
+ */
+FunctionRegistry *load_wasm_functions(const uint8_t *wasm, uintptr_t len, const char *functions);
+
+/**
+ * # Safety
+ * Pass in a valid strings and a registry obtained through `load_wasm_functions`,
+ * the expression can call the functions of the registry.
+ * Returns null if the registry is null, e.g. once it has been freed.
+ */
+char *evaluate_with_functions(const char *input,
+                              const char *event,
+                              const FunctionRegistry *functions);
+
 /**
  * # Safety
  * Only pass in pointers to strings that have been obtained through `evaluate`
  */
 void free_evaluate(char *ptr);
+
+/**
+ * # Safety
+ * Only pass in pointers to registries that have been obtained through
+ * `load_wasm_functions`
+ */
+void free_function_registry(FunctionRegistry *ptr);
@@ -5,8 +5,21 @@ package expression
 // #include <stdlib.h>
 // #include "bindings.h"
 import "C"
-import "unsafe"
+import (
+	"encoding/json"
+	"errors"
+	"unsafe"
+)
 
+// Functions loaded from a WebAssembly module, expressions evaluated with
+// EvaluateWithFunctions can call them. Close them once they're not used anymore.
+type Functions struct {
+	ptr *C.FunctionRegistry
+}
+
+// Evaluate returns the result of the expression for the event, as a string.
+// A null result is returned as "null", nil means that the expression can't be
+// parsed or evaluated.
 func Evaluate(expression string, event_json string) *string {
 	cs := C.CString(expression)
 	event := C.CString(event_json)
@@ -25,3 +38,81 @@ func Evaluate(expression string, event_json string) *string {
 		return nil
 	}
 }
+
+// EvaluateWithParams is like Evaluate, with the values of the params. used by
+// the expression given as a JSON object
+func EvaluateWithParams(expression string, event_json string, params_json string) *string {
+	cs := C.CString(expression)
+	event := C.CString(event_json)
+	params := C.CString(params_json)
+
+	// Evaluate the expression with the given parameters
+	ptr := C.evaluate_with_params(cs, event, params)
+
+	C.free(unsafe.Pointer(cs))
+	C.free(unsafe.Pointer(event))
+	C.free(unsafe.Pointer(params))
+
+	if ptr != nil {
+		result := C.GoString(ptr)
+		C.free_evaluate(ptr)
+		return &result
+	} else {
+		return nil
+	}
+}
+
+// LoadWasmFunctions loads functions exported by a WebAssembly module, with the
+// types of their arguments, e.g. {"tier": {"string", "number"}}
+func LoadWasmFunctions(wasm []byte, functions map[string][]string) (*Functions, error) {
+	functions_json, err := json.Marshal(functions)
+	if err != nil {
+		return nil, err
+	}
+	cs := C.CString(string(functions_json))
+
+	var module *C.uint8_t
+	if len(wasm) > 0 {
+		module = (*C.uint8_t)(unsafe.Pointer(&wasm[0]))
+	}
+	ptr := C.load_wasm_functions(module, C.uintptr_t(len(wasm)), cs)
+
+	C.free(unsafe.Pointer(cs))
+
+	if ptr == nil {
+		return nil, errors.New("cannot load the WebAssembly functions")
+	}
+	return &Functions{ptr: ptr}, nil
+}
+
+func (functions *Functions) Close() {
+	if functions.ptr != nil {
+		C.free_function_registry(functions.ptr)
+		functions.ptr = nil
+	}
+}
+
+// EvaluateWithFunctions is like Evaluate, the expression can call the functions.
+// It returns nil once the functions are closed.
+func EvaluateWithFunctions(expression string, event_json string, functions *Functions) *string {
+	if functions == nil || functions.ptr == nil {
+		return nil
+	}
+
+	cs := C.CString(expression)
+	event := C.CString(event_json)
+
+	// Evaluate the expression, it can call the loaded functions
+	ptr := C.evaluate_with_functions(cs, event, functions.ptr)
+
+	C.free(unsafe.Pointer(cs))
+	C.free(unsafe.Pointer(event))
+
+	if ptr != nil {
+		result := C.GoString(ptr)
+		C.free_evaluate(ptr)
+		return &result
+	} else {
+		return nil
+	}
+}
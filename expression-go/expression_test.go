@@ -0,0 +1,102 @@
+package expression
+
+import (
+	"os"
+	"testing"
+)
+
+const event = `{"code": "api_calls", "timestamp": 1700000000, "properties": {"plan": "pro", "calls": "12"}}`
+
+func TestEvaluate(t *testing.T) {
+	for expression, expected := range map[string]string{
+		"1 + 2":                              "3",
+		"coalesce(event.properties.missing)": "null",
+		"if(false, 1, null)":                 "null",
+	} {
+		result := Evaluate(expression, event)
+		if result == nil || *result != expected {
+			t.Errorf("%s: expected %s, got %v", expression, expected, result)
+		}
+	}
+
+	// Errors are returned as nil
+	for _, expression := range []string{"1 +", "1 / 0"} {
+		if result := Evaluate(expression, event); result != nil {
+			t.Errorf("%s: expected nil, got %s", expression, *result)
+		}
+	}
+}
+
+func loadFunctions(t *testing.T) *Functions {
+	wasm, err := os.ReadFile("testdata/functions.wasm")
+	if err != nil {
+		t.Fatal(err)
+	}
+	functions, err := LoadWasmFunctions(wasm, map[string][]string{
+		"tier": {"string"},
+		"echo": {"number"},
+	})
+	if err != nil {
+		t.Fatal(err)
+	}
+	t.Cleanup(functions.Close)
+	return functions
+}
+
+func TestEvaluateWithFunctions(t *testing.T) {
+	functions := loadFunctions(t)
+
+	for expression, expected := range map[string]string{
+		"tier(event.properties.plan)":         "gold",
+		"echo(event.properties.calls)[0] * 2": "24",
+	} {
+		result := EvaluateWithFunctions(expression, event, functions)
+		if result == nil || *result != expected {
+			t.Errorf("%s: expected %s, got %v", expression, expected, result)
+		}
+	}
+
+	// Wrong argument type
+	if result := EvaluateWithFunctions("echo('a')", event, functions); result != nil {
+		t.Errorf("expected nil, got %s", *result)
+	}
+	// Functions are only known to the expressions evaluated with them
+	if result := Evaluate("tier(event.properties.plan)", event); result != nil {
+		t.Errorf("expected nil, got %s", *result)
+	}
+}
+
+func TestEvaluateWithClosedFunctions(t *testing.T) {
+	functions := loadFunctions(t)
+	functions.Close()
+
+	if result := EvaluateWithFunctions("1 + 2", event, functions); result != nil {
+		t.Errorf("expected nil, got %s", *result)
+	}
+	if result := EvaluateWithFunctions("1 + 2", event, nil); result != nil {
+		t.Errorf("expected nil, got %s", *result)
+	}
+}
+
+func TestLoadWasmFunctionsErrors(t *testing.T) {
+	wasm, err := os.ReadFile("testdata/functions.wasm")
+	if err != nil {
+		t.Fatal(err)
+	}
+
+	for name, functions := range map[string]map[string][]string{
+		"missing export": {"missing": {"number"}},
+		"unknown type":   {"tier": {"decimal"}},
+		"builtin name":   {"round": {"number"}},
+	} {
+		if _, err := LoadWasmFunctions(wasm, functions); err == nil {
+			t.Errorf("%s: expected an error", name)
+		}
+	}
+	if _, err := LoadWasmFunctions([]byte("not wasm"), map[string][]string{}); err == nil {
+		t.Error("invalid module: expected an error")
+	}
+	if _, err := LoadWasmFunctions(nil, map[string][]string{}); err == nil {
+		t.Error("empty module: expected an error")
+	}
+}
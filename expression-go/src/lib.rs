@@ -1,18 +1,124 @@
 use std::{
+    collections::HashMap,
     ffi::{c_char, CStr, CString},
     ptr::null_mut,
+    slice,
+    sync::Arc,
 };
 
-use expression_core::ExpressionParser;
+use expression_core::{
+    ArgumentType, EvaluationContext, ExpressionParser, FunctionRegistry, PropertyValue, WasmLimits,
+    WasmModule,
+};
 
 #[no_mangle]
+/// Returns the result as a string, `"null"` for a null result, or a null
+/// pointer if the expression can't be parsed or evaluated
+///
 /// # Safety
 /// Pass in a valid strings
 pub unsafe extern "C" fn evaluate(input: *const c_char, event: *const c_char) -> *mut c_char {
+    unsafe {
+        evaluate_with_context(
+            input,
+            event,
+            EvaluationContext::default(),
+            &FunctionRegistry::default(),
+        )
+    }
+}
+
+#[no_mangle]
+/// # Safety
+/// Pass in a valid strings, `params` is a JSON object with the values of the
+/// `params.` used by the expression
+pub unsafe extern "C" fn evaluate_with_params(
+    input: *const c_char,
+    event: *const c_char,
+    params: *const c_char,
+) -> *mut c_char {
+    let json = unsafe { CStr::from_ptr(params).to_str().unwrap() };
+
+    let Ok(params) = serde_json::from_str::<HashMap<String, PropertyValue>>(json) else {
+        return null_mut();
+    };
+
+    let context = EvaluationContext {
+        params,
+        ..Default::default()
+    };
+    unsafe { evaluate_with_context(input, event, context, &FunctionRegistry::default()) }
+}
+
+#[no_mangle]
+/// # Safety
+/// `wasm` must point to `len` bytes of a WebAssembly module and `functions` be
+/// a valid string, a JSON object with the argument types of the functions to
+/// load from the module, e.g. `{"tier": ["string", "number"]}`.
+/// Returns null if the module or a function can't be loaded, the registry must
+/// be freed with `free_function_registry`.
+pub unsafe extern "C" fn load_wasm_functions(
+    wasm: *const u8,
+    len: usize,
+    functions: *const c_char,
+) -> *mut FunctionRegistry {
+    if wasm.is_null() {
+        return null_mut();
+    }
+    let wasm = unsafe { slice::from_raw_parts(wasm, len) };
+    let json = unsafe { CStr::from_ptr(functions).to_str().unwrap() };
+
+    let Ok(functions) = serde_json::from_str::<HashMap<String, Vec<String>>>(json) else {
+        return null_mut();
+    };
+    let Ok(module) = WasmModule::new(wasm, WasmLimits::default()) else {
+        return null_mut();
+    };
+
+    let module = Arc::new(module);
+    let mut registry = FunctionRegistry::new();
+    for (name, arguments) in functions {
+        let Ok(arguments) = arguments
+            .iter()
+            .map(|argument| argument.parse())
+            .collect::<Result<Vec<ArgumentType>, _>>()
+        else {
+            return null_mut();
+        };
+        if registry.register_wasm(&name, &arguments, &module).is_err() {
+            return null_mut();
+        }
+    }
+    Box::into_raw(Box::new(registry))
+}
+
+#[no_mangle]
+/// # Safety
+/// Pass in a valid strings and a registry obtained through `load_wasm_functions`,
+/// the expression can call the functions of the registry.
+/// Returns null if the registry is null, e.g. once it has been freed.
+pub unsafe extern "C" fn evaluate_with_functions(
+    input: *const c_char,
+    event: *const c_char,
+    functions: *const FunctionRegistry,
+) -> *mut c_char {
+    if functions.is_null() {
+        return null_mut();
+    }
+    let functions = unsafe { &*functions };
+    unsafe { evaluate_with_context(input, event, EvaluationContext::default(), functions) }
+}
+
+unsafe fn evaluate_with_context(
+    input: *const c_char,
+    event: *const c_char,
+    context: EvaluationContext,
+    functions: &FunctionRegistry,
+) -> *mut c_char {
     let input = unsafe { CStr::from_ptr(input).to_str().unwrap().to_owned() };
 
     // Cannot parse expression -> return null
-    let Ok(expr) = ExpressionParser::parse_expression(&input) else {
+    let Ok(expr) = ExpressionParser::parse_expression_with_functions(&input, functions) else {
         return null_mut();
     };
 
@@ -23,10 +129,11 @@ pub unsafe extern "C" fn evaluate(input: *const c_char, event: *const c_char) ->
     };
 
     // evaluate expression, errors are not returned, but we do catch them and return null
-    let Ok(res) = expr.evaluate(&event) else {
+    let Ok(res) = expr.evaluate_with_context(&event, &context) else {
         return null_mut();
     };
 
+    // A null result is returned as "null", so it can't be mistaken for an error
     let Ok(temp) = CString::new(res.to_string()) else {
         return null_mut();
     };
@@ -39,3 +146,11 @@ pub unsafe extern "C" fn evaluate(input: *const c_char, event: *const c_char) ->
 pub unsafe extern "C" fn free_evaluate(ptr: *mut c_char) {
     unsafe { drop(CString::from_raw(ptr)) }
 }
+
+#[no_mangle]
+/// # Safety
+/// Only pass in pointers to registries that have been obtained through
+/// `load_wasm_functions`
+pub unsafe extern "C" fn free_function_registry(ptr: *mut FunctionRegistry) {
+    unsafe { drop(Box::from_raw(ptr)) }
+}
@@ -0,0 +1,19 @@
+;; Functions used by the tests, compiled to functions.wasm with
+;; `wat2wasm functions.wat`
+(module
+  (memory (export "memory") 1)
+  (global $next (mut i32) (i32.const 1024))
+  (data (i32.const 0) "\"gold\"")
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $next))
+    (global.set $next (i32.add (global.get $next) (local.get $len)))
+    (local.get $ptr))
+  ;; Returns the arguments, as an array
+  (func (export "echo") (param $ptr i32) (param $len i32) (result i64)
+    (i64.or
+      (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+      (i64.extend_i32_u (local.get $len))))
+  ;; Returns "gold", whatever the arguments
+  (func (export "tier") (param i32 i32) (result i64)
+    (i64.const 6)))
@@ -1,11 +1,11 @@
 use std::collections::HashMap;
 
-use js_sys::Reflect;
+use js_sys::{Array, Object, Reflect};
 use wasm_bindgen::prelude::*;
 
 use bigdecimal::{BigDecimal, FromPrimitive, ToPrimitive};
 
-use expression_core::{ExpressionParser, ExpressionValue, PropertyValue};
+use expression_core::{EvaluationContext, ExpressionParser, ExpressionValue, PropertyValue, Tz};
 extern crate console_error_panic_hook;
 
 #[wasm_bindgen(start)]
@@ -17,6 +17,14 @@ fn start() {
 #[derive(Debug)]
 pub struct Expression(expression_core::Expression);
 
+#[wasm_bindgen]
+impl Expression {
+    /// Names of the `params.` used by the expression
+    pub fn parameters(&self) -> Vec<String> {
+        self.0.parameters()
+    }
+}
+
 #[wasm_bindgen(js_name = parseExpression)]
 pub fn parse_expression(expression: String) -> Result<Expression, String> {
     ExpressionParser::parse_expression(&expression)
@@ -30,29 +38,22 @@ pub fn evaluate_expression(
     code: String,
     timestamp: u64,
     js_properties: &JsValue,
+    timezone: Option<String>,
+    js_params: Option<JsValue>,
 ) -> Result<JsValue, JsValue> {
-    let mut properties = HashMap::new();
-
-    let keys = Reflect::own_keys(js_properties)?;
+    let mut context = EvaluationContext::default();
+    if let Some(timezone) = timezone {
+        context.timezone = timezone
+            .parse::<Tz>()
+            .map_err(|_| format!("unknown timezone: {}", timezone))?;
+    }
 
-    for key in keys {
-        let value = Reflect::get(js_properties, &key)?;
-
-        let property_value = if value.is_string() {
-            String::try_from(value)?.into()
-        } else if value.is_bigint() {
-            let n = u64::try_from(value)?;
-            PropertyValue::Number(n.into())
-        } else {
-            let n = f64::try_from(value)?;
-            PropertyValue::Number(
-                BigDecimal::from_f64(n).ok_or("failed to convert property value")?,
-            )
-        };
-
-        properties.insert(key.as_string().ok_or("expected string")?, property_value);
+    if let Some(js_params) = js_params {
+        context.params = object_properties(&js_params)?;
     }
 
+    let properties = object_properties(js_properties)?;
+
     let event = expression_core::Event {
         code,
         timestamp: PropertyValue::Number(timestamp.into()),
@@ -61,10 +62,81 @@ pub fn evaluate_expression(
 
     expression
         .0
-        .evaluate(&event)
-        .map(|value| match value {
-            ExpressionValue::Number(d) => d.to_f64().into(),
-            ExpressionValue::String(s) => s.into(),
-        })
-        .map_err(|e| format!("{}", e).into())
+        .evaluate_with_context(&event, &context)
+        .map_err(|e| JsValue::from(format!("{}", e)))
+        .and_then(js_value)
+}
+
+fn js_value(value: ExpressionValue) -> Result<JsValue, JsValue> {
+    let js_value = match value {
+        ExpressionValue::Number(d) | ExpressionValue::Duration(d) => d.to_f64().into(),
+        ExpressionValue::String(s) => s.into(),
+        ExpressionValue::Boolean(b) => b.into(),
+        ExpressionValue::DateTime(dt) => {
+            js_sys::Date::new(&(dt.timestamp_millis() as f64).into()).into()
+        }
+        ExpressionValue::Array(values) => values
+            .into_iter()
+            .map(js_value)
+            .collect::<Result<Array, _>>()?
+            .into(),
+        ExpressionValue::Object(object) => {
+            let js_object = Object::new();
+            for (key, value) in object {
+                Reflect::set(&js_object, &key.into(), &js_value(value)?)?;
+            }
+            js_object.into()
+        }
+        // Amounts are returned as `{ amount, currency }`
+        ExpressionValue::Money(amount, currency) => {
+            let js_object = Object::new();
+            Reflect::set(&js_object, &"amount".into(), &amount.to_f64().into())?;
+            Reflect::set(&js_object, &"currency".into(), &currency.code().into())?;
+            js_object.into()
+        }
+        ExpressionValue::Null => JsValue::NULL,
+    };
+    Ok(js_value)
+}
+
+fn object_properties(js_object: &JsValue) -> Result<HashMap<String, PropertyValue>, JsValue> {
+    let mut properties = HashMap::new();
+
+    let keys = Reflect::own_keys(js_object)?;
+
+    for key in keys {
+        let value = Reflect::get(js_object, &key)?;
+        properties.insert(
+            key.as_string().ok_or("expected string")?,
+            property_value(value)?,
+        );
+    }
+
+    Ok(properties)
+}
+
+fn property_value(value: JsValue) -> Result<PropertyValue, JsValue> {
+    let property_value = if value.is_string() {
+        String::try_from(value)?.into()
+    } else if value.is_bigint() {
+        let n = u64::try_from(value)?;
+        PropertyValue::Number(n.into())
+    } else if let Some(b) = value.as_bool() {
+        PropertyValue::Boolean(b)
+    } else if value.is_null() || value.is_undefined() {
+        PropertyValue::Null
+    } else if Array::is_array(&value) {
+        Array::from(&value)
+            .iter()
+            .map(property_value)
+            .collect::<Result<_, _>>()
+            .map(PropertyValue::Array)?
+    } else if value.is_object() {
+        PropertyValue::Object(object_properties(&value)?)
+    } else {
+        let n = f64::try_from(value)?;
+        PropertyValue::Number(BigDecimal::from_f64(n).ok_or("failed to convert property value")?)
+    };
+
+    Ok(property_value)
 }
@@ -1,9 +1,12 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, sync::Arc};
 
-use expression_core::{Event, Expression, ExpressionParser, ExpressionValue, PropertyValue};
+use expression_core::{
+    ArgumentType, Event, Expression, ExpressionParser, ExpressionValue, FunctionRegistry,
+    PropertyValue, WasmLimits, WasmModule,
+};
 use magnus::{
     error, function, method, r_hash::ForEach, value::ReprValue, Error, IntoValue, Module, Object,
-    RHash, Ruby, Value,
+    RArray, RHash, RString, Ruby, Value,
 };
 
 #[magnus::wrap(class = "Lago::Expression", free_immediately, size)]
@@ -12,34 +15,98 @@ struct ExpressionWrapper(Expression);
 #[magnus::wrap(class = "Lago::Event", free_immediately, size)]
 struct EventWrapper(Event);
 
+#[magnus::wrap(class = "Lago::FunctionRegistry", free_immediately, size)]
+struct FunctionRegistryWrapper(RefCell<FunctionRegistry>);
+
 impl EventWrapper {
     fn new(ruby: &Ruby, code: String, timestamp: u64, map: RHash) -> error::Result<EventWrapper> {
-        let mut properties = HashMap::default();
-
-        map.foreach(|key: String, value: Value| {
-            let property_value = if value.is_kind_of(ruby.class_numeric()) {
-                // Convert ruby numbers to a formatted string, that can be parsed into a BigDecimal
-                let ruby_string = value.to_r_string()?;
-                let big_d = ruby_string
-                    .to_string()?
-                    .parse()
-                    .expect("Failed to parse a number as bigdecimal");
-                PropertyValue::Number(big_d)
-            } else {
-                PropertyValue::String(value.to_string())
-            };
-            properties.insert(key, property_value);
-            Ok(ForEach::Continue)
-        })?;
-
         Ok(Self(Event {
             code,
             timestamp: timestamp.into(),
-            properties,
+            properties: hash_properties(ruby, map)?,
         }))
     }
 }
 
+impl FunctionRegistryWrapper {
+    fn new() -> Self {
+        Self(RefCell::new(FunctionRegistry::new()))
+    }
+
+    /// Loads functions exported by a WebAssembly module, `functions` maps their
+    /// names to the types of their arguments, e.g. `{"tier" => ["string", "number"]}`
+    fn register_wasm(
+        ruby: &Ruby,
+        rb_self: &Self,
+        wasm: RString,
+        functions: RHash,
+    ) -> error::Result<()> {
+        let argument_error =
+            |err: &dyn std::error::Error| Error::new(ruby.exception_arg_error(), err.to_string());
+
+        // The bytes are copied by the module before Ruby can change the string
+        let module = WasmModule::new(unsafe { wasm.as_slice() }, WasmLimits::default())
+            .map_err(|err| argument_error(&err))?;
+        let module = Arc::new(module);
+
+        functions.foreach(|name: String, arguments: Vec<String>| {
+            let arguments = arguments
+                .iter()
+                .map(|argument| argument.parse())
+                .collect::<Result<Vec<ArgumentType>, _>>()
+                .map_err(|err| argument_error(&err))?;
+            rb_self
+                .0
+                .borrow_mut()
+                .register_wasm(&name, &arguments, &module)
+                .map_err(|err| argument_error(&err))?;
+            Ok(ForEach::Continue)
+        })
+    }
+}
+
+fn hash_properties(ruby: &Ruby, map: RHash) -> error::Result<HashMap<String, PropertyValue>> {
+    let mut properties = HashMap::default();
+
+    map.foreach(|key: String, value: Value| {
+        properties.insert(key, property_value(ruby, value)?);
+        Ok(ForEach::Continue)
+    })?;
+
+    Ok(properties)
+}
+
+fn property_value(ruby: &Ruby, value: Value) -> error::Result<PropertyValue> {
+    let property_value = if value.is_kind_of(ruby.class_numeric()) {
+        // Convert ruby numbers to a formatted string, that can be parsed into a BigDecimal
+        let ruby_string = value.to_r_string()?;
+        let big_d = ruby_string
+            .to_string()?
+            .parse()
+            .expect("Failed to parse a number as bigdecimal");
+        PropertyValue::Number(big_d)
+    } else if value.is_nil() {
+        PropertyValue::Null
+    } else if value.is_kind_of(ruby.class_true_class()) {
+        PropertyValue::Boolean(true)
+    } else if value.is_kind_of(ruby.class_false_class()) {
+        PropertyValue::Boolean(false)
+    } else if let Some(array) = RArray::from_value(value) {
+        let values = array
+            .to_vec::<Value>()?
+            .into_iter()
+            .map(|value| property_value(ruby, value))
+            .collect::<error::Result<_>>()?;
+        PropertyValue::Array(values)
+    } else if let Some(hash) = RHash::from_value(value) {
+        PropertyValue::Object(hash_properties(ruby, hash)?)
+    } else {
+        PropertyValue::String(value.to_string())
+    };
+
+    Ok(property_value)
+}
+
 /// Parse the given input and return an Optional ExpressionWrapper,
 /// will return None when the expression is not valid
 fn parse(input: String) -> Option<ExpressionWrapper> {
@@ -56,6 +123,23 @@ fn validate(input: String) -> Option<String> {
         .map(|e| e.to_string())
 }
 
+/// Like `parse`, the expression can call the functions of the registry
+fn parse_with_functions(
+    input: String,
+    functions: &FunctionRegistryWrapper,
+) -> Option<ExpressionWrapper> {
+    ExpressionParser::parse_expression_with_functions(&input, &functions.0.borrow())
+        .ok()
+        .map(ExpressionWrapper)
+}
+
+/// Like `validate`, the expression can call the functions of the registry
+fn validate_with_functions(input: String, functions: &FunctionRegistryWrapper) -> Option<String> {
+    ExpressionParser::parse_expression_with_functions(&input, &functions.0.borrow())
+        .err()
+        .map(|e| e.to_string())
+}
+
 fn evaluate(
     ruby: &Ruby,
     expr: &ExpressionWrapper,
@@ -66,12 +150,66 @@ fn evaluate(
         .evaluate(&event.0)
         .map_err(|err| Error::new(ruby.exception_runtime_error(), err.to_string()))?;
 
-    match evaluated {
-        ExpressionValue::Number(d) => d
+    ruby_value(ruby, evaluated)
+}
+
+fn evaluate_with_params(
+    ruby: &Ruby,
+    expr: &ExpressionWrapper,
+    event: &EventWrapper,
+    params: RHash,
+) -> error::Result<magnus::Value> {
+    let evaluated = expr
+        .0
+        .evaluate_with_params(&event.0, hash_properties(ruby, params)?)
+        .map_err(|err| Error::new(ruby.exception_runtime_error(), err.to_string()))?;
+
+    ruby_value(ruby, evaluated)
+}
+
+fn parameters(expr: &ExpressionWrapper) -> Vec<String> {
+    expr.0.parameters()
+}
+
+fn ruby_value(ruby: &Ruby, value: ExpressionValue) -> error::Result<magnus::Value> {
+    match value {
+        // Durations are returned as their number of seconds
+        ExpressionValue::Number(d) | ExpressionValue::Duration(d) => d
             .to_string()
             .into_value_with(ruby)
             .funcall_public("to_d", ()),
         ExpressionValue::String(s) => Ok(s.into_value_with(ruby)),
+        ExpressionValue::Boolean(b) => Ok(b.into_value_with(ruby)),
+        ExpressionValue::DateTime(dt) => ruby.class_time().funcall(
+            "at",
+            (
+                dt.timestamp(),
+                dt.timestamp_subsec_nanos(),
+                ruby.to_symbol("nsec"),
+            ),
+        ),
+        ExpressionValue::Array(values) => {
+            let array = ruby.ary_new();
+            for value in values {
+                array.push(ruby_value(ruby, value)?)?;
+            }
+            Ok(array.as_value())
+        }
+        ExpressionValue::Object(object) => {
+            let hash = ruby.hash_new();
+            for (key, value) in object {
+                hash.aset(key, ruby_value(ruby, value)?)?;
+            }
+            Ok(hash.as_value())
+        }
+        // Amounts are returned as `{"amount" => BigDecimal, "currency" => "EUR"}`
+        ExpressionValue::Money(amount, currency) => {
+            let hash = ruby.hash_new();
+            hash.aset("amount", ruby_value(ruby, ExpressionValue::Number(amount))?)?;
+            hash.aset("currency", currency.code())?;
+            Ok(hash.as_value())
+        }
+        ExpressionValue::Null => Ok(ruby.qnil().as_value()),
     }
 }
 
@@ -82,12 +220,26 @@ fn init(ruby: &Ruby) -> Result<(), Error> {
     let class = module.define_class("ExpressionParser", ruby.class_object())?;
     class.define_singleton_method("parse", function!(parse, 1))?;
     class.define_singleton_method("validate", function!(validate, 1))?;
+    class.define_singleton_method("parse_with_functions", function!(parse_with_functions, 2))?;
+    class.define_singleton_method(
+        "validate_with_functions",
+        function!(validate_with_functions, 2),
+    )?;
 
     let class = module.define_class("Expression", ruby.class_object())?;
     class.define_method("evaluate", method!(evaluate, 1))?;
+    class.define_method("evaluate_with_params", method!(evaluate_with_params, 2))?;
+    class.define_method("parameters", method!(parameters, 0))?;
 
     let class = module.define_class("Event", ruby.class_object())?;
     class.define_singleton_method("new", function!(EventWrapper::new, 3))?;
 
+    let class = module.define_class("FunctionRegistry", ruby.class_object())?;
+    class.define_singleton_method("new", function!(FunctionRegistryWrapper::new, 0))?;
+    class.define_method(
+        "register_wasm",
+        method!(FunctionRegistryWrapper::register_wasm, 2),
+    )?;
+
     Ok(())
 }
@@ -22,10 +22,95 @@ RSpec.describe Lago::Expression do
     end
 
     context "failing evaluation" do
-      let(:expression) { Lago::ExpressionParser.parse('event.properties.does_not_exists') }
+      let(:expression) { Lago::ExpressionParser.parse('event.properties.property_2 + 1') }
 
       it "raises an error" do
-        expect {expression.evaluate(event)}.to raise_error(RuntimeError, /does_not_exists not found/)
+        expect {expression.evaluate(event)}.to raise_error(RuntimeError, /Expected a decimal/)
+      end
+    end
+
+    context "with a missing property" do
+      let(:expression) { Lago::ExpressionParser.parse('event.properties.does_not_exists') }
+
+      it "returns nil" do
+        expect(expression.evaluate(event)).to be_nil
+      end
+    end
+
+    context "with a boolean property" do
+      let(:event) { Lago::Event.new("code", 1234, {"enabled" => true}) }
+      let(:expression) { Lago::ExpressionParser.parse('event.properties.enabled') }
+
+      it "returns a boolean" do
+        expect(expression.evaluate(event)).to eq(true)
+      end
+    end
+
+    context "with a nil property" do
+      let(:event) { Lago::Event.new("code", 1234, {"region" => nil}) }
+      let(:expression) { Lago::ExpressionParser.parse('event.properties.region') }
+
+      it "returns nil" do
+        expect(expression.evaluate(event)).to be_nil
+      end
+    end
+
+    context "with a map over line items" do
+      let(:event) { Lago::Event.new("code", 1234, {"items" => [{"qty" => 2, "price" => 1.5}, {"qty" => 1, "price" => 3}]}) }
+      let(:expression) { Lago::ExpressionParser.parse('map(event.properties.items, x -> x.qty * x.price)') }
+
+      it "returns an array" do
+        expect(expression.evaluate(event)).to eq([3.to_d, 3.to_d])
+      end
+    end
+
+    context "with a sum over line items" do
+      let(:event) { Lago::Event.new("code", 1234, {"items" => [{"qty" => 2, "price" => 1.5}, {"qty" => 1, "price" => 3}]}) }
+      let(:expression) { Lago::ExpressionParser.parse('sum(map(event.properties.items, x -> x.qty * x.price))') }
+
+      it "returns the total" do
+        expect(expression.evaluate(event)).to eq(6.to_d)
+      end
+    end
+
+    context "with params" do
+      let(:expression) { Lago::ExpressionParser.parse('event.properties.property_1 * params.rate') }
+
+      it "returns the value with the params" do
+        expect(expression.evaluate_with_params(event, {"rate" => 2})).to eq(2.46.to_d)
+      end
+
+      it "lists the params" do
+        expect(expression.parameters).to eq(["rate"])
+      end
+
+      it "raises an error for missing params" do
+        expect { expression.evaluate(event) }.to raise_error(RuntimeError, "Missing parameter: rate")
+      end
+    end
+
+    context "with graduated pricing" do
+      let(:event) { Lago::Event.new("code", 1234, {"units" => 150}) }
+      let(:expression) { Lago::ExpressionParser.parse('graduated(event.properties.units, [[0, 100, 0.5], [100, null, 0.3]])') }
+
+      it "returns the amount" do
+        expect(expression.evaluate(event)).to eq(65.to_d)
+      end
+    end
+
+    context "with a money amount" do
+      let(:expression) { Lago::ExpressionParser.parse("round(money(event.properties.property_1 * 3, 'EUR'))") }
+
+      it "returns the amount and currency" do
+        expect(expression.evaluate(event)).to eq({"amount" => 3.69.to_d, "currency" => "EUR"})
+      end
+    end
+
+    context "with a coalesce function" do
+      let(:expression) { Lago::ExpressionParser.parse('coalesce(event.properties.does_not_exists, 10)') }
+
+      it "returns the default value" do
+        expect(expression.evaluate(event)).to eq(10)
       end
     end
 
@@ -117,5 +202,13 @@ RSpec.describe Lago::Expression do
         expect(expression.evaluate(event)).to eq(15.0)
       end
     end
+
+    context "with a comparison expression" do
+      let(:expression) { Lago::ExpressionParser.parse("event.properties.property_2 == 'test' and event.properties.property_1 > 1") }
+
+      it "returns a boolean" do
+        expect(expression.evaluate(event)).to eq(true)
+      end
+    end
   end
 end
@@ -0,0 +1,42 @@
+require 'spec_helper'
+
+
+RSpec.describe Lago::FunctionRegistry do
+  let(:wasm) { File.binread(File.expand_path('../fixtures/functions.wasm', __dir__)) }
+  let(:registry) { described_class.new.tap { |r| r.register_wasm(wasm, {"tier" => ["string"], "echo" => ["number"]}) } }
+  let(:event) { Lago::Event.new("code", 1234, {"plan" => "pro", "calls" => 12}) }
+
+  describe '#register_wasm' do
+    it "makes the functions available to expressions" do
+      expression = Lago::ExpressionParser.parse_with_functions("tier(event.properties.plan)", registry)
+      expect(expression.evaluate(event)).to eq("gold")
+
+      expression = Lago::ExpressionParser.parse_with_functions("echo(event.properties.calls)[0] * 2", registry)
+      expect(expression.evaluate(event)).to eq(24.to_d)
+    end
+
+    it "checks the types of the arguments" do
+      expression = Lago::ExpressionParser.parse_with_functions("echo('a')", registry)
+      expect { expression.evaluate(event) }.to raise_error(RuntimeError, "Expected a decimal")
+    end
+
+    it "raises an error for invalid modules" do
+      expect { described_class.new.register_wasm("not wasm", {}) }.to raise_error(ArgumentError, /Invalid WebAssembly module/)
+    end
+
+    it "raises an error for missing exports" do
+      expect { described_class.new.register_wasm(wasm, {"missing" => []}) }.to raise_error(ArgumentError, "Missing WebAssembly export: missing")
+    end
+
+    it "raises an error for unknown argument types" do
+      expect { described_class.new.register_wasm(wasm, {"tier" => ["decimal"]}) }.to raise_error(ArgumentError, /Invalid argument type: decimal/)
+    end
+  end
+
+  describe 'Lago::ExpressionParser.validate_with_functions' do
+    it "only knows the functions of the registry" do
+      expect(Lago::ExpressionParser.validate_with_functions("tier(event.properties.plan)", registry)).to be_nil
+      expect(Lago::ExpressionParser.validate("tier(event.properties.plan)")).to eq("Unknown function: tier")
+    end
+  end
+end